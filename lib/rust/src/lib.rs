@@ -1,11 +1,13 @@
 use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 
 pub mod abi;
+pub mod revert;
 pub mod rpc;
 pub mod utils;
 
 pub use ethers_core as core;
 
+pub use revert::{decode_revert_reason, RevertReason};
 pub use rpc::{call_contract, get_provider, request};
 
 #[ic_cdk_macros::query(name = "__transform_ic_evm_rpc")]
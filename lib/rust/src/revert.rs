@@ -0,0 +1,225 @@
+use crate::utils::to_hex;
+use ethers_core::abi::{self, Contract, ParamType, Token};
+use ethers_core::types::I256;
+use std::fmt;
+
+/// Selector of `Error(string)`, used by `require` and `revert` with a message.
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Selector of `Panic(uint256)`, used by failing assertions and runtime checks.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// The reason for which a contract call reverted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RevertReason {
+    /// `Error(string)` with its message.
+    Error(String),
+    /// `Panic(uint256)` with its code and, for known codes, a description.
+    Panic {
+        code: ethers_core::types::U256,
+        description: Option<&'static str>,
+    },
+    /// A custom error found in the contract ABI.
+    Custom { name: String, args: Vec<Token> },
+    /// Revert data which could not be decoded, possibly empty.
+    Unknown(Vec<u8>),
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Error(message) => write!(f, "{}", message),
+            RevertReason::Panic { code, description } => {
+                if *code <= u64::MAX.into() {
+                    write!(f, "panic {:#04x}", code.as_u64())?;
+                } else {
+                    write!(f, "panic {}", code)?;
+                }
+                match description {
+                    Some(description) => write!(f, " ({})", description),
+                    None => Ok(()),
+                }
+            }
+            RevertReason::Custom { name, args } => write!(
+                f,
+                "{}({})",
+                name,
+                args.iter().map(format_token).collect::<Vec<_>>().join(", ")
+            ),
+            RevertReason::Unknown(data) if data.is_empty() => write!(f, "no revert data"),
+            RevertReason::Unknown(data) => write!(f, "unknown revert data {}", to_hex(data)),
+        }
+    }
+}
+
+/// Returns the description of a Solidity panic code.
+///
+/// See <https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require>.
+pub fn get_panic_description(code: ethers_core::types::U256) -> Option<&'static str> {
+    if code > 0xff.into() {
+        return None;
+    }
+    Some(match code.as_u32() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "incorrectly encoded storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized internal function",
+        _ => return None,
+    })
+}
+
+/// Decodes the data returned by a reverted call.
+///
+/// Custom errors are only recognized when the contract ABI is given.
+pub fn decode_revert_reason(data: &[u8], abi: Option<&Contract>) -> RevertReason {
+    if data.len() < 4 {
+        return RevertReason::Unknown(data.to_vec());
+    }
+    let (selector, args) = data.split_at(4);
+    if selector == ERROR_SELECTOR {
+        if let Ok(tokens) = abi::decode(&[ParamType::String], args) {
+            if let [Token::String(message)] = &tokens[..] {
+                return RevertReason::Error(message.clone());
+            }
+        }
+    } else if selector == PANIC_SELECTOR {
+        if let Ok(tokens) = abi::decode(&[ParamType::Uint(256)], args) {
+            if let [Token::Uint(code)] = tokens[..] {
+                return RevertReason::Panic {
+                    code,
+                    description: get_panic_description(code),
+                };
+            }
+        }
+    } else if let Some(abi) = abi {
+        for error in abi.errors() {
+            if error.signature()[..4] != *selector {
+                continue;
+            }
+            if let Ok(args) = error.decode(args) {
+                return RevertReason::Custom {
+                    name: error.name.clone(),
+                    args,
+                };
+            }
+        }
+    }
+    RevertReason::Unknown(data.to_vec())
+}
+
+/// Formats an ABI value, with integers in decimal and bytes and addresses in hex.
+pub fn format_token(token: &Token) -> String {
+    let join = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .map(format_token)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match token {
+        Token::Address(address) => to_hex(address.as_bytes()),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => to_hex(bytes),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Uint(value) => value.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::FixedArray(tokens) | Token::Array(tokens) => format!("[{}]", join(tokens)),
+        Token::Tuple(tokens) => format!("({})", join(tokens)),
+    }
+}
+
+#[test]
+fn test_decode_revert_reason() {
+    use crate::utils::from_hex;
+
+    // require(false, "Not enough Ether provided.")
+    let error = from_hex("0x08c379a00000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000001a4e6f7420656e6f7567682045746865722070726f76696465642e000000000000").unwrap();
+    assert_eq!(
+        decode_revert_reason(&error, None),
+        RevertReason::Error("Not enough Ether provided.".to_string())
+    );
+
+    // Arithmetic overflow
+    let panic =
+        from_hex("0x4e487b710000000000000000000000000000000000000000000000000000000000000011")
+            .unwrap();
+    let reason = decode_revert_reason(&panic, None);
+    assert_eq!(
+        reason,
+        RevertReason::Panic {
+            code: 0x11.into(),
+            description: Some("arithmetic overflow or underflow"),
+        }
+    );
+    assert_eq!(
+        reason.to_string(),
+        "panic 0x11 (arithmetic overflow or underflow)"
+    );
+    let unknown_panic =
+        from_hex("0x4e487b7100000000000000000000000000000000000000000000000000000000000000ff")
+            .unwrap();
+    assert_eq!(
+        decode_revert_reason(&unknown_panic, None),
+        RevertReason::Panic {
+            code: 0xff.into(),
+            description: None,
+        }
+    );
+
+    // error InsufficientBalance(uint256 available, uint256 required)
+    let abi: Contract = serde_json::from_str(
+        r#"[{"type":"error","name":"InsufficientBalance","inputs":[{"name":"available","type":"uint256"},{"name":"required","type":"uint256"}]}]"#,
+    )
+    .unwrap();
+    let custom = abi
+        .error("InsufficientBalance")
+        .unwrap()
+        .encode(&[Token::Uint(1.into()), Token::Uint(2.into())])
+        .unwrap();
+    let reason = decode_revert_reason(&custom, Some(&abi));
+    assert_eq!(
+        reason,
+        RevertReason::Custom {
+            name: "InsufficientBalance".to_string(),
+            args: vec![Token::Uint(1.into()), Token::Uint(2.into())],
+        }
+    );
+    assert_eq!(reason.to_string(), "InsufficientBalance(1, 2)");
+    assert_eq!(
+        decode_revert_reason(&custom, None),
+        RevertReason::Unknown(custom.clone())
+    );
+
+    // Malformed or missing data
+    assert_eq!(
+        decode_revert_reason(&[], None),
+        RevertReason::Unknown(vec![])
+    );
+    assert_eq!(
+        decode_revert_reason(&error[..4], None),
+        RevertReason::Unknown(error[..4].to_vec())
+    );
+    assert_eq!(
+        decode_revert_reason(&panic[..20], None),
+        RevertReason::Unknown(panic[..20].to_vec())
+    );
+}
+
+#[test]
+fn test_format_token() {
+    assert_eq!(format_token(&Token::Int(I256::from(-1).into_raw())), "-1");
+    assert_eq!(
+        format_token(&Token::Tuple(vec![
+            Token::Bool(true),
+            Token::String("a".to_string()),
+            Token::Array(vec![Token::Bytes(vec![0xab])]),
+        ])),
+        r#"(true, "a", [0xab])"#
+    );
+}
@@ -1,3 +1,4 @@
+use crate::revert::decode_revert_reason;
 use crate::utils::{from_hex, to_hex};
 use async_trait::async_trait;
 use ethers_core::abi::{Contract, FunctionExt, Token};
@@ -44,6 +45,23 @@ struct JsonRpcResponse {
 struct JsonRpcError {
     code: isize,
     message: String,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+}
+
+impl JsonRpcError {
+    /// Returns the revert data of a failed call, which providers include as a hex string.
+    fn revert_data(&self) -> Option<Vec<u8>> {
+        from_hex(self.data.as_ref()?.as_str()?)
+    }
+
+    fn into_provider_error(self, abi: Option<&Contract>) -> ProviderError {
+        let message = match self.revert_data() {
+            Some(data) => format!("{}: {}", self.message, decode_revert_reason(&data, abi)),
+            None => self.message,
+        };
+        ProviderError::CustomError(format!("JSON-RPC error code {}: {}", self.code, message))
+    }
 }
 
 fn next_id() -> u64 {
@@ -110,6 +128,17 @@ pub async fn request<'a, T: Serialize>(
     request: &JsonRpcRequest<'a, T>,
     cycles: u128,
     max_response_bytes: Option<u64>,
+) -> Result<Vec<u8>, ProviderError> {
+    send_request(url, request, cycles, max_response_bytes, None).await
+}
+
+/// Sends a JSON-RPC request, decoding revert reasons with the given contract ABI.
+async fn send_request<'a, T: Serialize>(
+    url: &str,
+    request: &JsonRpcRequest<'a, T>,
+    cycles: u128,
+    max_response_bytes: Option<u64>,
+    abi: Option<&Contract>,
 ) -> Result<Vec<u8>, ProviderError> {
     let json_rpc_payload =
         serde_json::to_string(request).expect("Error while encoding JSON-RPC request");
@@ -150,10 +179,7 @@ pub async fn request<'a, T: Serialize>(
         serde_json::from_str(std::str::from_utf8(&response.body).expect("utf8"))
             .expect("JSON was not well-formatted");
     if let Some(err) = json.error {
-        return Err(ProviderError::CustomError(format!(
-            "JSON-RPC error code {}: {}",
-            err.code, err.message
-        )));
+        return Err(err.into_provider_error(abi));
     }
     from_hex(
         &json
@@ -173,6 +199,7 @@ pub async fn request<'a, T: Serialize>(
 /// * The `args` parameter is an array of `Token` values representing the function's input arguments.
 ///
 /// The function returns a `Vec<Token>` representing the output values returned by the called function.
+/// If the call reverts, the error includes the revert reason, decoding custom errors with `abi`.
 ///
 /// ### Example
 ///
@@ -228,7 +255,14 @@ pub async fn call_contract(
             "latest",
         ),
     );
-    let result = request(service_url, &json_request, cycles, max_response_bytes).await?;
+    let result = send_request(
+        service_url,
+        &json_request,
+        cycles,
+        max_response_bytes,
+        Some(abi),
+    )
+    .await?;
     f.decode_output(&result)
         .map_err(|e| ProviderError::CustomError(format!("{}", e)))
 }
@@ -1,47 +1,177 @@
-use cketh_common::eth_rpc_client::providers::RpcApi;
-
 use crate::*;
 
 pub fn get_request_cost(
+    caller: &Principal,
     source: &ResolvedSource,
     json_rpc_payload: &str,
     max_response_bytes: u64,
 ) -> u128 {
-    let (http_cost, provider_cost) =
-        get_request_costs(source, json_rpc_payload, max_response_bytes);
-    http_cost + provider_cost
+    let (http_cost, provider_cost, service_fee) =
+        get_request_costs(caller, source, json_rpc_payload, max_response_bytes);
+    http_cost + provider_cost + service_fee
 }
 
+/// Returns the HTTPS outcall cost, provider cost and service fee of a request.
 pub fn get_request_costs(
+    caller: &Principal,
     source: &ResolvedSource,
     json_rpc_payload: &str,
     max_response_bytes: u64,
-) -> (u128, u128) {
-    match source {
+) -> (u128, u128, u128) {
+    let (http_cost, provider_cost) = match source {
         ResolvedSource::Api(api) => (
-            get_http_request_cost(api, json_rpc_payload, max_response_bytes),
+            get_http_request_cost(api.url.len(), json_rpc_payload, max_response_bytes),
             0,
         ),
         ResolvedSource::Provider(p) => (
-            get_http_request_cost(&p.api(), json_rpc_payload, max_response_bytes),
+            get_http_request_cost(
+                get_provider_url_len(p),
+                json_rpc_payload,
+                max_response_bytes,
+            ),
             get_provider_cost(p, json_rpc_payload),
         ),
+    };
+    let service_fee = get_service_fee(caller, http_cost + provider_cost);
+    (http_cost, provider_cost, service_fee)
+}
+
+/// Returns the service fee applying to the caller: the fee for the most privileged `Auth` the
+/// caller holds, otherwise the default.
+pub fn get_service_fee_config(caller: &Principal) -> Option<ServiceFee> {
+    let service_fees = METADATA.with(|m| m.borrow().get().service_fees.clone().unwrap_or_default());
+    find_auth_tier(caller, &service_fees, |f| f.auth).map(|f| f.fee.clone())
+}
+
+/// Calculate the service fee charged to the caller for a request with the given cost.
+pub fn get_service_fee(caller: &Principal, cost: u128) -> u128 {
+    get_service_fee_config(caller).map_or(0, |fee| {
+        fee.flat_fee + cost * fee.fee_bps as u128 / MAX_SERVICE_FEE_BPS as u128
+    })
+}
+
+pub fn do_set_service_fee(auth: Option<Auth>, fee: Option<ServiceFee>) {
+    if let Some(fee) = &fee {
+        if fee.fee_bps > MAX_SERVICE_FEE_BPS {
+            ic_cdk::trap("Service fee must not exceed 100%");
+        }
     }
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        let mut service_fees = metadata.service_fees.unwrap_or_default();
+        service_fees.retain(|f| f.auth != auth);
+        if let Some(fee) = fee {
+            service_fees.push(AuthServiceFee { auth, fee });
+        }
+        metadata.service_fees = Some(service_fees);
+        m.borrow_mut().set(metadata).unwrap();
+    });
 }
 
 /// Calculate the baseline cost of sending a JSON-RPC request using HTTP outcalls.
 pub fn get_http_request_cost(
-    api: &RpcApi,
+    url_len: usize,
     json_rpc_payload: &str,
     max_response_bytes: u64,
 ) -> u128 {
-    let nodes_in_subnet = METADATA.with(|m| m.borrow().get().nodes_in_subnet);
-    let ingress_bytes = (json_rpc_payload.len() + api.url.len()) as u128 + INGRESS_OVERHEAD_BYTES;
-    let base_cost = INGRESS_MESSAGE_RECEIVED_COST
-        + INGRESS_MESSAGE_BYTE_RECEIVED_COST * ingress_bytes
-        + HTTP_OUTCALL_REQUEST_COST
-        + HTTP_OUTCALL_BYTE_RECEIEVED_COST * (ingress_bytes + max_response_bytes as u128);
-    base_cost * (nodes_in_subnet as u128) / DEFAULT_NODES_IN_SUBNET as u128
+    let (nodes_in_subnet, pricing) = METADATA.with(|m| {
+        let metadata = m.borrow().get().clone();
+        (
+            metadata.nodes_in_subnet,
+            metadata.pricing.unwrap_or_default(),
+        )
+    });
+    let ingress_bytes = (json_rpc_payload.len() + url_len) as u128 + INGRESS_OVERHEAD_BYTES;
+    get_ingress_cost(&pricing, nodes_in_subnet, ingress_bytes)
+        + get_http_outcall_cost(
+            &pricing,
+            nodes_in_subnet,
+            ingress_bytes,
+            max_response_bytes as u128,
+        )
+}
+
+/// Calculate the cost of receiving an ingress message with the given size.
+pub fn get_ingress_cost(pricing: &PricingConfig, nodes_in_subnet: u32, bytes: u128) -> u128 {
+    (pricing.ingress_message_received_cost + pricing.ingress_message_byte_received_cost * bytes)
+        * nodes_in_subnet as u128
+        / DEFAULT_NODES_IN_SUBNET as u128
+}
+
+/// Calculate the cost of an HTTPS outcall, following the formula used by the replica.
+pub fn get_http_outcall_cost(
+    pricing: &PricingConfig,
+    nodes_in_subnet: u32,
+    request_bytes: u128,
+    max_response_bytes: u128,
+) -> u128 {
+    let n = nodes_in_subnet as u128;
+    (pricing.http_outcall_request_base_cost + pricing.http_outcall_request_per_node_cost * n) * n
+        + pricing.http_outcall_request_byte_cost * n * request_bytes
+        + pricing.http_outcall_response_byte_cost * n * max_response_bytes
+}
+
+/// Checks that the pricing charges something and that costs cannot overflow for any subnet size.
+pub fn validate_pricing(pricing: &PricingConfig) -> Result<(), String> {
+    let zero = PricingConfig {
+        ingress_message_received_cost: 0,
+        ingress_message_byte_received_cost: 0,
+        http_outcall_request_base_cost: 0,
+        http_outcall_request_per_node_cost: 0,
+        http_outcall_request_byte_cost: 0,
+        http_outcall_response_byte_cost: 0,
+    };
+    if *pricing == zero {
+        return Err("Pricing must not be zero".to_string());
+    }
+    let n = u32::MAX as u128;
+    let bytes = MAX_PRICED_MESSAGE_BYTES;
+    let max_cost = || {
+        let ingress = pricing
+            .ingress_message_byte_received_cost
+            .checked_mul(bytes)?
+            .checked_add(pricing.ingress_message_received_cost)?
+            .checked_mul(n)?;
+        let outcall = pricing
+            .http_outcall_request_per_node_cost
+            .checked_mul(n)?
+            .checked_add(pricing.http_outcall_request_base_cost)?
+            .checked_mul(n)?
+            .checked_add(
+                pricing
+                    .http_outcall_request_byte_cost
+                    .checked_add(pricing.http_outcall_response_byte_cost)?
+                    .checked_mul(n)?
+                    .checked_mul(bytes)?,
+            )?;
+        // Service fees are calculated in basis points of the cost.
+        ingress
+            .checked_add(outcall)?
+            .checked_mul(MAX_SERVICE_FEE_BPS as u128)
+    };
+    if max_cost().is_none() {
+        return Err("Pricing would overflow request costs".to_string());
+    }
+    Ok(())
+}
+
+pub fn do_set_pricing(pricing: PricingConfig) {
+    validate_pricing(&pricing).unwrap_or_else(|err| ic_cdk::trap(&err));
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        metadata.pricing = Some(pricing);
+        m.borrow_mut().set(metadata).unwrap();
+    });
+}
+
+pub fn get_pricing() -> PricingConfig {
+    METADATA.with(|m| m.borrow().get().pricing.clone().unwrap_or_default())
+}
+
+/// Returns the cycles consumed by an HTTP outcall from the cycles attached to it and those
+/// refunded by the management canister.
+pub fn get_http_outcall_cycles_consumed(attached: u128, refunded: u128) -> u128 {
+    attached.saturating_sub(refunded)
 }
 
 /// Calculate the additional cost for calling a registered JSON-RPC provider.
@@ -54,6 +184,8 @@ pub fn get_provider_cost(provider: &Provider, json_rpc_payload: &str) -> u128 {
 
 #[test]
 fn test_request_cost() {
+    use cketh_common::eth_rpc_client::providers::RpcApi;
+
     METADATA.with(|m| {
         let mut metadata = m.borrow().get().clone();
         metadata.nodes_in_subnet = 13;
@@ -63,6 +195,7 @@ fn test_request_cost() {
     let url = "https://cloudflare-eth.com";
     let payload = "{\"jsonrpc\":\"2.0\",\"method\":\"eth_gasPrice\",\"params\":[],\"id\":1}";
     let base_cost = get_request_cost(
+        &Principal::anonymous(),
         &ResolvedSource::Api(RpcApi {
             url: url.to_string(),
             headers: vec![],
@@ -72,6 +205,7 @@ fn test_request_cost() {
     );
     let s10 = "0123456789";
     let base_cost_s10 = get_request_cost(
+        &Principal::anonymous(),
         &ResolvedSource::Api(RpcApi {
             url: url.to_string(),
             headers: vec![],
@@ -80,11 +214,132 @@ fn test_request_cost() {
         1000,
     );
     assert_eq!(
-        base_cost + 10 * (INGRESS_MESSAGE_BYTE_RECEIVED_COST + HTTP_OUTCALL_BYTE_RECEIEVED_COST),
+        base_cost + 10 * (INGRESS_MESSAGE_BYTE_RECEIVED_COST + HTTP_OUTCALL_REQUEST_BYTE_COST * 13),
         base_cost_s10
     )
 }
 
+#[test]
+fn test_service_fee() {
+    let caller = Principal::from_slice(&[1]);
+    let rpc_caller = Principal::from_slice(&[2]);
+    do_authorize(rpc_caller, Auth::Rpc);
+
+    assert_eq!(get_service_fee(&caller, 1_000_000), 0);
+    do_set_service_fee(
+        None,
+        Some(ServiceFee {
+            fee_bps: 250,
+            flat_fee: 0,
+        }),
+    );
+    do_set_service_fee(
+        Some(Auth::Rpc),
+        Some(ServiceFee {
+            fee_bps: 100,
+            flat_fee: 1000,
+        }),
+    );
+    assert_eq!(get_service_fee(&caller, 1_000_000), 25_000);
+    assert_eq!(get_service_fee(&rpc_caller, 1_000_000), 11_000);
+    assert_eq!(get_service_fee(&rpc_caller, 0), 1000);
+
+    let source = ResolvedSource::Api(cketh_common::eth_rpc_client::providers::RpcApi {
+        url: "https://cloudflare-eth.com".to_string(),
+        headers: vec![],
+    });
+    let (http_cost, provider_cost, service_fee) = get_request_costs(&caller, &source, "{}", 1000);
+    assert_eq!(provider_cost, 0);
+    assert_eq!(service_fee, http_cost * 250 / 10_000);
+    assert_eq!(
+        get_request_cost(&caller, &source, "{}", 1000),
+        http_cost + service_fee
+    );
+
+    do_set_service_fee(None, None);
+    assert_eq!(get_service_fee(&caller, 1_000_000), 0);
+    assert_eq!(get_service_fee(&rpc_caller, 1_000_000), 11_000);
+}
+
+#[test]
+fn test_http_outcall_cost() {
+    let pricing = PricingConfig::default();
+    // Fees published for each subnet size at
+    // https://internetcomputer.org/docs/current/developer-docs/gas-cost
+    // (nodes, per call, per request byte, per response byte, ingress message)
+    let table: &[(u32, u128, u128, u128, u128)] = &[
+        (13, 49_140_000, 5_200, 10_400, 1_200_000),
+        (34, 171_360_000, 13_600, 27_200, 3_138_461),
+    ];
+    for (nodes, per_call, per_request_byte, per_response_byte, ingress) in table {
+        let cost = |request_bytes, response_bytes| {
+            get_http_outcall_cost(&pricing, *nodes, request_bytes, response_bytes)
+        };
+        assert_eq!(cost(0, 0), *per_call, "nodes: {nodes}");
+        assert_eq!(cost(1, 0) - cost(0, 0), *per_request_byte, "nodes: {nodes}");
+        assert_eq!(
+            cost(0, 1) - cost(0, 0),
+            *per_response_byte,
+            "nodes: {nodes}"
+        );
+        assert_eq!(get_ingress_cost(&pricing, *nodes, 0), *ingress);
+    }
+    // A 1 KB request with a 2 MB response on a 13-node subnet, as charged by the replica
+    assert_eq!(
+        get_http_outcall_cost(&pricing, 13, 1_000, 2_000_000),
+        20_854_340_000
+    );
+    assert_eq!(get_ingress_cost(&pricing, 13, 100), 1_400_000);
+
+    let pricing = PricingConfig {
+        http_outcall_request_base_cost: 0,
+        http_outcall_request_per_node_cost: 1,
+        http_outcall_request_byte_cost: 0,
+        http_outcall_response_byte_cost: 0,
+        ..Default::default()
+    };
+    do_set_pricing(pricing.clone());
+    assert_eq!(get_pricing(), pricing);
+    assert_eq!(get_http_outcall_cost(&pricing, 13, 100, 100), 169);
+}
+
+#[test]
+fn test_validate_pricing() {
+    assert_eq!(validate_pricing(&PricingConfig::default()), Ok(()));
+    let zero = PricingConfig {
+        ingress_message_received_cost: 0,
+        ingress_message_byte_received_cost: 0,
+        http_outcall_request_base_cost: 0,
+        http_outcall_request_per_node_cost: 0,
+        http_outcall_request_byte_cost: 0,
+        http_outcall_response_byte_cost: 0,
+    };
+    assert!(validate_pricing(&zero).is_err());
+    assert_eq!(
+        validate_pricing(&PricingConfig {
+            http_outcall_request_base_cost: 1,
+            ..zero.clone()
+        }),
+        Ok(())
+    );
+    for pricing in [
+        PricingConfig {
+            http_outcall_response_byte_cost: u128::MAX,
+            ..Default::default()
+        },
+        PricingConfig {
+            http_outcall_request_per_node_cost: u64::MAX as u128,
+            ..Default::default()
+        },
+        PricingConfig {
+            ingress_message_received_cost: u128::MAX / 2,
+            ..Default::default()
+        },
+    ] {
+        assert!(validate_pricing(&pricing).is_err(), "{:?}", pricing);
+    }
+}
+
 #[test]
 fn test_provider_cost() {
     METADATA.with(|m| {
@@ -104,6 +359,7 @@ fn test_provider_cost() {
         cycles_per_call: 0,
         cycles_per_message_byte: 2,
         primary: false,
+        capabilities: None,
     };
     let base_cost = get_provider_cost(
         &provider,
@@ -121,6 +377,7 @@ fn test_provider_cost() {
         cycles_per_call: 1000,
         cycles_per_message_byte: 2,
         primary: false,
+        capabilities: None,
     };
     let s10 = "0123456789";
     let base_cost_s10 = get_provider_cost(
@@ -130,3 +387,12 @@ fn test_provider_cost() {
     );
     assert_eq!(base_cost + (10 * 2 + 1000) * 13, base_cost_s10)
 }
+
+#[test]
+fn test_http_outcall_cycles_consumed() {
+    assert_eq!(get_http_outcall_cycles_consumed(1000, 400), 600);
+    assert_eq!(get_http_outcall_cycles_consumed(1000, 0), 1000);
+    assert_eq!(get_http_outcall_cycles_consumed(1000, 1000), 0);
+    assert_eq!(get_http_outcall_cycles_consumed(1000, 5000), 0);
+    assert!(get_http_request_cost(30, "{}", 100) < get_http_request_cost(30, "{}", 1000));
+}
@@ -0,0 +1,78 @@
+use candid::Principal;
+
+use crate::*;
+
+/// Appends an administrative action to the audit log, dropping the oldest entry once the log
+/// holds `MAX_AUDIT_LOG_ENTRIES`.
+pub fn record_audit_entry(caller: Principal, action: AuditAction) {
+    AUDIT_LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        // Entries are removed from the front only, so ids are contiguous.
+        let first_id = log.iter().next().map_or(0, |(id, _)| id);
+        let id = first_id + log.len();
+        log.insert(
+            id,
+            AuditEntry {
+                id,
+                timestamp: get_time(),
+                caller,
+                action,
+            },
+        );
+        if log.len() > MAX_AUDIT_LOG_ENTRIES {
+            log.remove(&first_id);
+        }
+    });
+}
+
+/// Appends a provider change to the audit log if it was made by an admin or controller.
+///
+/// Provider owners may change their own providers at will, so recording their changes would let
+/// them evict administrative entries from the bounded log.
+pub fn record_provider_audit_entry(caller: Principal, action: AuditAction) {
+    if is_admin_or_controller(&caller) {
+        record_audit_entry(caller, action);
+    }
+}
+
+/// Returns up to `limit` audit log entries with ids of at least `start`.
+pub fn do_get_audit_log(start: u64, limit: u64) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|l| {
+        l.borrow()
+            .range(start..)
+            .take(limit.min(MAX_AUDIT_LOG_PAGE_SIZE) as usize)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+#[test]
+fn test_audit_log() {
+    let admin = Principal::from_slice(&[1]);
+    for i in 0..MAX_AUDIT_LOG_ENTRIES + 5 {
+        record_audit_entry(
+            admin,
+            AuditAction::SetNodesInSubnet {
+                nodes_in_subnet: i as u32,
+            },
+        );
+    }
+    assert_eq!(AUDIT_LOG.with(|l| l.borrow().len()), MAX_AUDIT_LOG_ENTRIES);
+    assert!(do_get_audit_log(0, 10).iter().map(|e| e.id).eq(5..15));
+    let last = do_get_audit_log(MAX_AUDIT_LOG_ENTRIES + 4, 10);
+    assert_eq!(
+        last,
+        vec![AuditEntry {
+            id: MAX_AUDIT_LOG_ENTRIES + 4,
+            timestamp: last[0].timestamp,
+            caller: admin,
+            action: AuditAction::SetNodesInSubnet {
+                nodes_in_subnet: MAX_AUDIT_LOG_ENTRIES as u32 + 4
+            },
+        }]
+    );
+    assert_eq!(
+        do_get_audit_log(0, 1000).len(),
+        MAX_AUDIT_LOG_PAGE_SIZE as usize
+    );
+}
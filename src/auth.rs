@@ -1,20 +1,94 @@
 use candid::Principal;
+use ic_canister_log::log;
+use ic_stable_structures::Storable;
+use std::ops::Bound;
 
 use crate::*;
 
+/// Returns `true` if the principal holds an active grant for `auth` in any scope.
 pub fn is_authorized(principal: &Principal, auth: Auth) -> bool {
+    is_authorized_at(principal, auth, get_time())
+}
+
+/// Returns `true` if the principal holds an active grant for `auth` covering the target.
+pub fn is_authorized_for(principal: &Principal, auth: Auth, target: &AuthTarget) -> bool {
     AUTH.with(|a| {
         if let Some(v) = a.borrow().get(&PrincipalStorable(*principal)) {
-            v.is_authorized(auth)
+            v.is_authorized_for(auth, target, get_time())
         } else {
             false
         }
     })
 }
 
+pub fn is_authorized_at(principal: &Principal, auth: Auth, now: u64) -> bool {
+    AUTH.with(|a| {
+        if let Some(v) = a.borrow().get(&PrincipalStorable(*principal)) {
+            v.is_authorized(auth, now)
+        } else {
+            false
+        }
+    })
+}
+
+/// Returns `true` if the principal's grant for `auth` covers the target and allows another
+/// request costing `cycles`.
+pub fn can_use_authorization(
+    principal: &Principal,
+    auth: Auth,
+    target: &AuthTarget,
+    cycles: u128,
+) -> bool {
+    AUTH.with(|a| {
+        a.borrow()
+            .get(&PrincipalStorable(*principal))
+            .and_then(|v| {
+                v.get(auth)
+                    .map(|g| g.allows_usage(get_time(), cycles) && g.allows_target(target))
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Records a request charged `cycles` against the principal's grant for `auth`.
+///
+/// This runs after the HTTP outcall, where trapping would drop the caller's refund, so usage
+/// which cannot be stored is logged instead.
+pub fn record_authorization_usage(principal: &Principal, auth: Auth, cycles: u128) {
+    let result = try_update_auth_set(*principal, |v| {
+        if let Some(grant) = v.get_mut(auth) {
+            grant.record_usage(cycles);
+        }
+    });
+    if let Err(err) = result {
+        log!(INFO, "Unable to record usage of {}: {}", principal, err);
+    }
+}
+
+/// Returns the setting of the most privileged `Auth` tier the principal holds (`FreeRpc`, then
+/// `Rpc`), otherwise the default setting for the `None` tier.
+pub fn find_auth_tier<T>(
+    principal: &Principal,
+    settings: &[T],
+    tier: impl Fn(&T) -> Option<Auth>,
+) -> Option<&T> {
+    if settings.is_empty() {
+        return None;
+    }
+    [Auth::FreeRpc, Auth::Rpc]
+        .into_iter()
+        .filter(|auth| is_authorized(principal, *auth))
+        .map(Some)
+        .chain([None])
+        .find_map(|auth| settings.iter().find(|s| tier(s) == auth))
+}
+
+pub fn is_admin_or_controller(principal: &Principal) -> bool {
+    is_authorized(principal, Auth::ManageService) || ic_cdk::api::is_controller(principal)
+}
+
 pub fn require_admin_or_controller() -> Result<(), String> {
-    let caller = ic_cdk::caller();
-    if is_authorized(&caller, Auth::ManageService) || ic_cdk::api::is_controller(&caller) {
+    if is_admin_or_controller(&ic_cdk::caller()) {
         Ok(())
     } else {
         Err("You are not authorized".to_string())
@@ -29,34 +103,150 @@ pub fn require_register_provider() -> Result<(), String> {
     }
 }
 
+pub fn require_manage_provider() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if is_authorized(&caller, Auth::RegisterProvider)
+        || is_authorized(&caller, Auth::ManageProvider)
+    {
+        Ok(())
+    } else {
+        Err("You are not authorized".to_string())
+    }
+}
+
+pub fn is_open_rpc_access() -> bool {
+    METADATA.with(|m| m.borrow().get().open_rpc_access)
+}
+
 pub fn is_rpc_allowed(caller: &Principal) -> bool {
-    METADATA.with(|m| m.borrow().get().open_rpc_access) || is_authorized(caller, Auth::Rpc)
+    is_open_rpc_access() || is_authorized(caller, Auth::Rpc)
 }
 
 pub fn do_authorize(principal: Principal, auth: Auth) {
-    AUTH.with(|a| {
-        let mut auth_map = a.borrow_mut();
-        let principal = PrincipalStorable(principal);
-        if let Some(mut v) = auth_map.get(&principal) {
-            v.authorize(auth);
-            auth_map.insert(principal, v);
-        } else {
-            auth_map.insert(principal, AuthSet::new(vec![auth]));
+    do_authorize_with_limits(principal, auth, AuthLimits::default())
+}
+
+pub fn do_authorize_with_limits(principal: Principal, auth: Auth, limits: AuthLimits) {
+    if let Some(scope) = &limits.scope {
+        if auth == Auth::ManageService {
+            ic_cdk::trap("ManageService cannot be scoped");
+        }
+        // Scoped hostnames may be allowed for some chains only.
+        for hostname in scope.hostnames.iter().flatten() {
+            if get_host_scope(hostname).is_none() {
+                ic_cdk::trap(&format!("Host not allowed: {}", hostname));
+            }
         }
+    }
+    let grant = AuthGrant::new(auth, limits);
+    update_auth_set(principal, |v| {
+        v.authorize(grant);
     });
 }
 
 pub fn do_deauthorize(principal: Principal, auth: Auth) {
+    update_auth_set(principal, |v| {
+        v.deauthorize(auth);
+    });
+}
+
+/// Lets the principal manage the given provider on behalf of its owner.
+pub fn do_authorize_provider_manager(principal: Principal, provider_id: u64) {
+    update_auth_set(principal, |v| match v.get_mut(Auth::ManageProvider) {
+        Some(grant) => {
+            // Grants without a provider restriction already cover every provider.
+            if let Some(provider_ids) = grant
+                .scope
+                .as_mut()
+                .and_then(|scope| scope.provider_ids.as_mut())
+            {
+                if !provider_ids.contains(&provider_id) {
+                    provider_ids.push(provider_id);
+                }
+            }
+        }
+        None => {
+            v.authorize(AuthGrant::new(
+                Auth::ManageProvider,
+                AuthLimits {
+                    scope: Some(AuthScope {
+                        provider_ids: Some(vec![provider_id]),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            ));
+        }
+    });
+}
+
+pub fn do_deauthorize_provider_manager(principal: Principal, provider_id: u64) {
+    update_auth_set(principal, |v| {
+        let provider_ids = v
+            .get_mut(Auth::ManageProvider)
+            .and_then(|grant| grant.scope.as_mut())
+            .and_then(|scope| scope.provider_ids.as_mut());
+        if let Some(provider_ids) = provider_ids {
+            provider_ids.retain(|id| *id != provider_id);
+            if provider_ids.is_empty() {
+                v.deauthorize(Auth::ManageProvider);
+            }
+        }
+    });
+}
+
+fn update_auth_set(principal: Principal, f: impl FnOnce(&mut AuthSet)) {
+    try_update_auth_set(principal, f).unwrap_or_else(|err| ic_cdk::trap(&err));
+}
+
+fn try_update_auth_set(principal: Principal, f: impl FnOnce(&mut AuthSet)) -> Result<(), String> {
     AUTH.with(|a| {
         let mut auth_map = a.borrow_mut();
         let principal = PrincipalStorable(principal);
-        if let Some(mut v) = auth_map.get(&principal) {
-            v.deauthorize(auth);
-            if v.is_empty() {
-                auth_map.remove(&principal);
-            } else {
-                auth_map.insert(principal, v);
+        let mut v = auth_map.get(&principal).unwrap_or_default();
+        f(&mut v);
+        if v.is_empty() {
+            auth_map.remove(&principal);
+        } else {
+            if v.to_bytes().len() > AUTH_SET_STORABLE_MAX_SIZE as usize {
+                return Err("Authorization exceeds storage limit".to_string());
             }
+            auth_map.insert(principal, v);
+        }
+        Ok(())
+    })
+}
+
+/// Lists principals in ascending order, optionally only those holding the given `Auth`.
+pub fn do_list_authorizations(
+    auth: Option<Auth>,
+    start_after: Option<Principal>,
+    limit: u64,
+) -> Vec<Authorization> {
+    let start = match start_after {
+        Some(principal) => Bound::Excluded(PrincipalStorable(principal)),
+        None => Bound::Unbounded,
+    };
+    AUTH.with(|a| {
+        a.borrow()
+            .range((start, Bound::Unbounded))
+            .filter(|(_, auths)| auth.map_or(true, |auth| auths.has_grant(auth)))
+            .take(limit.min(MAX_AUTHORIZATIONS_PAGE_SIZE) as usize)
+            .map(|(principal, auths)| Authorization {
+                principal: principal.0,
+                grants: auths.to_vec(),
+            })
+            .collect()
+    })
+}
+
+/// Rewrites `AuthSet` entries stored in the legacy format (a JSON list of `Auth` values).
+pub fn migrate_auth_sets() {
+    AUTH.with(|a| {
+        let entries: Vec<_> = a.borrow().iter().collect();
+        let mut auth_map = a.borrow_mut();
+        for (principal, auth_set) in entries {
+            auth_map.insert(principal, auth_set);
         }
     });
 }
@@ -95,3 +285,272 @@ fn test_authorization() {
     assert!(!is_authorized(&principal2, Auth::FreeRpc));
     assert!(!is_authorized(&principal2, Auth::RegisterProvider));
 }
+
+#[test]
+fn test_list_authorizations() {
+    let principals: Vec<_> = (1..=4).map(|i| Principal::from_slice(&[i])).collect();
+    do_authorize(principals[0], Auth::Rpc);
+    do_authorize(principals[1], Auth::Rpc);
+    do_authorize(principals[1], Auth::FreeRpc);
+    do_authorize(principals[2], Auth::ManageService);
+    do_authorize(principals[3], Auth::Rpc);
+
+    let list = |auth, start_after, limit| {
+        do_list_authorizations(auth, start_after, limit)
+            .into_iter()
+            .map(|a| a.principal)
+            .collect::<Vec<_>>()
+    };
+
+    // Filter semantics
+    assert_eq!(list(None, None, 10), principals);
+    assert_eq!(
+        list(Some(Auth::Rpc), None, 10),
+        vec![principals[0], principals[1], principals[3]]
+    );
+    assert_eq!(list(Some(Auth::FreeRpc), None, 10), vec![principals[1]]);
+    assert_eq!(list(Some(Auth::RegisterProvider), None, 10), vec![]);
+    assert_eq!(
+        do_list_authorizations(Some(Auth::FreeRpc), None, 10),
+        vec![Authorization {
+            principal: principals[1],
+            grants: vec![
+                AuthGrant::new(Auth::Rpc, AuthLimits::default()),
+                AuthGrant::new(Auth::FreeRpc, AuthLimits::default())
+            ],
+        }]
+    );
+
+    // Pagination
+    assert_eq!(
+        list(Some(Auth::Rpc), None, 2),
+        vec![principals[0], principals[1]]
+    );
+    assert_eq!(
+        list(Some(Auth::Rpc), Some(principals[1]), 2),
+        vec![principals[3]]
+    );
+    assert_eq!(list(None, Some(principals[3]), 2), vec![]);
+    assert_eq!(list(None, None, 0), vec![]);
+
+    // Revoked authorizations are no longer listed
+    do_deauthorize(principals[3], Auth::Rpc);
+    assert_eq!(list(None, Some(principals[2]), 10), vec![]);
+}
+
+#[test]
+fn test_authorization_limits() {
+    let principal = Principal::from_slice(&[1]);
+    let now = 1_700_000_000_000_000_000;
+    let day = 24 * 60 * 60 * 1_000_000_000;
+
+    do_authorize_with_limits(
+        principal,
+        Auth::Rpc,
+        AuthLimits {
+            expires_at: Some(now + 30 * day),
+            ..Default::default()
+        },
+    );
+    assert!(is_authorized_at(&principal, Auth::Rpc, now));
+    assert!(is_authorized_at(&principal, Auth::Rpc, now + 30 * day - 1));
+    assert!(!is_authorized_at(&principal, Auth::Rpc, now + 30 * day));
+
+    do_authorize_with_limits(
+        principal,
+        Auth::FreeRpc,
+        AuthLimits {
+            max_requests: Some(2),
+            max_cycles: Some(1000),
+            ..Default::default()
+        },
+    );
+    let target = AuthTarget::default();
+    assert!(can_use_authorization(
+        &principal,
+        Auth::FreeRpc,
+        &target,
+        600
+    ));
+    record_authorization_usage(&principal, Auth::FreeRpc, 600);
+    assert!(!can_use_authorization(
+        &principal,
+        Auth::FreeRpc,
+        &target,
+        600
+    ));
+    assert!(can_use_authorization(
+        &principal,
+        Auth::FreeRpc,
+        &target,
+        400
+    ));
+    record_authorization_usage(&principal, Auth::FreeRpc, 100);
+    // Request quota exhausted
+    assert!(!can_use_authorization(
+        &principal,
+        Auth::FreeRpc,
+        &target,
+        0
+    ));
+    assert!(!is_authorized(&principal, Auth::FreeRpc));
+
+    // Re-authorizing resets the grant
+    do_authorize(principal, Auth::FreeRpc);
+    assert!(can_use_authorization(
+        &principal,
+        Auth::FreeRpc,
+        &target,
+        u128::MAX
+    ));
+    assert!(!can_use_authorization(
+        &principal,
+        Auth::ManageService,
+        &target,
+        0
+    ));
+}
+
+#[test]
+fn test_auth_set_migration() {
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+
+    let legacy = AuthSet::from_bytes(Cow::Borrowed(br#"["Rpc","FreeRpc"]"#));
+    assert_eq!(
+        legacy,
+        AuthSet::new(vec![
+            AuthGrant::new(Auth::Rpc, AuthLimits::default()),
+            AuthGrant::new(Auth::FreeRpc, AuthLimits::default()),
+        ])
+    );
+    assert_eq!(
+        legacy.to_bytes().as_ref(),
+        br#"[{"auth":"Rpc","requests_used":0,"cycles_used":0},{"auth":"FreeRpc","requests_used":0,"cycles_used":0}]"#
+    );
+    assert_eq!(AuthSet::from_bytes(legacy.to_bytes()), legacy);
+}
+
+#[test]
+fn test_authorization_scopes() {
+    init_allowed_hosts();
+    let principal = Principal::from_slice(&[2]);
+    let mainnet = AuthTarget {
+        chain_id: Some(ETH_MAINNET_CHAIN_ID),
+        hostname: Some("cloudflare-eth.com".to_string()),
+        provider_id: Some(0),
+    };
+    let sepolia = AuthTarget {
+        chain_id: Some(ETH_SEPOLIA_CHAIN_ID),
+        hostname: Some("rpc.sepolia.org".to_string()),
+        provider_id: Some(3),
+    };
+    let custom = AuthTarget {
+        hostname: Some("cloudflare-eth.com".to_string()),
+        ..Default::default()
+    };
+
+    do_authorize_with_limits(
+        principal,
+        Auth::Rpc,
+        AuthLimits {
+            scope: Some(AuthScope {
+                chain_ids: Some(vec![ETH_MAINNET_CHAIN_ID]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    assert!(is_authorized(&principal, Auth::Rpc));
+    assert!(is_authorized_for(&principal, Auth::Rpc, &mainnet));
+    assert!(!is_authorized_for(&principal, Auth::Rpc, &sepolia));
+    // Custom URLs have no known chain
+    assert!(!is_authorized_for(&principal, Auth::Rpc, &custom));
+    assert!(can_use_authorization(&principal, Auth::Rpc, &mainnet, 0));
+    assert!(!can_use_authorization(&principal, Auth::Rpc, &sepolia, 0));
+
+    do_authorize_with_limits(
+        principal,
+        Auth::RegisterProvider,
+        AuthLimits {
+            scope: Some(AuthScope {
+                hostnames: Some(vec!["cloudflare-eth.com".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    assert!(is_authorized(&principal, Auth::RegisterProvider));
+    assert!(is_authorized_for(
+        &principal,
+        Auth::RegisterProvider,
+        &mainnet
+    ));
+    assert!(is_authorized_for(
+        &principal,
+        Auth::RegisterProvider,
+        &custom
+    ));
+    assert!(!is_authorized_for(
+        &principal,
+        Auth::RegisterProvider,
+        &sepolia
+    ));
+
+    // Unscoped grants cover every target
+    do_authorize(principal, Auth::Rpc);
+    assert!(is_authorized_for(&principal, Auth::Rpc, &sepolia));
+    assert!(is_authorized_for(&principal, Auth::Rpc, &custom));
+}
+
+#[test]
+fn test_provider_manager_authorization() {
+    let principal = Principal::from_slice(&[3]);
+    let target = |provider_id| AuthTarget {
+        chain_id: Some(ETH_MAINNET_CHAIN_ID),
+        hostname: Some("cloudflare-eth.com".to_string()),
+        provider_id: Some(provider_id),
+    };
+
+    do_authorize_provider_manager(principal, 5);
+    do_authorize_provider_manager(principal, 7);
+    do_authorize_provider_manager(principal, 7);
+    assert!(is_authorized(&principal, Auth::ManageProvider));
+    assert!(is_authorized_for(
+        &principal,
+        Auth::ManageProvider,
+        &target(5)
+    ));
+    assert!(is_authorized_for(
+        &principal,
+        Auth::ManageProvider,
+        &target(7)
+    ));
+    assert!(!is_authorized_for(
+        &principal,
+        Auth::ManageProvider,
+        &target(6)
+    ));
+    assert_eq!(
+        do_list_authorizations(Some(Auth::ManageProvider), None, 10)[0].grants[0].scope,
+        Some(AuthScope {
+            provider_ids: Some(vec![5, 7]),
+            ..Default::default()
+        })
+    );
+
+    do_deauthorize_provider_manager(principal, 5);
+    assert!(!is_authorized_for(
+        &principal,
+        Auth::ManageProvider,
+        &target(5)
+    ));
+    assert!(is_authorized_for(
+        &principal,
+        Auth::ManageProvider,
+        &target(7)
+    ));
+    do_deauthorize_provider_manager(principal, 7);
+    assert!(!is_authorized(&principal, Auth::ManageProvider));
+    assert!(do_list_authorizations(None, None, 10).is_empty());
+}
@@ -0,0 +1,180 @@
+use candid::Principal;
+
+use crate::*;
+
+pub fn get_balance(principal: &Principal) -> u128 {
+    BALANCES.with(|b| {
+        b.borrow()
+            .get(&PrincipalStorable(*principal))
+            .unwrap_or_default()
+            .0
+    })
+}
+
+fn set_balance(principal: &Principal, balance: u128) {
+    BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        let principal = PrincipalStorable(*principal);
+        if balance == 0 {
+            balances.remove(&principal);
+        } else {
+            balances.insert(principal, CyclesStorable(balance));
+        }
+    });
+}
+
+fn record_balance_entry(
+    principal: &Principal,
+    kind: BalanceEntryKind,
+    amount: u128,
+    balance: u128,
+) {
+    let entry = BalanceEntry {
+        timestamp: get_time(),
+        principal: *principal,
+        kind,
+        amount,
+        balance,
+    };
+    BALANCE_HISTORY.with(|h| {
+        h.borrow_mut()
+            .append(&entry)
+            .expect("Unable to append balance entry")
+    });
+}
+
+/// Adds cycles to the principal's balance, returning the new balance.
+pub fn credit_balance(principal: &Principal, amount: u128, kind: BalanceEntryKind) -> u128 {
+    let balance = get_balance(principal)
+        .checked_add(amount)
+        .unwrap_or_else(|| ic_cdk::trap("Balance overflow"));
+    set_balance(principal, balance);
+    record_balance_entry(principal, kind, amount, balance);
+    balance
+}
+
+/// Removes cycles from the principal's balance, returning the new balance.
+/// Returns the current balance as an error if it is insufficient.
+pub fn debit_balance(
+    principal: &Principal,
+    amount: u128,
+    kind: BalanceEntryKind,
+) -> Result<u128, u128> {
+    let balance = get_balance(principal);
+    if amount == 0 {
+        return Ok(balance);
+    }
+    let balance = balance.checked_sub(amount).ok_or(balance)?;
+    set_balance(principal, balance);
+    record_balance_entry(principal, kind, amount, balance);
+    Ok(balance)
+}
+
+pub fn get_treasury_balance() -> u128 {
+    TREASURY.with(|t| t.borrow().get().0)
+}
+
+/// Adds collected service fees to the treasury, returning the new balance.
+pub fn credit_treasury(amount: u128) -> u128 {
+    let balance = get_treasury_balance()
+        .checked_add(amount)
+        .unwrap_or_else(|| ic_cdk::trap("Treasury overflow"));
+    TREASURY.with(|t| t.borrow_mut().set(CyclesStorable(balance)).unwrap());
+    balance
+}
+
+/// Removes cycles from the treasury, returning the new balance.
+/// Returns the current balance as an error if it is insufficient.
+pub fn debit_treasury(amount: u128) -> Result<u128, u128> {
+    let balance = get_treasury_balance();
+    let balance = balance.checked_sub(amount).ok_or(balance)?;
+    TREASURY.with(|t| t.borrow_mut().set(CyclesStorable(balance)).unwrap());
+    Ok(balance)
+}
+
+pub fn do_deposit_cycles(caller: Principal, beneficiary: Principal, amount: u128) -> u128 {
+    if beneficiary == Principal::anonymous() {
+        ic_cdk::trap("Cannot deposit to the anonymous principal");
+    }
+    credit_balance(
+        &beneficiary,
+        amount,
+        BalanceEntryKind::Deposit { from: caller },
+    )
+}
+
+pub fn do_get_balance_history(start: u64, limit: u64) -> Vec<BalanceEntry> {
+    BALANCE_HISTORY.with(|h| {
+        let history = h.borrow();
+        (start..history.len())
+            .take(limit.min(MAX_BALANCE_HISTORY_PAGE_SIZE) as usize)
+            .filter_map(|index| history.get(index))
+            .collect()
+    })
+}
+
+#[test]
+fn test_balances() {
+    let principal = Principal::from_slice(&[1]);
+    let depositor = Principal::from_slice(&[2]);
+    let canister = Principal::from_slice(&[3]);
+
+    assert_eq!(get_balance(&principal), 0);
+    assert_eq!(do_deposit_cycles(depositor, principal, 1000), 1000);
+    assert_eq!(do_deposit_cycles(principal, principal, 500), 1500);
+    assert_eq!(
+        debit_balance(&principal, 2000, BalanceEntryKind::Request),
+        Err(1500)
+    );
+    assert_eq!(
+        debit_balance(&principal, 0, BalanceEntryKind::Request),
+        Ok(1500)
+    );
+    assert_eq!(
+        debit_balance(&principal, 600, BalanceEntryKind::Request),
+        Ok(900)
+    );
+    assert_eq!(
+        debit_balance(&principal, 900, BalanceEntryKind::Withdraw { to: canister }),
+        Ok(0)
+    );
+    assert_eq!(
+        credit_balance(
+            &principal,
+            900,
+            BalanceEntryKind::WithdrawRefund { to: canister }
+        ),
+        900
+    );
+    assert_eq!(get_balance(&principal), 900);
+    assert_eq!(get_balance(&depositor), 0);
+
+    // Failed and zero-cycle debits are not recorded
+    let history = do_get_balance_history(0, 10);
+    assert_eq!(
+        history
+            .iter()
+            .map(|e| (e.kind.clone(), e.amount, e.balance))
+            .collect::<Vec<_>>(),
+        vec![
+            (BalanceEntryKind::Deposit { from: depositor }, 1000, 1000),
+            (BalanceEntryKind::Deposit { from: principal }, 500, 1500),
+            (BalanceEntryKind::Request, 600, 900),
+            (BalanceEntryKind::Withdraw { to: canister }, 900, 0),
+            (BalanceEntryKind::WithdrawRefund { to: canister }, 900, 900),
+        ]
+    );
+    assert!(history.iter().all(|e| e.principal == principal));
+    assert_eq!(do_get_balance_history(3, 1).len(), 1);
+    assert!(do_get_balance_history(5, 10).is_empty());
+}
+
+#[test]
+fn test_treasury() {
+    assert_eq!(get_treasury_balance(), 0);
+    assert_eq!(credit_treasury(1000), 1000);
+    assert_eq!(credit_treasury(500), 1500);
+    assert_eq!(debit_treasury(2000), Err(1500));
+    assert_eq!(debit_treasury(1500), Ok(0));
+    assert_eq!(get_treasury_balance(), 0);
+}
@@ -1,10 +1,15 @@
 use std::str::FromStr;
 
 use async_trait::async_trait;
+use candid::{Decode, Encode};
 use cketh_common::{
+    address::Address,
     eth_rpc::{
-        into_nat, Block, FeeHistory, GetLogsParam, Hash, HttpOutcallError, JsonRpcReply, LogEntry,
-        ProviderError, RpcError, SendRawTransactionResult, ValidationError,
+        into_nat, Block, BlockSpec, FeeHistory, FeeHistoryParams, GetBlockByNumberParams,
+        GetLogsParam, Hash, HttpOutcallError, JsonRpcError as CkEthJsonRpcError, JsonRpcReply,
+        JsonRpcRequest, LogEntry, ProviderError as CkEthProviderError, ResponseSizeEstimate,
+        RpcError as CkEthRpcError, SendRawTransactionResult,
+        ValidationError as CkEthValidationError, HEADER_SIZE_LIMIT,
     },
     eth_rpc_client::{
         providers::{RpcApi, RpcNodeProvider},
@@ -13,10 +18,104 @@ use cketh_common::{
     },
     lifecycle::EthereumNetwork,
 };
-use serde::de::DeserializeOwned;
+use ic_cdk::api::call::RejectionCode;
+use ic_eth::core::abi::Contract;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::*;
 
+/// Prefix of the `HttpOutcallError::IcError` messages which carry an `RpcError` without a
+/// `cketh_common` equivalent (such as `ProviderError::RateLimited`) through `CkEthRpcClient`.
+const RPC_ERROR_PREFIX: &str = "evm_rpc error: ";
+
+fn into_cketh_error(err: RpcError) -> CkEthRpcError {
+    match err {
+        RpcError::ProviderError(ProviderError::NoPermission) => {
+            CkEthProviderError::NoPermission.into()
+        }
+        RpcError::ProviderError(ProviderError::TooFewCycles { expected, received }) => {
+            CkEthProviderError::TooFewCycles { expected, received }.into()
+        }
+        RpcError::ProviderError(ProviderError::ProviderNotFound) => {
+            CkEthProviderError::ProviderNotFound.into()
+        }
+        RpcError::HttpOutcallError(err) => err.into(),
+        // `CkEthRpcClient` interprets the messages of some JSON-RPC errors (e.g. of
+        // `eth_sendRawTransaction`), so they keep their type at the cost of their `data`.
+        RpcError::JsonRpcError(JsonRpcError { code, message, .. }) => {
+            CkEthRpcError::JsonRpcError(CkEthJsonRpcError { code, message })
+        }
+        RpcError::ValidationError(ValidationError::CredentialPathNotAllowed(s)) => {
+            CkEthValidationError::CredentialPathNotAllowed(s).into()
+        }
+        RpcError::ValidationError(ValidationError::HostNotAllowed(s)) => {
+            CkEthValidationError::HostNotAllowed(s).into()
+        }
+        RpcError::ValidationError(ValidationError::CredentialHeaderNotAllowed(s)) => {
+            CkEthValidationError::CredentialHeaderNotAllowed(s).into()
+        }
+        RpcError::ValidationError(ValidationError::UrlParseError(s)) => {
+            CkEthValidationError::UrlParseError(s).into()
+        }
+        RpcError::ValidationError(ValidationError::InvalidHex(s)) => {
+            CkEthValidationError::InvalidHex(s).into()
+        }
+        err => HttpOutcallError::IcError {
+            code: RejectionCode::Unknown,
+            message: format!(
+                "{}{}",
+                RPC_ERROR_PREFIX,
+                ic_eth::utils::to_hex(&Encode!(&err).expect("Unable to encode RPC error"))
+            ),
+        }
+        .into(),
+    }
+}
+
+/// Recovers the original error (such as `ProviderError::RateLimited`) from a transport error.
+fn recover_error(err: CkEthRpcError) -> RpcError {
+    if let CkEthRpcError::HttpOutcallError(HttpOutcallError::IcError { message, .. }) = &err {
+        let rpc_error = message
+            .strip_prefix(RPC_ERROR_PREFIX)
+            .and_then(ic_eth::utils::from_hex)
+            .and_then(|bytes| Decode!(&bytes, RpcError).ok());
+        if let Some(rpc_error) = rpc_error {
+            return rpc_error;
+        }
+    }
+    err.into()
+}
+
+/// Sends a JSON-RPC request to the provider, returning errors with their `data`.
+async fn do_json_rpc_request<T: DeserializeOwned>(
+    provider: RpcNodeProvider,
+    json: &str,
+    max_response_bytes: u64,
+) -> RpcResult<T> {
+    let response = do_http_request(
+        ic_cdk::caller(),
+        ResolvedSource::Api(CanisterTransport::resolve_api(provider)?),
+        json,
+        max_response_bytes,
+    )
+    .await?;
+    let status = get_http_response_status(response.status.clone());
+    // Checked first since some providers reply with an error status along with the error.
+    if let Some(err) = get_json_rpc_error(&response.body) {
+        return Err(RpcError::JsonRpcError(err));
+    }
+    check_http_response_status(&response)?;
+    let body = get_http_response_body(response)?;
+    let json: JsonRpcReply<T> = serde_json::from_str(&body).unwrap_or_else(|e| {
+        Err(HttpOutcallError::InvalidHttpJsonRpcResponse {
+            status,
+            body,
+            parsing_error: Some(format!("JSON response parse error: {e}")),
+        })
+    })?;
+    Result::<T, CkEthRpcError>::from(json.result).map_err(RpcError::from)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct CanisterTransport;
 
@@ -27,7 +126,7 @@ impl RpcTransport for CanisterTransport {
         METADATA.with(|m| m.borrow().get().nodes_in_subnet)
     }
 
-    fn resolve_api(provider: RpcNodeProvider) -> Result<RpcApi, ProviderError> {
+    fn resolve_api(provider: RpcNodeProvider) -> Result<RpcApi, CkEthProviderError> {
         // TODO: https://github.com/internet-computer-protocol/ic-eth-rpc/issues/73
         Ok(provider.api())
     }
@@ -36,51 +135,165 @@ impl RpcTransport for CanisterTransport {
         provider: RpcNodeProvider,
         json: &str,
         max_response_bytes: u64,
-    ) -> Result<T, RpcError> {
-        let response = do_http_request(
-            ic_cdk::caller(),
-            ResolvedSource::Api(Self::resolve_api(provider)?),
-            json,
-            max_response_bytes,
-        )
-        .await
-        .unwrap();
-        let status = get_http_response_status(response.status.clone());
-        let body = get_http_response_body(response)?;
-        let json: JsonRpcReply<T> = serde_json::from_str(&body).unwrap_or_else(|e| {
-            Err(HttpOutcallError::InvalidHttpJsonRpcResponse {
-                status,
-                body,
-                parsing_error: Some(format!("JSON response parse error: {e}")),
-            })
-        })?;
-        json.result.into()
+    ) -> Result<T, CkEthRpcError> {
+        do_json_rpc_request(provider, json, max_response_bytes)
+            .await
+            .map_err(into_cketh_error)
     }
 }
 
-fn get_rpc_client(source: CandidRpcSource) -> RpcResult<CkEthRpcClient<CanisterTransport>> {
-    if !is_rpc_allowed(&ic_cdk::caller()) {
-        return Err(ProviderError::NoPermission.into());
-    }
-    Ok(match source {
-        CandidRpcSource::EthMainnet(service) => CkEthRpcClient::new(
+#[derive(Deserialize)]
+struct JsonRpcErrorReply {
+    error: JsonRpcErrorObject,
+}
+
+#[derive(Deserialize)]
+struct JsonRpcErrorObject {
+    code: i64,
+    message: String,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+}
+
+/// Parses the error of a JSON-RPC reply, which `cketh_common` returns without its `data`.
+fn get_json_rpc_error(body: &[u8]) -> Option<JsonRpcError> {
+    let JsonRpcErrorReply { error } = serde_json::from_slice(body).ok()?;
+    let data = error.data.map(|data| match data {
+        serde_json::Value::String(data) => data,
+        data => data.to_string(),
+    });
+    Some(JsonRpcError {
+        code: error.code,
+        message: error.message,
+        revert: get_revert_reason(data.as_deref(), None),
+        data,
+    })
+}
+
+/// Decodes the revert reason from hex-encoded error data.
+fn get_revert_reason(data: Option<&str>, abi: Option<&Contract>) -> Option<RevertReason> {
+    let data = ic_eth::utils::from_hex(data?)?;
+    Some(ic_eth::decode_revert_reason(&data, abi).into())
+}
+
+/// Parses the optional JSON ABI of an `eth_call` contract.
+fn get_contract_abi(abi: Option<&str>) -> Result<Option<Contract>, ValidationError> {
+    abi.map(serde_json::from_str::<Contract>)
+        .transpose()
+        .map_err(|e| ValidationError::InvalidAbi(e.to_string()))
+}
+
+#[derive(Serialize)]
+struct EthCallObject {
+    to: String,
+    data: String,
+}
+
+fn get_eth_call_params(
+    args: &candid_types::CallArgs,
+) -> Result<(EthCallObject, BlockSpec), ValidationError> {
+    Address::from_str(&args.to).map_err(|_| ValidationError::InvalidHex(args.to.clone()))?;
+    ic_eth::utils::from_hex(&args.data)
+        .ok_or_else(|| ValidationError::InvalidHex(args.data.clone()))?;
+    let block = args
+        .block
+        .clone()
+        .unwrap_or(candid_types::BlockSpec::Tag(Default::default()));
+    Ok((
+        EthCallObject {
+            to: args.to.clone(),
+            data: args.data.clone(),
+        },
+        block.into(),
+    ))
+}
+
+fn get_rpc_providers(source: &CandidRpcSource) -> (EthereumNetwork, Vec<RpcNodeProvider>) {
+    match source {
+        CandidRpcSource::EthMainnet(service) => (
             EthereumNetwork::Ethereum,
-            Some(vec![service.unwrap_or(DEFAULT_ETHEREUM_PROVIDER)])
-                .map(|p| p.into_iter().map(RpcNodeProvider::Ethereum).collect()),
+            vec![RpcNodeProvider::Ethereum(
+                service.unwrap_or(DEFAULT_ETHEREUM_PROVIDER),
+            )],
         ),
-        CandidRpcSource::EthSepolia(service) => CkEthRpcClient::new(
+        CandidRpcSource::EthSepolia(service) => (
             EthereumNetwork::Sepolia,
-            Some(vec![service.unwrap_or(DEFAULT_SEPOLIA_PROVIDER)])
-                .map(|p| p.into_iter().map(RpcNodeProvider::Sepolia).collect()),
+            vec![RpcNodeProvider::Sepolia(
+                service.unwrap_or(DEFAULT_SEPOLIA_PROVIDER),
+            )],
         ),
+    }
+}
+
+fn get_rpc_client(source: CandidRpcSource) -> RpcResult<CkEthRpcClient<CanisterTransport>> {
+    if !is_rpc_allowed(&ic_cdk::caller()) {
+        return Err(ProviderError::NoPermission.into());
+    }
+    let (network, providers) = get_rpc_providers(&source);
+    Ok(CkEthRpcClient::new(network, Some(providers)))
+}
+
+/// Serializes a JSON-RPC request with the request type of `CkEthRpcClient`.
+fn get_json_rpc_payload<T: Serialize>(method: &str, params: T) -> String {
+    serde_json::to_string(&JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: method.to_string(),
+        id: 1,
+        params,
     })
+    .expect("Unable to serialize JSON-RPC request")
+}
+
+/// Returns the `max_response_bytes` of each attempt to send a request.
+///
+/// `CkEthRpcClient` retries requests whose response exceeds the estimate with a larger estimate,
+/// up to the maximum response size. Requests with `retried` responses are priced for every
+/// attempt, since their response size depends on the chain state rather than the request.
+fn get_response_size_attempts(response_size_estimate: u64, retried: bool) -> Vec<u64> {
+    let mut estimate = ResponseSizeEstimate::new(response_size_estimate + HEADER_SIZE_LIMIT);
+    let mut attempts = vec![estimate.get()];
+    while retried {
+        let next = estimate.adjust();
+        if next == estimate {
+            break;
+        }
+        attempts.push(next.get());
+        estimate = next;
+    }
+    attempts
+}
+
+/// Calculates the total cost of sending a JSON-RPC request to every provider of the source.
+fn get_json_rpc_cost<T: Serialize>(
+    caller: &Principal,
+    source: &CandidRpcSource,
+    method: &str,
+    params: T,
+    response_size_estimate: u64,
+    retried: bool,
+) -> RpcResult<u128> {
+    let payload = get_json_rpc_payload(method, params);
+    let attempts = get_response_size_attempts(response_size_estimate, retried);
+    let (_, providers) = get_rpc_providers(source);
+    Ok(providers
+        .into_iter()
+        .map(|provider| {
+            let source = ResolvedSource::Api(CanisterTransport::resolve_api(provider)?);
+            Ok(attempts
+                .iter()
+                .map(|max_response_bytes| {
+                    get_request_cost(caller, &source, &payload, *max_response_bytes)
+                })
+                .sum::<u128>())
+        })
+        .sum::<Result<u128, CkEthProviderError>>()?)
 }
 
 fn wrap_result<T>(result: Result<T, MultiCallError<T>>) -> RpcResult<T> {
     match result {
         Ok(value) => Ok(value),
         Err(err) => match err {
-            MultiCallError::ConsistentError(err) => Err(err),
+            MultiCallError::ConsistentError(err) => Err(recover_error(err)),
             MultiCallError::InconsistentResults(_results) => {
                 unreachable!("BUG: receieved more than one RPC provider result")
             }
@@ -90,15 +303,130 @@ fn wrap_result<T>(result: Result<T, MultiCallError<T>>) -> RpcResult<T> {
 
 pub struct CandidRpcClient {
     client: CkEthRpcClient<CanisterTransport>,
+    providers: Vec<RpcNodeProvider>,
 }
 
 impl CandidRpcClient {
     pub fn from_source(source: CandidRpcSource) -> RpcResult<Self> {
+        let (_, providers) = get_rpc_providers(&source);
         Ok(Self {
             client: get_rpc_client(source)?,
+            providers,
         })
     }
 
+    pub fn eth_get_logs_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        args: candid_types::GetLogsArgs,
+    ) -> RpcResult<u128> {
+        let args: GetLogsParam = args.try_into()?;
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_getLogs",
+            vec![args],
+            ETH_GET_LOGS_RESPONSE_SIZE_ESTIMATE,
+            true,
+        )
+    }
+
+    pub fn eth_get_block_by_number_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        block: candid_types::BlockSpec,
+    ) -> RpcResult<u128> {
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_getBlockByNumber",
+            GetBlockByNumberParams {
+                block: block.into(),
+                include_full_transactions: false,
+            },
+            ETH_GET_BLOCK_BY_NUMBER_RESPONSE_SIZE_ESTIMATE,
+            true,
+        )
+    }
+
+    pub fn eth_get_transaction_receipt_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        hash: String,
+    ) -> RpcResult<u128> {
+        let hash = Hash::from_str(&hash).map_err(|_| ValidationError::InvalidHex(hash))?;
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_getTransactionReceipt",
+            vec![hash],
+            ETH_GET_TRANSACTION_RECEIPT_RESPONSE_SIZE_ESTIMATE,
+            false,
+        )
+    }
+
+    pub fn eth_get_transaction_count_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        args: candid_types::GetTransactionCountArgs,
+    ) -> RpcResult<u128> {
+        let args: GetTransactionCountParams = args.try_into()?;
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_getTransactionCount",
+            args,
+            ETH_GET_TRANSACTION_COUNT_RESPONSE_SIZE_ESTIMATE,
+            false,
+        )
+    }
+
+    pub fn eth_fee_history_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        args: candid_types::FeeHistoryArgs,
+    ) -> RpcResult<u128> {
+        let args: FeeHistoryParams = args.into();
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_feeHistory",
+            args,
+            ETH_FEE_HISTORY_RESPONSE_SIZE_ESTIMATE,
+            false,
+        )
+    }
+
+    pub fn eth_send_raw_transaction_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        raw_signed_transaction_hex: String,
+    ) -> RpcResult<u128> {
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_sendRawTransaction",
+            vec![raw_signed_transaction_hex],
+            ETH_SEND_RAW_TRANSACTION_RESPONSE_SIZE_ESTIMATE,
+            false,
+        )
+    }
+
+    pub fn eth_call_cost(
+        caller: &Principal,
+        source: &CandidRpcSource,
+        args: candid_types::CallArgs,
+    ) -> RpcResult<u128> {
+        get_json_rpc_cost(
+            caller,
+            source,
+            "eth_call",
+            get_eth_call_params(&args)?,
+            ETH_CALL_RESPONSE_SIZE_ESTIMATE,
+            false,
+        )
+    }
+
     pub async fn eth_get_logs(&self, args: candid_types::GetLogsArgs) -> RpcResult<Vec<LogEntry>> {
         let args: GetLogsParam = match args.try_into() {
             Ok(args) => args,
@@ -152,6 +480,32 @@ impl CandidRpcClient {
         wrap_result(self.client.eth_fee_history(args.into()).await).map(|history| history.into())
     }
 
+    /// Calls a contract, decoding the revert reason of a failed call with the optional ABI.
+    ///
+    /// Unlike the other methods, the call is not sent through `CkEthRpcClient`, which drops the
+    /// revert data of JSON-RPC errors. The providers are tried in order and the first reply is
+    /// returned without any consensus between providers: the next provider is only tried after
+    /// an HTTPS outcall error, whereas a JSON-RPC error (such as a revert) is final.
+    pub async fn eth_call(&self, args: candid_types::CallArgs) -> RpcResult<String> {
+        let abi = get_contract_abi(args.abi.as_deref())?;
+        let payload = get_json_rpc_payload("eth_call", get_eth_call_params(&args)?);
+        let max_response_bytes = ETH_CALL_RESPONSE_SIZE_ESTIMATE + HEADER_SIZE_LIMIT;
+        let mut result: RpcResult<String> = Err(ProviderError::ProviderNotFound.into());
+        for provider in self.providers.iter().cloned() {
+            result = do_json_rpc_request(provider, &payload, max_response_bytes).await;
+            if !matches!(result, Err(RpcError::HttpOutcallError(_))) {
+                break;
+            }
+        }
+        result.map_err(|err| match err {
+            RpcError::JsonRpcError(err) => RpcError::JsonRpcError(JsonRpcError {
+                revert: get_revert_reason(err.data.as_deref(), abi.as_ref()),
+                ..err
+            }),
+            err => err,
+        })
+    }
+
     pub async fn eth_send_raw_transaction(
         &self,
         raw_signed_transaction_hex: String,
@@ -159,5 +513,189 @@ impl CandidRpcClient {
         self.client
             .eth_send_raw_transaction(raw_signed_transaction_hex)
             .await
+            .map_err(recover_error)
     }
 }
+
+#[test]
+fn test_json_rpc_payload() {
+    assert_eq!(
+        get_json_rpc_payload("eth_sendRawTransaction", vec!["0x1234"]),
+        r#"{"jsonrpc":"2.0","method":"eth_sendRawTransaction","id":1,"params":["0x1234"]}"#
+    );
+}
+
+#[test]
+fn test_json_rpc_cost() {
+    let caller = Principal::anonymous();
+    let source = CandidRpcSource::EthMainnet(None);
+    let raw_transaction = "0xf86c0a8502540be400825208".to_string();
+    let payload = get_json_rpc_payload("eth_sendRawTransaction", vec![raw_transaction.clone()]);
+    let api = RpcNodeProvider::Ethereum(DEFAULT_ETHEREUM_PROVIDER).api();
+    assert_eq!(
+        CandidRpcClient::eth_send_raw_transaction_cost(&caller, &source, raw_transaction).unwrap(),
+        get_request_cost(
+            &caller,
+            &ResolvedSource::Api(api),
+            &payload,
+            ETH_SEND_RAW_TRANSACTION_RESPONSE_SIZE_ESTIMATE + HEADER_SIZE_LIMIT
+        )
+    );
+
+    // Responses which may exceed their estimate are priced for every retry
+    assert_eq!(
+        get_response_size_attempts(ETH_SEND_RAW_TRANSACTION_RESPONSE_SIZE_ESTIMATE, false),
+        vec![ETH_SEND_RAW_TRANSACTION_RESPONSE_SIZE_ESTIMATE + HEADER_SIZE_LIMIT]
+    );
+    let attempts = get_response_size_attempts(ETH_GET_LOGS_RESPONSE_SIZE_ESTIMATE, true);
+    assert_eq!(
+        attempts[0],
+        ETH_GET_LOGS_RESPONSE_SIZE_ESTIMATE + HEADER_SIZE_LIMIT
+    );
+    assert_eq!(attempts[1], 2 * attempts[0]);
+    assert!(attempts.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(
+        ResponseSizeEstimate::new(*attempts.last().unwrap()).adjust(),
+        ResponseSizeEstimate::new(*attempts.last().unwrap())
+    );
+
+    assert!(matches!(
+        CandidRpcClient::eth_get_transaction_receipt_cost(&caller, &source, "0x1".to_string()),
+        Err(RpcError::ValidationError(_))
+    ));
+}
+
+#[test]
+fn test_transport_error() {
+    let errors: Vec<RpcError> = vec![
+        ProviderError::RateLimited {
+            retry_after_ns: 1_000,
+        }
+        .into(),
+        ProviderError::CredentialsUnavailable.into(),
+        ProviderError::TooFewCycles {
+            expected: 2,
+            received: 1,
+        }
+        .into(),
+        HttpOutcallError::IcError {
+            code: RejectionCode::SysFatal,
+            message: "Http body exceeds size limit".to_string(),
+        }
+        .into(),
+        ValidationError::InvalidHex("0x".to_string()).into(),
+        ValidationError::InvalidAbi("[".to_string()).into(),
+    ];
+    for err in errors {
+        assert_eq!(
+            format!("{:?}", recover_error(into_cketh_error(err.clone()))),
+            format!("{:?}", err)
+        );
+    }
+}
+
+#[test]
+fn test_json_rpc_error() {
+    let revert = br#"{"jsonrpc":"2.0","id":1,"error":{"code":3,"message":"execution reverted","data":"0x08c379a0"}}"#;
+    assert_eq!(
+        get_json_rpc_error(revert),
+        Some(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some("0x08c379a0".to_string()),
+            revert: Some(RevertReason::Unknown("0x08c379a0".to_string())),
+        })
+    );
+    let panic = br#"{"jsonrpc":"2.0","id":1,"error":{"code":3,"message":"execution reverted","data":"0x4e487b710000000000000000000000000000000000000000000000000000000000000012"}}"#;
+    assert_eq!(
+        get_json_rpc_error(panic).and_then(|e| e.revert),
+        Some(RevertReason::Panic {
+            code: 0x12_u32.into(),
+            description: Some("division or modulo by zero".to_string()),
+        })
+    );
+    let object_data =
+        br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"error","data":{"a":1}}}"#;
+    assert_eq!(
+        get_json_rpc_error(object_data).and_then(|e| e.data),
+        Some(r#"{"a":1}"#.to_string())
+    );
+    let no_data = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"not found"}}"#;
+    assert_eq!(get_json_rpc_error(no_data).and_then(|e| e.data), None);
+    assert_eq!(get_json_rpc_error(object_data).and_then(|e| e.revert), None);
+    assert_eq!(
+        get_json_rpc_error(br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#),
+        None
+    );
+    assert_eq!(get_json_rpc_error(b"Bad Gateway"), None);
+}
+
+#[test]
+fn test_revert_reason() {
+    let abi: Contract = serde_json::from_str(
+        r#"[{"type":"error","name":"Unauthorized","inputs":[{"name":"caller","type":"address"}]}]"#,
+    )
+    .unwrap();
+    let data = "0x8e4a23d60000000000000000000000000000000000000000000000000000000000000001";
+    assert_eq!(
+        get_revert_reason(Some(data), Some(&abi)),
+        Some(RevertReason::Custom {
+            name: "Unauthorized".to_string(),
+            args: vec!["0x0000000000000000000000000000000000000001".to_string()],
+        })
+    );
+    assert_eq!(
+        get_revert_reason(Some(data), None),
+        Some(RevertReason::Unknown(data.to_string()))
+    );
+    assert_eq!(
+        get_revert_reason(Some("0x"), None),
+        Some(RevertReason::Unknown("0x".to_string()))
+    );
+    assert_eq!(get_revert_reason(Some("execution reverted"), None), None);
+    assert_eq!(get_revert_reason(None, None), None);
+}
+
+#[test]
+fn test_eth_call_params() {
+    let args = candid_types::CallArgs {
+        to: "0xdAC17F958D2ee523a2206206994597C13D831ec7".to_string(),
+        data: "0x18160ddd".to_string(),
+        block: None,
+        abi: None,
+    };
+    assert_eq!(
+        get_json_rpc_payload("eth_call", get_eth_call_params(&args).unwrap()),
+        r#"{"jsonrpc":"2.0","method":"eth_call","id":1,"params":[{"to":"0xdAC17F958D2ee523a2206206994597C13D831ec7","data":"0x18160ddd"},"latest"]}"#
+    );
+    for invalid in [
+        candid_types::CallArgs {
+            to: "0x1234".to_string(),
+            ..args.clone()
+        },
+        candid_types::CallArgs {
+            data: "18160ddd".to_string(),
+            ..args.clone()
+        },
+    ] {
+        assert!(matches!(
+            get_eth_call_params(&invalid),
+            Err(ValidationError::InvalidHex(_))
+        ));
+    }
+}
+
+#[test]
+fn test_contract_abi() {
+    assert!(get_contract_abi(None).unwrap().is_none());
+    let abi = get_contract_abi(Some(
+        r#"[{"type":"error","name":"InsufficientBalance","inputs":[{"name":"available","type":"uint256"}]}]"#,
+    ))
+    .unwrap()
+    .unwrap();
+    assert!(abi.error("InsufficientBalance").is_ok());
+    assert!(matches!(
+        get_contract_abi(Some("[")),
+        Err(ValidationError::InvalidAbi(_))
+    ));
+}
@@ -0,0 +1,302 @@
+use serde_json::Value;
+
+use crate::*;
+
+const SUBSCRIPTION_METHODS: &[&str] = &["eth_subscribe", "eth_unsubscribe"];
+
+// Methods reading account state at a given block, along with the index of the block parameter.
+const STATE_METHODS: &[(&str, usize)] = &[
+    ("eth_call", 1),
+    ("eth_estimateGas", 1),
+    ("eth_getBalance", 1),
+    ("eth_getCode", 1),
+    ("eth_getProof", 2),
+    ("eth_getStorageAt", 2),
+    ("eth_getTransactionCount", 1),
+];
+
+/// Capabilities a provider needs in order to serve a JSON-RPC payload.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RpcRequirements {
+    pub methods: Vec<String>,
+    pub archive: bool,
+    pub logs_block_range: Option<u64>,
+    pub batch_size: u32,
+}
+
+impl RpcRequirements {
+    pub fn from_payload(json_rpc_payload: &str) -> Self {
+        let mut requirements = Self::default();
+        match serde_json::from_str::<Value>(json_rpc_payload) {
+            Ok(Value::Array(requests)) => {
+                for request in &requests {
+                    requirements.add_request(request);
+                }
+                requirements.batch_size = requests.len() as u32;
+            }
+            Ok(request) => {
+                requirements.add_request(&request);
+                requirements.batch_size = 1;
+            }
+            // Malformed payloads are rejected by the provider itself.
+            Err(_) => {}
+        }
+        requirements
+    }
+
+    fn add_request(&mut self, request: &Value) {
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => return,
+        };
+        let params = request.get("params");
+        if let Some((_, index)) = STATE_METHODS.iter().find(|(m, _)| *m == method) {
+            if params
+                .and_then(|p| p.get(index))
+                .map_or(false, is_historical_block)
+            {
+                self.archive = true;
+            }
+        }
+        if method == "eth_getLogs" {
+            if let Some(range) = params.and_then(|p| p.get(0)).and_then(get_logs_block_range) {
+                self.logs_block_range = Some(self.logs_block_range.unwrap_or_default().max(range));
+            }
+        }
+        self.methods.push(method.to_string());
+    }
+}
+
+impl ProviderCapabilities {
+    pub fn supports_method(&self, method: &str) -> bool {
+        if SUBSCRIPTION_METHODS.contains(&method) {
+            return false;
+        }
+        match get_method_namespace(method) {
+            Some(namespace) => self.namespaces.contains(&namespace),
+            // Unknown namespaces are passed through to the provider.
+            None => true,
+        }
+    }
+
+    pub fn can_serve(&self, requirements: &RpcRequirements) -> bool {
+        requirements
+            .methods
+            .iter()
+            .all(|method| self.supports_method(method))
+            && (self.archive || !requirements.archive)
+            && match (self.max_logs_block_range, requirements.logs_block_range) {
+                (Some(max), Some(range)) => range <= max,
+                _ => true,
+            }
+            && match self.max_batch_size {
+                Some(max) => requirements.batch_size <= max,
+                None => true,
+            }
+    }
+}
+
+pub fn get_method_namespace(method: &str) -> Option<RpcNamespace> {
+    match method.split_once('_')?.0 {
+        "eth" | "net" | "web3" => Some(RpcNamespace::Eth),
+        "debug" => Some(RpcNamespace::Debug),
+        "trace" => Some(RpcNamespace::Trace),
+        _ => None,
+    }
+}
+
+fn parse_block_number(value: &Value) -> Option<u64> {
+    let hex = value.as_str()?.strip_prefix("0x")?;
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Returns `true` if a block parameter refers to state which is only kept by archive nodes.
+///
+/// The canister does not track the latest block of each chain, so block numbers are only known
+/// to be old when they refer to the genesis block. Other block numbers are assumed to be recent,
+/// leaving providers without archive data to reject requests for pruned state.
+fn is_historical_block(block: &Value) -> bool {
+    match block {
+        Value::String(tag) if tag == "earliest" => true,
+        Value::String(_) => parse_block_number(block) == Some(0),
+        // EIP-1898 block selectors: block hashes may refer to any block.
+        Value::Object(selector) => match selector.get("blockNumber") {
+            Some(number) => is_historical_block(number),
+            None => selector.contains_key("blockHash"),
+        },
+        _ => false,
+    }
+}
+
+fn get_logs_block_range(filter: &Value) -> Option<u64> {
+    let from_block = parse_block_number(filter.get("fromBlock")?)?;
+    let to_block = parse_block_number(filter.get("toBlock")?)?;
+    Some(to_block.saturating_sub(from_block) + 1)
+}
+
+#[test]
+fn test_rpc_requirements() {
+    let requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"eth_gasPrice","params":[],"id":1}"#,
+    );
+    assert_eq!(
+        requirements,
+        RpcRequirements {
+            methods: vec!["eth_gasPrice".to_string()],
+            archive: false,
+            logs_block_range: None,
+            batch_size: 1,
+        }
+    );
+
+    let requirements = RpcRequirements::from_payload(
+        r#"[
+            {"jsonrpc":"2.0","method":"eth_call","params":[{"to":"0x0"},"earliest"],"id":1},
+            {"jsonrpc":"2.0","method":"eth_getLogs","params":[{"fromBlock":"0x10","toBlock":"0x1f"}],"id":2}
+        ]"#,
+    );
+    assert_eq!(
+        requirements,
+        RpcRequirements {
+            methods: vec!["eth_call".to_string(), "eth_getLogs".to_string()],
+            archive: true,
+            logs_block_range: Some(16),
+            batch_size: 2,
+        }
+    );
+
+    for (block, archive) in [
+        (r#""latest""#, false),
+        (r#""0x10d4f""#, false),
+        (r#"{"blockNumber":"0x10d4f"}"#, false),
+        (r#""earliest""#, true),
+        (r#""0x0""#, true),
+        (r#"{"blockNumber":"0x0"}"#, true),
+        (
+            r#"{"blockHash":"0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"}"#,
+            true,
+        ),
+    ] {
+        let requirements = RpcRequirements::from_payload(&format!(
+            r#"{{"jsonrpc":"2.0","method":"eth_getBalance","params":["0x0",{}],"id":1}}"#,
+            block
+        ));
+        assert_eq!(requirements.archive, archive, "block: {}", block);
+    }
+
+    assert_eq!(
+        RpcRequirements::from_payload("not json"),
+        RpcRequirements::default()
+    );
+}
+
+#[test]
+fn test_provider_capabilities() {
+    let capabilities = ProviderCapabilities::default();
+    assert!(capabilities.supports_method("eth_blockNumber"));
+    assert!(capabilities.supports_method("net_version"));
+    assert!(!capabilities.supports_method("debug_traceTransaction"));
+    assert!(!capabilities.supports_method("trace_block"));
+    assert!(!capabilities.supports_method("eth_subscribe"));
+
+    let debug_requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"debug_traceTransaction","params":["0x0"],"id":1}"#,
+    );
+    let archive_requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"eth_call","params":[{"to":"0x0"},"earliest"],"id":1}"#,
+    );
+    let logs_requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"eth_getLogs","params":[{"fromBlock":"0x0","toBlock":"0x3e7"}],"id":1}"#,
+    );
+    let batch_requirements = RpcRequirements::from_payload(
+        r#"[{"jsonrpc":"2.0","method":"eth_chainId","id":1},{"jsonrpc":"2.0","method":"eth_chainId","id":2}]"#,
+    );
+    assert!(!capabilities.can_serve(&debug_requirements));
+    assert!(!capabilities.can_serve(&archive_requirements));
+    assert!(capabilities.can_serve(&logs_requirements));
+    assert!(capabilities.can_serve(&batch_requirements));
+
+    let capabilities = ProviderCapabilities {
+        namespaces: vec![RpcNamespace::Eth, RpcNamespace::Debug],
+        archive: true,
+        max_logs_block_range: Some(500),
+        max_batch_size: Some(1),
+    };
+    assert!(capabilities.can_serve(&debug_requirements));
+    assert!(capabilities.can_serve(&archive_requirements));
+    assert!(!capabilities.can_serve(&logs_requirements));
+    assert!(!capabilities.can_serve(&batch_requirements));
+}
+
+#[test]
+fn test_resolve_source_capabilities() {
+    PROVIDERS.with(|p| {
+        p.borrow_mut().insert(
+            0,
+            Provider {
+                provider_id: 0,
+                owner: Principal::anonymous(),
+                chain_id: ETH_MAINNET_CHAIN_ID,
+                hostname: "cloudflare-eth.com".to_string(),
+                credential_path: String::new(),
+                credential_headers: vec![],
+                cycles_per_call: 0,
+                cycles_per_message_byte: 0,
+                cycles_owed: 0,
+                primary: false,
+                capabilities: None,
+            },
+        )
+    });
+    let requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"eth_gasPrice","params":[],"id":1}"#,
+    );
+    let debug_requirements = RpcRequirements::from_payload(
+        r#"{"jsonrpc":"2.0","method":"debug_traceTransaction","params":["0x0"],"id":1}"#,
+    );
+    let error = |source: Source, requirements: &RpcRequirements| match source.resolve(requirements)
+    {
+        Ok(_) => None,
+        Err(err) => Some(err),
+    };
+    for source in [
+        Source::Provider(0),
+        Source::Chain(ETH_MAINNET_CHAIN_ID),
+        Source::Service {
+            hostname: "cloudflare-eth.com".to_string(),
+            chain_id: None,
+        },
+    ] {
+        // Providers with unknown capabilities are not filtered
+        assert_eq!(error(source.clone(), &requirements), None);
+        assert_eq!(error(source, &debug_requirements), None);
+    }
+    PROVIDERS.with(|p| {
+        let mut providers = p.borrow_mut();
+        let mut provider = providers.get(&0).unwrap();
+        provider.capabilities = Some(ProviderCapabilities::default());
+        providers.insert(0, provider);
+    });
+    for source in [
+        Source::Provider(0),
+        Source::Chain(ETH_MAINNET_CHAIN_ID),
+        Source::Service {
+            hostname: "cloudflare-eth.com".to_string(),
+            chain_id: None,
+        },
+    ] {
+        assert_eq!(error(source.clone(), &requirements), None);
+        assert_eq!(
+            error(source, &debug_requirements),
+            Some(ProviderError::UnsupportedCapabilities)
+        );
+    }
+    assert_eq!(
+        error(Source::Provider(1), &requirements),
+        Some(ProviderError::ProviderNotFound)
+    );
+    assert_eq!(
+        error(Source::Chain(ETH_SEPOLIA_CHAIN_ID), &debug_requirements),
+        Some(ProviderError::ProviderNotFound)
+    );
+}
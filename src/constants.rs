@@ -3,14 +3,57 @@ use cketh_common::eth_rpc_client::providers::{EthereumProvider, SepoliaProvider}
 pub const INGRESS_OVERHEAD_BYTES: u128 = 100;
 pub const INGRESS_MESSAGE_RECEIVED_COST: u128 = 1_200_000;
 pub const INGRESS_MESSAGE_BYTE_RECEIVED_COST: u128 = 2_000;
-pub const HTTP_OUTCALL_REQUEST_COST: u128 = 400_000_000;
-pub const HTTP_OUTCALL_BYTE_RECEIEVED_COST: u128 = 100_000;
+// HTTPS outcall pricing used by the replica, see `PricingConfig`
+pub const HTTP_OUTCALL_REQUEST_BASE_COST: u128 = 3_000_000;
+pub const HTTP_OUTCALL_REQUEST_PER_NODE_COST: u128 = 60_000;
+pub const HTTP_OUTCALL_REQUEST_BYTE_COST: u128 = 400;
+pub const HTTP_OUTCALL_RESPONSE_BYTE_COST: u128 = 800;
+// Upper bound on the size of priced messages (ingress messages and HTTPS outcall responses are
+// limited to 2 MiB), used to check that pricing cannot overflow.
+pub const MAX_PRICED_MESSAGE_BYTES: u128 = 4 * 1024 * 1024;
 
 pub const MINIMUM_WITHDRAWAL_CYCLES: u128 = 1_000_000_000;
 
 pub const STRING_STORABLE_MAX_SIZE: u32 = 100;
 pub const AUTH_SET_STORABLE_MAX_SIZE: u32 = 1000;
+pub const CREDENTIALS_STORABLE_MAX_SIZE: u32 = 2048;
+pub const LEGACY_PROVIDER_STORABLE_MAX_SIZE: u32 = 256;
+pub const PROVIDER_STORABLE_MAX_SIZE: u32 = 4096;
+pub const HOST_SCOPE_STORABLE_MAX_SIZE: u32 = 512;
 pub const WASM_PAGE_SIZE: u64 = 65536;
+/// Virtual memory of the credential key, which `stable_read` refuses to return.
+pub const CREDENTIAL_KEY_MEMORY_ID: u8 = 4;
+pub const MAX_AUTHORIZATIONS_PAGE_SIZE: u64 = 100;
+pub const MAX_BALANCE_HISTORY_PAGE_SIZE: u64 = 100;
+pub const MAX_PAYOUT_HISTORY_PAGE_SIZE: u64 = 100;
+pub const MAX_PAYOUT_HISTORY_SCAN: u64 = 10_000;
+pub const MAX_SERVICE_FEE_BPS: u32 = 10_000;
+pub const REGISTRY_SNAPSHOT_VERSION: u32 = 1;
+pub const MAX_REGISTRY_EXPORT_PAGE_SIZE: u64 = 100;
+pub const MAX_METRIC_LABELS: usize = 1_000;
+pub const MAX_METHOD_LABEL_LENGTH: usize = 64;
+pub const OTHER_METRIC_LABEL: &str = "other";
+pub const BATCH_METRIC_LABEL: &str = "batch";
+pub const MAX_REQUEST_LOG_ENTRIES: usize = 1_000;
+pub const MAX_REQUEST_LOG_PAGE_SIZE: usize = 100;
+pub const MAX_AUDIT_LOG_ENTRIES: u64 = 10_000;
+pub const MAX_AUDIT_LOG_PAGE_SIZE: u64 = 100;
+pub const REQUEST_LATENCY_BUCKETS_MS: &[u64] = &[
+    500, 1_000, 2_000, 3_000, 5_000, 10_000, 20_000, 30_000, 60_000,
+];
+pub const RESPONSE_SIZE_BUCKETS_BYTES: &[u64] = &[
+    256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 2_097_152,
+];
+
+pub const MAX_ERROR_RESPONSE_BODY_LENGTH: usize = 1024;
+// Response size estimates used by `CkEthRpcClient`, excluding headers
+pub const ETH_GET_LOGS_RESPONSE_SIZE_ESTIMATE: u64 = 1024;
+pub const ETH_GET_BLOCK_BY_NUMBER_RESPONSE_SIZE_ESTIMATE: u64 = 24 * 1024;
+pub const ETH_GET_TRANSACTION_RECEIPT_RESPONSE_SIZE_ESTIMATE: u64 = 700;
+pub const ETH_GET_TRANSACTION_COUNT_RESPONSE_SIZE_ESTIMATE: u64 = 50;
+pub const ETH_FEE_HISTORY_RESPONSE_SIZE_ESTIMATE: u64 = 512;
+pub const ETH_SEND_RAW_TRANSACTION_RESPONSE_SIZE_ESTIMATE: u64 = 256;
+pub const ETH_CALL_RESPONSE_SIZE_ESTIMATE: u64 = 2 * 1024;
 
 pub const DEFAULT_NODES_IN_SUBNET: u32 = 13;
 pub const DEFAULT_OPEN_RPC_ACCESS: bool = true;
@@ -20,11 +63,31 @@ pub const DEFAULT_ETHEREUM_PROVIDER: EthereumProvider = EthereumProvider::Ankr;
 pub const DEFAULT_SEPOLIA_PROVIDER: SepoliaProvider = SepoliaProvider::PublicNode;
 
 pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+/// Headers which are set by the canister or the HTTP client, or which affect how the request is
+/// framed or forwarded.
+pub const RESERVED_CREDENTIAL_HEADERS: &[&str] = &[
+    CONTENT_TYPE_HEADER,
+    "Host",
+    "Content-Length",
+    "Content-Encoding",
+    "Transfer-Encoding",
+    "Connection",
+    "Keep-Alive",
+    "Upgrade",
+    "TE",
+    "Trailer",
+    "Expect",
+    "Forwarded",
+    "Via",
+];
+pub const RESERVED_CREDENTIAL_HEADER_PREFIXES: &[&str] = &["Proxy-", "X-Forwarded-"];
 
 pub const ETH_MAINNET_CHAIN_ID: u64 = 1;
 pub const ETH_SEPOLIA_CHAIN_ID: u64 = 11155111;
 
-pub const SERVICE_HOSTS_ALLOWLIST: &[&str] = &[
+/// Hosts allowed on first install, after which the allowlist is managed with `add_allowed_host`
+/// and `remove_allowed_host`.
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
     "cloudflare-eth.com",
     "ethereum.publicnode.com",
     "ethereum-goerli.publicnode.com",
@@ -49,8 +112,6 @@ pub const SERVICE_HOSTS_ALLOWLIST: &[&str] = &[
     "eth.llamarpc.com",
     "ethereum-mainnet-rpc.allthatnode.com",
     "api.zmok.io",
-    "in-light.eth.linkpool.iono",
     "api.mycryptoapi.com",
-    "mainnet.eth.cloud.ava.dono",
     "eth-mainnet.gateway.pokt.network",
 ];
@@ -0,0 +1,185 @@
+use ic_stable_structures::Storable;
+
+use crate::*;
+
+/// Returns the scope of the allowlist entry matching the hostname, preferring an exact match
+/// over the most specific wildcard.
+pub fn get_host_scope(hostname: &str) -> Option<HostScope> {
+    // Wildcard patterns only match concrete hostnames.
+    if hostname.contains('*') {
+        return None;
+    }
+    let hostname = hostname.to_ascii_lowercase();
+    ALLOWED_HOSTS.with(|h| {
+        let hosts = h.borrow();
+        if let Some(scope) = hosts.get(&StringStorable(hostname.clone())) {
+            return Some(scope);
+        }
+        hostname
+            .match_indices('.')
+            .find_map(|(i, _)| hosts.get(&StringStorable(format!("*{}", &hostname[i..]))))
+    })
+}
+
+/// Returns `true` if the hostname is allowed for the chain (`None` for custom URLs, see
+/// `HostScope::allows`).
+pub fn is_host_allowed(hostname: &str, chain_id: Option<u64>) -> bool {
+    get_host_scope(hostname).map_or(false, |scope| scope.allows(chain_id))
+}
+
+/// Checks that the hostname is a lowercase DNS name, optionally prefixed with `*.`.
+///
+/// Wildcards must be below a registrable domain, so that `*.com` or `*.github.io` cannot allow
+/// hosts of every owner under a public suffix.
+pub fn validate_host_pattern(hostname: &str) -> Result<(), String> {
+    if hostname.len() > STRING_STORABLE_MAX_SIZE as usize {
+        return Err("Hostname is too long".to_string());
+    }
+    let wildcard_domain = hostname.strip_prefix("*.");
+    let domain = wildcard_domain.unwrap_or(hostname);
+    let valid = domain.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    });
+    if !valid {
+        return Err(format!("Invalid hostname: {}", hostname));
+    }
+    if wildcard_domain.map_or(false, |domain| psl::domain_str(domain).is_none()) {
+        return Err(format!("Wildcard on a public suffix: {}", hostname));
+    }
+    Ok(())
+}
+
+pub fn do_add_allowed_host(host: AllowedHost) {
+    validate_host_pattern(&host.hostname).unwrap_or_else(|e| ic_cdk::trap(&e));
+    for prefix in host.path_prefixes.iter().flatten() {
+        if !prefix.starts_with('/') {
+            ic_cdk::trap("Path prefixes must start with '/'");
+        }
+    }
+    let scope = HostScope {
+        chain_ids: host.chain_ids,
+        ports: host.ports,
+        path_prefixes: host.path_prefixes,
+    };
+    if scope.to_bytes().len() > HOST_SCOPE_STORABLE_MAX_SIZE as usize {
+        ic_cdk::trap("Host rules are too large");
+    }
+    ALLOWED_HOSTS.with(|h| h.borrow_mut().insert(StringStorable(host.hostname), scope));
+}
+
+pub fn do_remove_allowed_host(hostname: String) -> bool {
+    ALLOWED_HOSTS.with(|h| h.borrow_mut().remove(&StringStorable(hostname)).is_some())
+}
+
+pub fn do_get_allowed_hosts() -> Vec<AllowedHost> {
+    ALLOWED_HOSTS.with(|h| {
+        h.borrow()
+            .iter()
+            .map(|(hostname, scope)| AllowedHost {
+                hostname: hostname.0,
+                chain_ids: scope.chain_ids,
+                ports: scope.ports,
+                path_prefixes: scope.path_prefixes,
+            })
+            .collect()
+    })
+}
+
+/// Seeds the allowlist with `DEFAULT_ALLOWED_HOSTS`.
+pub fn init_allowed_hosts() {
+    for hostname in DEFAULT_ALLOWED_HOSTS {
+        do_add_allowed_host(AllowedHost {
+            hostname: hostname.to_string(),
+            ..Default::default()
+        });
+    }
+}
+
+#[test]
+fn test_allowed_hosts() {
+    init_allowed_hosts();
+    assert!(is_host_allowed("cloudflare-eth.com", None));
+    assert!(is_host_allowed(
+        "Cloudflare-ETH.com",
+        Some(ETH_MAINNET_CHAIN_ID)
+    ));
+    assert!(is_host_allowed("api.zmok.io", None));
+    assert!(!is_host_allowed("api.zmok.iono", None));
+    assert!(!is_host_allowed("example.com", None));
+
+    do_add_allowed_host(AllowedHost {
+        hostname: "*.example.com".to_string(),
+        chain_ids: Some(vec![ETH_SEPOLIA_CHAIN_ID]),
+        ..Default::default()
+    });
+    assert!(is_host_allowed(
+        "rpc.example.com",
+        Some(ETH_SEPOLIA_CHAIN_ID)
+    ));
+    assert!(is_host_allowed(
+        "a.rpc.example.com",
+        Some(ETH_SEPOLIA_CHAIN_ID)
+    ));
+    // Custom URLs have no known chain
+    assert!(!is_host_allowed("rpc.example.com", None));
+    assert!(!is_host_allowed(
+        "rpc.example.com",
+        Some(ETH_MAINNET_CHAIN_ID)
+    ));
+    assert!(!is_host_allowed("example.com", None));
+    assert!(!is_host_allowed("rpc.example.com.evil.org", None));
+    assert!(!is_host_allowed("*.example.com", None));
+
+    // Exact matches take precedence over wildcards
+    do_add_allowed_host(AllowedHost {
+        hostname: "mainnet.example.com".to_string(),
+        chain_ids: Some(vec![ETH_MAINNET_CHAIN_ID]),
+        ..Default::default()
+    });
+    assert!(is_host_allowed(
+        "mainnet.example.com",
+        Some(ETH_MAINNET_CHAIN_ID)
+    ));
+    assert!(!is_host_allowed(
+        "mainnet.example.com",
+        Some(ETH_SEPOLIA_CHAIN_ID)
+    ));
+
+    assert!(do_remove_allowed_host("*.example.com".to_string()));
+    assert!(!do_remove_allowed_host("*.example.com".to_string()));
+    assert!(!is_host_allowed(
+        "rpc.example.com",
+        Some(ETH_SEPOLIA_CHAIN_ID)
+    ));
+    assert_eq!(
+        do_get_allowed_hosts().len(),
+        DEFAULT_ALLOWED_HOSTS.len() + 1
+    );
+
+    for hostname in [
+        "*.example.com",
+        "*.rpc.example.co.uk",
+        "example.com",
+        "a-1.example.com",
+        "com",
+    ] {
+        assert!(validate_host_pattern(hostname).is_ok());
+    }
+    for hostname in [
+        "",
+        "*",
+        "a.*.com",
+        "example..com",
+        "Example.com",
+        "example.com/",
+        "*.com",
+        "*.co.uk",
+        "*.github.io",
+        "*.localhost",
+    ] {
+        assert!(validate_host_pattern(hostname).is_err());
+    }
+}
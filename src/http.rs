@@ -1,4 +1,4 @@
-use cketh_common::eth_rpc::{HttpOutcallError, ProviderError, RpcError, ValidationError};
+use cketh_common::eth_rpc::HttpOutcallError;
 use ic_canister_log::log;
 use ic_cdk::api::management_canister::http_request::{
     http_request as make_http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
@@ -13,53 +13,153 @@ pub async fn do_http_request(
     source: ResolvedSource,
     json_rpc_payload: &str,
     max_response_bytes: u64,
+) -> Result<HttpResponse, RpcError> {
+    let (provider_id, chain_id) = match &source {
+        ResolvedSource::Api(_) => (None, None),
+        ResolvedSource::Provider(p) => (Some(p.provider_id), Some(p.chain_id)),
+    };
+    let mut entry = RequestLogEntry {
+        id: 0,
+        timestamp: get_time(),
+        severity: LogSeverity::Info,
+        caller: caller.to_text(),
+        provider_id,
+        chain_id,
+        method: get_method_metric_label(json_rpc_payload),
+        host: None,
+        cycles_charged: 0,
+        status: None,
+        latency_ns: None,
+        error: None,
+    };
+    let result = do_logged_http_request(
+        &mut entry,
+        caller,
+        source,
+        json_rpc_payload,
+        max_response_bytes,
+    )
+    .await;
+    match &result {
+        Ok(response) => entry.status = Some(get_http_response_status(response.status.clone())),
+        Err(err) => {
+            entry.severity = LogSeverity::Error;
+            entry.error = Some(get_error_label(err));
+        }
+    }
+    record_request_log(entry);
+    result
+}
+
+/// Makes the request, recording the host, latency and cycles charged in the log entry.
+async fn do_logged_http_request(
+    entry: &mut RequestLogEntry,
+    caller: Principal,
+    source: ResolvedSource,
+    json_rpc_payload: &str,
+    max_response_bytes: u64,
 ) -> Result<HttpResponse, RpcError> {
     inc_metric!(requests);
     if !is_rpc_allowed(&caller) {
         inc_metric!(request_err_no_permission);
+        record_request_error("no_permission");
         return Err(ProviderError::NoPermission.into());
     }
     let cycles_available = ic_cdk::api::call::msg_cycles_available128();
-    let cost = get_request_cost(&source, json_rpc_payload, max_response_bytes);
+    let (http_cost, provider_cost, service_fee) =
+        get_request_costs(&caller, &source, json_rpc_payload, max_response_bytes);
+    let target = match &source {
+        ResolvedSource::Api(api) => AuthTarget {
+            hostname: url::Url::parse(&api.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string)),
+            ..Default::default()
+        },
+        ResolvedSource::Provider(provider) => AuthTarget::provider(provider),
+    };
+    // Requests covered by a `FreeRpc` grant are neither charged nor subject to service fees.
+    let free_rpc =
+        can_use_authorization(&caller, Auth::FreeRpc, &target, http_cost + provider_cost);
+    let service_fee = if free_rpc { 0 } else { service_fee };
+    let cost = http_cost + provider_cost + service_fee;
+    // Callers without open access are additionally limited by the scope and quotas of their
+    // `Rpc` grant.
+    let metered_rpc = !is_open_rpc_access();
+    if metered_rpc && !can_use_authorization(&caller, Auth::Rpc, &target, cost) {
+        inc_metric!(request_err_no_permission);
+        record_request_error("no_permission");
+        return Err(ProviderError::NoPermission.into());
+    }
+    record_request_metrics(&caller, &source, json_rpc_payload);
+    let chain_id = match &source {
+        ResolvedSource::Api(_) => None,
+        ResolvedSource::Provider(provider) => Some(provider.chain_id),
+    };
     let (api, provider) = match source {
         ResolvedSource::Api(api) => (api, None),
-        ResolvedSource::Provider(provider) => (provider.api(), Some(provider)),
+        ResolvedSource::Provider(provider) => match get_provider_api(&CanisterKeySource, &provider)
+        {
+            Ok(api) => (api, Some(provider)),
+            Err(err) => {
+                log!(INFO, "{}", err);
+                record_request_error("credentials_unavailable");
+                return Err(ProviderError::CredentialsUnavailable.into());
+            }
+        },
     };
-    let parsed_url = match url::Url::parse(&api.url) {
+    let parsed_url = match validate_url(&api.url, chain_id) {
         Ok(url) => url,
-        Err(_) => return Err(ValidationError::UrlParseError(api.url).into()),
-    };
-    let host = match parsed_url.host_str() {
-        Some(host) => host,
-        None => return Err(ValidationError::UrlParseError(api.url).into()),
+        Err(err) => {
+            if let ValidationError::HostNotAllowed(host) = &err {
+                log!(INFO, "host not allowed: {}", host);
+                inc_metric!(request_err_host_not_allowed);
+                record_request_error("host_not_allowed");
+            } else {
+                record_request_error("url_parse");
+            }
+            return Err(err.into());
+        }
     };
-    if !SERVICE_HOSTS_ALLOWLIST.contains(&host) {
-        log!(INFO, "host not allowed: {}", host);
-        inc_metric!(request_err_host_not_allowed);
-        return Err(ValidationError::HostNotAllowed(host.to_string()).into());
+    if let Err(err) = validate_credential_headers(&api.headers) {
+        record_request_error("header_not_allowed");
+        return Err(err.into());
+    }
+    // Validated URLs always have a domain.
+    let host = parsed_url.host_str().unwrap_or_default();
+    // Requests rejected for lack of cycles below are refunded to the rate limit buckets.
+    if let Err(err) = check_rate_limit(&caller, cost, get_time()) {
+        inc_metric!(request_err_rate_limited);
+        record_request_error("rate_limited");
+        return Err(err.into());
     }
-    if !is_authorized(&caller, Auth::FreeRpc) {
-        if cycles_available < cost {
+    // Callers without attached cycles pay from their prepaid balance.
+    let prepaid = !free_rpc && cycles_available == 0;
+    if !free_rpc {
+        if prepaid {
+            // Debit the full estimate up front, crediting the unused part after the outcall.
+            if let Err(balance) = debit_balance(&caller, cost, BalanceEntryKind::Request) {
+                refund_rate_limit(&caller, cost);
+                record_request_error("too_few_cycles");
+                return Err(ProviderError::TooFewCycles {
+                    expected: cost,
+                    received: balance,
+                }
+                .into());
+            }
+        } else if cycles_available < cost {
+            refund_rate_limit(&caller, cost);
+            record_request_error("too_few_cycles");
             return Err(ProviderError::TooFewCycles {
                 expected: cost,
                 received: cycles_available,
             }
             .into());
         }
-        ic_cdk::api::call::msg_cycles_accept128(cost);
-        if let Some(mut provider) = provider {
-            provider.cycles_owed += get_provider_cost(&provider, json_rpc_payload);
-            PROVIDERS.with(|p| {
-                // Error should not happen here as it was checked before.
-                p.borrow_mut()
-                    .insert(provider.provider_id, provider)
-                    .expect("unable to update Provider");
-            });
-        }
-        add_metric!(request_cycles_charged, cost);
-        add_metric!(request_cycles_refunded, cycles_available - cost);
     }
-    inc_metric_entry!(host_requests, host.to_string());
+    // Wildcard hosts let callers choose subdomains, so the host label is bounded like callers.
+    let host_label = METRICS.with(|m| get_metric_label(&m.borrow().host_requests, host));
+    inc_metric_entry!(host_requests, host_label);
+    entry.host = Some(host.to_string());
     let mut request_headers = vec![HttpHeader {
         name: CONTENT_TYPE_HEADER.to_string(),
         value: "application/json".to_string(),
@@ -76,10 +176,65 @@ pub async fn do_http_request(
             vec![],
         )),
     };
-    match make_http_request(request, cost).await {
+    let start_time = ic_cdk::api::time();
+    let result = make_http_request(request, http_cost).await;
+    // Read right after the outcall, since the refund is that of the last call of this message.
+    let http_cycles_consumed =
+        get_http_outcall_cycles_consumed(http_cost, ic_cdk::api::call::msg_cycles_refunded128());
+    let latency_ns = ic_cdk::api::time().saturating_sub(start_time);
+    entry.latency_ns = Some(latency_ns);
+    record_outcall_metrics(
+        host,
+        &entry.method,
+        latency_ns,
+        result
+            .as_ref()
+            .ok()
+            .map(|(response,)| response.body.len() as u64),
+    );
+    let charge = match &result {
+        // The replica prices outcalls by `max_response_bytes` rather than the actual response
+        // size, so successful outcalls are charged at least that price.
+        Ok(_) => http_cost.max(http_cycles_consumed) + provider_cost + service_fee,
+        // Failed outcalls are charged for consumed cycles only.
+        Err(_) => http_cycles_consumed,
+    };
+    if !free_rpc {
+        if prepaid {
+            let refund = cost - charge;
+            if refund > 0 {
+                credit_balance(&caller, refund, BalanceEntryKind::RequestRefund);
+            }
+            add_metric!(request_cycles_refunded, refund);
+        } else {
+            ic_cdk::api::call::msg_cycles_accept128(charge);
+            add_metric!(request_cycles_refunded, cycles_available - charge);
+        }
+        if result.is_ok() {
+            if let Some(provider) = provider {
+                accrue_provider_cycles(&provider, provider_cost);
+                add_metric_entry!(provider_cycles_charged, provider.provider_id, provider_cost);
+            }
+            if service_fee > 0 {
+                credit_treasury(service_fee);
+                add_metric!(request_cycles_service_fee, service_fee);
+            }
+        }
+        add_metric!(request_cycles_charged, charge);
+        entry.cycles_charged = charge;
+    }
+    // Quotas were checked against the estimate but are consumed by the actual charge.
+    if metered_rpc {
+        record_authorization_usage(&caller, Auth::Rpc, charge);
+    }
+    if free_rpc {
+        record_authorization_usage(&caller, Auth::FreeRpc, charge);
+    }
+    match result {
         Ok((response,)) => Ok(response),
         Err((code, message)) => {
             inc_metric!(request_err_http);
+            record_request_error("http");
             Err(HttpOutcallError::IcError { code, message }.into())
         }
     }
@@ -89,6 +244,34 @@ pub fn get_http_response_status(status: candid::Nat) -> u16 {
     status.0.to_u16().unwrap_or(u16::MAX)
 }
 
+/// Returns the body, truncated to `MAX_ERROR_RESPONSE_BODY_LENGTH` bytes, for inclusion in errors.
+pub fn get_truncated_response_body(body: &[u8]) -> String {
+    let body = String::from_utf8_lossy(body);
+    if body.len() <= MAX_ERROR_RESPONSE_BODY_LENGTH {
+        return body.into_owned();
+    }
+    let mut end = MAX_ERROR_RESPONSE_BODY_LENGTH;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &body[..end])
+}
+
+/// Returns an error for responses with a non-2xx HTTP status.
+pub fn check_http_response_status(response: &HttpResponse) -> Result<(), RpcError> {
+    let status = get_http_response_status(response.status.clone());
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(HttpOutcallError::InvalidHttpJsonRpcResponse {
+            status,
+            body: get_truncated_response_body(&response.body),
+            parsing_error: None,
+        }
+        .into())
+    }
+}
+
 pub fn get_http_response_body(response: HttpResponse) -> Result<String, RpcError> {
     String::from_utf8(response.body).map_err(|e| {
         HttpOutcallError::InvalidHttpJsonRpcResponse {
@@ -99,3 +282,35 @@ pub fn get_http_response_body(response: HttpResponse) -> Result<String, RpcError
         .into()
     })
 }
+
+#[test]
+fn test_check_http_response_status() {
+    let response = |status: u16, body: Vec<u8>| HttpResponse {
+        status: status.into(),
+        headers: vec![],
+        body,
+    };
+    assert!(check_http_response_status(&response(200, b"{}".to_vec())).is_ok());
+    assert!(check_http_response_status(&response(204, vec![])).is_ok());
+    for status in [301, 400, 429, 500, 503] {
+        match check_http_response_status(&response(status, b"error".to_vec())) {
+            Err(RpcError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse {
+                status: error_status,
+                body,
+                parsing_error: None,
+            })) => {
+                assert_eq!(error_status, status);
+                assert_eq!(body, "error");
+            }
+            result => panic!("unexpected result for status {}: {:?}", status, result),
+        }
+    }
+
+    // Long bodies are truncated at a character boundary
+    let body = "é".repeat(MAX_ERROR_RESPONSE_BODY_LENGTH);
+    let truncated = get_truncated_response_body(body.as_bytes());
+    assert_eq!(
+        truncated,
+        format!("{}...", "é".repeat(MAX_ERROR_RESPONSE_BODY_LENGTH / 2))
+    );
+}
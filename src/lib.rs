@@ -1,27 +1,49 @@
 pub use candid::Principal;
 
 mod accounting;
+mod audit;
 mod auth;
+mod balances;
 mod candid_rpc;
+mod capabilities;
 mod constants;
+mod hosts;
 mod http;
+mod logs;
 mod memory;
 mod metrics;
+mod migrations;
+mod payouts;
 mod providers;
+mod rate_limit;
 mod signature;
+mod snapshot;
 mod types;
+mod url_policy;
 mod util;
 mod validate;
+mod vault;
 
 pub use crate::accounting::*;
+pub use crate::audit::*;
 pub use crate::auth::*;
+pub use crate::balances::*;
 pub use crate::candid_rpc::*;
+pub use crate::capabilities::*;
 pub use crate::constants::*;
+pub use crate::hosts::*;
 pub use crate::http::*;
+pub use crate::logs::*;
 pub use crate::memory::*;
 pub use crate::metrics::*;
+pub use crate::migrations::*;
+pub use crate::payouts::*;
 pub use crate::providers::*;
+pub use crate::rate_limit::*;
 pub use crate::signature::*;
+pub use crate::snapshot::*;
 pub use crate::types::*;
+pub use crate::url_policy::*;
 pub use crate::util::*;
 pub use crate::validate::*;
+pub use crate::vault::*;
@@ -0,0 +1,227 @@
+use crate::*;
+
+/// Returns the variant name of an error, e.g. `ProviderError::TooFewCycles`.
+pub fn get_error_label(err: &RpcError) -> String {
+    fn variant<T: std::fmt::Debug>(value: &T) -> String {
+        format!("{value:?}")
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+    match err {
+        RpcError::ProviderError(e) => format!("ProviderError::{}", variant(e)),
+        RpcError::HttpOutcallError(e) => format!("HttpOutcallError::{}", variant(e)),
+        RpcError::JsonRpcError(_) => "JsonRpcError".to_string(),
+        RpcError::ValidationError(e) => format!("ValidationError::{}", variant(e)),
+    }
+}
+
+/// Appends an entry to the request log, dropping the oldest entry once the log is full.
+pub fn record_request_log(mut entry: RequestLogEntry) {
+    entry.id = NEXT_REQUEST_LOG_ID.with(|n| {
+        let mut next_id = n.borrow_mut();
+        *next_id += 1;
+        *next_id - 1
+    });
+    REQUEST_LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        if log.len() >= MAX_REQUEST_LOG_ENTRIES {
+            log.pop_front();
+        }
+        log.push_back(entry);
+    });
+}
+
+/// Returns the caller as shown in the request log, which is served to anyone over HTTP.
+///
+/// Callers are replaced with a fingerprint keyed with a canister secret, so that the requests of
+/// a caller can be correlated without revealing the caller.
+pub fn get_caller_fingerprint(caller: &str) -> String {
+    let data = [b"caller:".as_slice(), caller.as_bytes()].concat();
+    CanisterKeySource
+        .fingerprint_key()
+        .map(|key| get_fingerprint(&key, &data))
+        .unwrap_or_else(|| "***".to_string())
+}
+
+impl RequestLogFilter {
+    /// Parses the filter from the `since`, `until`, `caller` (a caller fingerprint), `provider`,
+    /// `severity`, `cursor` and `limit` query parameters.
+    pub fn from_query_params<'a>(param: impl Fn(&str) -> Option<&'a str>) -> Result<Self, String> {
+        fn parse<T: std::str::FromStr>(
+            name: &str,
+            value: Option<&str>,
+        ) -> Result<Option<T>, String> {
+            value
+                .map(|v| v.parse().map_err(|_| format!("Invalid {name}: {v}")))
+                .transpose()
+        }
+        let severity = match param("severity").map(str::to_lowercase).as_deref() {
+            None => None,
+            Some("info") => Some(LogSeverity::Info),
+            Some("error") => Some(LogSeverity::Error),
+            Some(severity) => return Err(format!("Invalid severity: {severity}")),
+        };
+        Ok(Self {
+            since: parse("since", param("since"))?,
+            until: parse("until", param("until"))?,
+            caller: param("caller").map(str::to_string),
+            provider_id: parse("provider", param("provider"))?,
+            severity,
+            cursor: parse("cursor", param("cursor"))?.unwrap_or_default(),
+            limit: parse("limit", param("limit"))?
+                .unwrap_or(MAX_REQUEST_LOG_PAGE_SIZE)
+                .min(MAX_REQUEST_LOG_PAGE_SIZE),
+        })
+    }
+
+    pub fn matches(&self, entry: &RequestLogEntry) -> bool {
+        entry.id >= self.cursor
+            && self.since.map_or(true, |since| entry.timestamp >= since)
+            && self.until.map_or(true, |until| entry.timestamp <= until)
+            && self.caller.as_ref().map_or(true, |c| *c == entry.caller)
+            && self
+                .provider_id
+                .map_or(true, |id| entry.provider_id == Some(id))
+            && self.severity.map_or(true, |s| s == entry.severity)
+    }
+}
+
+/// Returns a page of the request log, with callers replaced by their fingerprints.
+pub fn do_get_request_log(filter: &RequestLogFilter) -> RequestLogPage {
+    REQUEST_LOG.with(|l| {
+        let log = l.borrow();
+        let mut matching = log
+            .iter()
+            .map(|entry| RequestLogEntry {
+                caller: get_caller_fingerprint(&entry.caller),
+                ..entry.clone()
+            })
+            .filter(|entry| filter.matches(entry));
+        let entries: Vec<_> = matching.by_ref().take(filter.limit).collect();
+        RequestLogPage {
+            entries,
+            next_cursor: matching.next().map(|entry| entry.id),
+        }
+    })
+}
+
+#[cfg(test)]
+fn test_log_entry(
+    caller: &str,
+    provider_id: Option<u64>,
+    severity: LogSeverity,
+) -> RequestLogEntry {
+    RequestLogEntry {
+        id: 0,
+        timestamp: 0,
+        severity,
+        caller: caller.to_string(),
+        provider_id,
+        chain_id: None,
+        method: "eth_gasPrice".to_string(),
+        host: None,
+        cycles_charged: 0,
+        status: None,
+        latency_ns: None,
+        error: None,
+    }
+}
+
+#[test]
+fn test_request_log() {
+    CREDENTIAL_KEY.with(|k| {
+        let mut state = k.borrow().get().clone();
+        state.fingerprint_key = Some([1; 32]);
+        k.borrow_mut().set(state).unwrap();
+    });
+    for i in 0..MAX_REQUEST_LOG_ENTRIES + 10 {
+        let mut entry = test_log_entry(
+            if i % 2 == 0 { "a" } else { "b" },
+            Some(i as u64 % 3),
+            if i % 5 == 0 {
+                LogSeverity::Error
+            } else {
+                LogSeverity::Info
+            },
+        );
+        entry.timestamp = i as u64;
+        record_request_log(entry);
+    }
+    // The oldest entries are dropped
+    let all = RequestLogFilter {
+        limit: MAX_REQUEST_LOG_PAGE_SIZE,
+        ..Default::default()
+    };
+    let page = do_get_request_log(&all);
+    assert_eq!(page.entries.len(), MAX_REQUEST_LOG_PAGE_SIZE);
+    assert_eq!(page.entries[0].id, 10);
+    assert_eq!(
+        page.next_cursor,
+        Some(10 + MAX_REQUEST_LOG_PAGE_SIZE as u64)
+    );
+
+    // Callers are only shown as fingerprints
+    let fingerprint = get_caller_fingerprint("a");
+    assert_ne!(fingerprint, "a");
+    assert_ne!(fingerprint, get_caller_fingerprint("b"));
+    assert!(page
+        .entries
+        .iter()
+        .all(|e| e.caller != "a" && e.caller != "b"));
+
+    let filter = RequestLogFilter::from_query_params(|name| match name {
+        "caller" => Some(fingerprint.as_str()),
+        "provider" => Some("0"),
+        "severity" => Some("ERROR"),
+        "since" => Some("100"),
+        "until" => Some("200"),
+        "limit" => Some("2"),
+        _ => None,
+    })
+    .unwrap();
+    let page = do_get_request_log(&filter);
+    // Entries divisible by 2, 3 and 5
+    assert_eq!(
+        page.entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+        vec![120, 150]
+    );
+    assert_eq!(page.next_cursor, Some(180));
+    let page = do_get_request_log(&RequestLogFilter {
+        cursor: 180,
+        ..filter
+    });
+    assert_eq!(
+        page.entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+        vec![180]
+    );
+    assert_eq!(page.next_cursor, None);
+
+    assert!(
+        RequestLogFilter::from_query_params(|name| (name == "severity").then_some("x")).is_err()
+    );
+    assert!(RequestLogFilter::from_query_params(|name| (name == "since").then_some("x")).is_err());
+}
+
+#[test]
+fn test_error_label() {
+    assert_eq!(
+        get_error_label(&RpcError::ProviderError(ProviderError::TooFewCycles {
+            expected: 1,
+            received: 0
+        })),
+        "ProviderError::TooFewCycles"
+    );
+    assert_eq!(
+        get_error_label(&ProviderError::NoPermission.into()),
+        "ProviderError::NoPermission"
+    );
+    assert_eq!(
+        get_error_label(
+            &cketh_common::eth_rpc::ValidationError::HostNotAllowed("example.com".to_string())
+                .into()
+        ),
+        "ValidationError::HostNotAllowed"
+    );
+}
@@ -1,7 +1,5 @@
-use candid::{candid_method, CandidType};
-use cketh_common::eth_rpc::{
-    Block, FeeHistory, LogEntry, ProviderError, RpcError, SendRawTransactionResult,
-};
+use candid::candid_method;
+use cketh_common::eth_rpc::{Block, FeeHistory, LogEntry, SendRawTransactionResult};
 
 use ic_canister_log::log;
 use ic_canisters_http_types::{
@@ -9,7 +7,8 @@ use ic_canisters_http_types::{
 };
 use ic_cdk::api::management_canister::http_request::{HttpHeader, HttpResponse, TransformArgs};
 use ic_cdk::{query, update};
-use ic_nervous_system_common::{serve_logs, serve_logs_v2, serve_metrics};
+use ic_nervous_system_common::{serve_logs, serve_metrics};
+use ic_stable_structures::DefaultMemoryImpl;
 
 use evm_rpc::*;
 
@@ -79,9 +78,82 @@ pub async fn eth_send_raw_transaction(
         .await
 }
 
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn eth_call(source: CandidRpcSource, args: candid_types::CallArgs) -> RpcResult<String> {
+    CandidRpcClient::from_source(source)?.eth_call(args).await
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_get_logs_cost(
+    source: CandidRpcSource,
+    args: candid_types::GetLogsArgs,
+) -> RpcResult<u128> {
+    CandidRpcClient::eth_get_logs_cost(&ic_cdk::caller(), &source, args)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_get_block_by_number_cost(
+    source: CandidRpcSource,
+    block: candid_types::BlockSpec,
+) -> RpcResult<u128> {
+    CandidRpcClient::eth_get_block_by_number_cost(&ic_cdk::caller(), &source, block)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_get_transaction_receipt_cost(source: CandidRpcSource, hash: String) -> RpcResult<u128> {
+    CandidRpcClient::eth_get_transaction_receipt_cost(&ic_cdk::caller(), &source, hash)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_get_transaction_count_cost(
+    source: CandidRpcSource,
+    args: candid_types::GetTransactionCountArgs,
+) -> RpcResult<u128> {
+    CandidRpcClient::eth_get_transaction_count_cost(&ic_cdk::caller(), &source, args)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_fee_history_cost(
+    source: CandidRpcSource,
+    args: candid_types::FeeHistoryArgs,
+) -> RpcResult<u128> {
+    CandidRpcClient::eth_fee_history_cost(&ic_cdk::caller(), &source, args)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_send_raw_transaction_cost(
+    source: CandidRpcSource,
+    raw_signed_transaction_hex: String,
+) -> RpcResult<u128> {
+    CandidRpcClient::eth_send_raw_transaction_cost(
+        &ic_cdk::caller(),
+        &source,
+        raw_signed_transaction_hex,
+    )
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn eth_call_cost(source: CandidRpcSource, args: candid_types::CallArgs) -> RpcResult<u128> {
+    CandidRpcClient::eth_call_cost(&ic_cdk::caller(), &source, args)
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn recover_address(message: Message, signature: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+    do_recover_address(message.into(), &signature).map(|address| address.to_vec())
+}
+
 #[ic_cdk_macros::query]
 #[candid_method(query)]
-pub fn verify_signature(signed_message: SignedMessage) -> bool {
+pub fn verify_signature(signed_message: SignedMessage) -> Result<bool, SignatureError> {
     do_verify_signature(
         &signed_message.address,
         signed_message.message.into(),
@@ -89,20 +161,51 @@ pub fn verify_signature(signed_message: SignedMessage) -> bool {
     )
 }
 
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn verify_personal_signature(
+    signed_message: SignedPersonalMessage,
+) -> Result<bool, SignatureError> {
+    do_verify_personal_signature(
+        &signed_message.address,
+        signed_message.message,
+        signed_message.signature,
+    )
+}
+
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn verify_typed_data_signature(signed_data: SignedTypedData) -> Result<bool, SignatureError> {
+    do_verify_typed_data_signature(
+        &signed_data.address,
+        &signed_data.typed_data,
+        signed_data.signature,
+    )
+}
+
 #[update]
 #[candid_method]
 async fn request(
     source: Source,
     json_rpc_payload: String,
     max_response_bytes: u64,
+    options: Option<RequestOptions>,
 ) -> Result<String, RpcError> {
+    let requirements = RpcRequirements::from_payload(&json_rpc_payload);
     let response = do_http_request(
         ic_cdk::caller(),
-        source.resolve()?,
+        source.resolve(&requirements)?,
         &json_rpc_payload,
         max_response_bytes,
     )
     .await?;
+    if !options
+        .unwrap_or_default()
+        .allow_http_errors
+        .unwrap_or_default()
+    {
+        check_http_response_status(&response)?;
+    }
     get_http_response_body(response)
 }
 
@@ -113,8 +216,10 @@ fn request_cost(
     json_rpc_payload: String,
     max_response_bytes: u64,
 ) -> Result<u128, RpcError> {
+    let requirements = RpcRequirements::from_payload(&json_rpc_payload);
     Ok(get_request_cost(
-        &source.resolve().unwrap(),
+        &ic_cdk::caller(),
+        &source.resolve(&requirements)?,
         &json_rpc_payload,
         max_response_bytes,
     ))
@@ -131,22 +236,68 @@ fn get_providers() -> Vec<ProviderView> {
     })
 }
 
+#[query]
+#[candid_method(query)]
+fn get_provider_details(provider_id: u64) -> ProviderDetails {
+    do_get_provider_details(ic_cdk::caller(), provider_id)
+}
+
 #[update(guard = "require_register_provider")]
 #[candid_method]
 fn register_provider(provider: RegisterProviderArgs) -> u64 {
-    do_register_provider(ic_cdk::caller(), provider)
+    let caller = ic_cdk::caller();
+    require_register_provider_scope(&caller, provider.chain_id, &provider.hostname);
+    let provider_id = do_register_provider(caller, provider);
+    record_provider_audit_entry(caller, AuditAction::RegisterProvider { provider_id });
+    provider_id
 }
 
 #[update(guard = "require_register_provider")]
 #[candid_method]
 fn unregister_provider(provider_id: u64) -> bool {
-    do_unregister_provider(ic_cdk::caller(), provider_id)
+    let caller = ic_cdk::caller();
+    let removed = do_unregister_provider(caller, provider_id);
+    if removed {
+        record_provider_audit_entry(caller, AuditAction::UnregisterProvider { provider_id });
+    }
+    removed
 }
 
-#[update(guard = "require_register_provider")]
+#[update(guard = "require_manage_provider")]
 #[candid_method]
 fn update_provider(provider: UpdateProviderArgs) {
-    do_update_provider(ic_cdk::caller(), provider)
+    let caller = ic_cdk::caller();
+    let provider_id = provider.provider_id;
+    do_update_provider(caller, provider);
+    record_provider_audit_entry(caller, AuditAction::UpdateProvider { provider_id });
+}
+
+#[update(guard = "require_register_provider")]
+#[candid_method]
+fn add_provider_manager(provider_id: u64, manager: Principal) {
+    let caller = ic_cdk::caller();
+    do_add_provider_manager(caller, provider_id, manager);
+    record_provider_audit_entry(
+        caller,
+        AuditAction::AddProviderManager {
+            provider_id,
+            manager,
+        },
+    );
+}
+
+#[update(guard = "require_manage_provider")]
+#[candid_method]
+fn remove_provider_manager(provider_id: u64, manager: Principal) {
+    let caller = ic_cdk::caller();
+    do_remove_provider_manager(caller, provider_id, manager);
+    record_provider_audit_entry(
+        caller,
+        AuditAction::RemoveProviderManager {
+            provider_id,
+            manager,
+        },
+    );
 }
 
 #[query(guard = "require_register_provider")]
@@ -164,31 +315,82 @@ fn get_accumulated_cycle_count(provider_id: u64) -> u128 {
     provider.cycles_owed
 }
 
-#[derive(CandidType)]
-struct DepositCyclesArgs {
-    canister_id: Principal,
+#[update]
+#[candid_method]
+async fn withdraw_accumulated_cycles(provider_id: u64, canister_id: Principal) {
+    do_withdraw_accumulated_cycles(ic_cdk::caller(), provider_id, canister_id).await
 }
 
-#[update(guard = "require_register_provider")]
+#[query]
+#[candid_method(query)]
+fn get_escrow(provider_id: u64) -> Option<Escrow> {
+    evm_rpc::get_escrow(provider_id)
+}
+
+#[query]
+#[candid_method(query)]
+fn get_payout_history(provider_id: Option<u64>, start: u64, limit: u64) -> PayoutHistory {
+    let caller = ic_cdk::caller();
+    let authorized = match provider_id {
+        Some(provider_id) => can_view_payouts(&caller, provider_id),
+        None => is_admin_or_controller(&caller),
+    };
+    if !authorized {
+        ic_cdk::trap("Not authorized");
+    }
+    do_get_payout_history(provider_id, start, limit)
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn reconcile_cycles() -> CyclesReconciliation {
+    do_reconcile_cycles(ic_cdk::api::canister_balance128())
+}
+
+#[update]
 #[candid_method]
-async fn withdraw_accumulated_cycles(provider_id: u64, canister_id: Principal) {
-    let provider = PROVIDERS.with(|p| {
-        p.borrow()
-            .get(&provider_id)
-            .ok_or(ProviderError::ProviderNotFound)
-    });
-    let mut provider = provider.expect("Provider not found");
-    if ic_cdk::caller() != provider.owner {
-        ic_cdk::trap("Not owner");
+fn deposit_cycles(beneficiary: Option<Principal>) -> u128 {
+    let caller = ic_cdk::caller();
+    let amount =
+        ic_cdk::api::call::msg_cycles_accept128(ic_cdk::api::call::msg_cycles_available128());
+    do_deposit_cycles(caller, beneficiary.unwrap_or(caller), amount)
+}
+
+#[query]
+#[candid_method(query)]
+fn get_balance(principal: Option<Principal>) -> u128 {
+    let caller = ic_cdk::caller();
+    let principal = principal.unwrap_or(caller);
+    // Only admins may see the balances of other principals.
+    if principal != caller && !is_admin_or_controller(&caller) {
+        ic_cdk::trap("You are not authorized");
     }
-    let amount = provider.cycles_owed;
+    evm_rpc::get_balance(&principal)
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn get_balance_history(start: u64, limit: u64) -> Vec<BalanceEntry> {
+    do_get_balance_history(start, limit)
+}
+
+#[update]
+#[candid_method]
+async fn withdraw(amount: u128, canister_id: Principal) {
+    let caller = ic_cdk::caller();
     if amount < MINIMUM_WITHDRAWAL_CYCLES {
         ic_cdk::trap("Too few cycles to withdraw");
     }
-    PROVIDERS.with(|p| {
-        provider.cycles_owed = 0;
-        p.borrow_mut().insert(provider_id, provider)
-    });
+    // Debit before sending so that concurrent withdrawals cannot spend the same cycles.
+    if debit_balance(
+        &caller,
+        amount,
+        BalanceEntryKind::Withdraw { to: canister_id },
+    )
+    .is_err()
+    {
+        ic_cdk::trap("Insufficient balance");
+    }
     match ic_cdk::api::call::call_with_payment128(
         Principal::management_canister(),
         "deposit_cycles",
@@ -199,25 +401,20 @@ async fn withdraw_accumulated_cycles(provider_id: u64, canister_id: Principal) {
     {
         Ok(()) => (),
         e => {
-            // Refund on failure to send cycles.
+            // Refund on failure to send cycles (without trapping, which would revert the refund).
             log!(
                 INFO,
-                "Unable to send {} cycles to {} for provider {}: {:?}",
+                "Unable to withdraw {} cycles from {} to {}: {:?}",
                 amount,
+                caller,
                 canister_id,
-                provider_id,
                 e
             );
-            let provider = PROVIDERS.with(|p| {
-                p.borrow()
-                    .get(&provider_id)
-                    .ok_or(ProviderError::ProviderNotFound)
-            });
-            let mut provider = provider.expect("Provider not found during refund, cycles lost.");
-            PROVIDERS.with(|p| {
-                provider.cycles_owed += amount;
-                p.borrow_mut().insert(provider_id, provider)
-            });
+            credit_balance(
+                &caller,
+                amount,
+                BalanceEntryKind::WithdrawRefund { to: canister_id },
+            );
         }
     };
 }
@@ -234,33 +431,88 @@ fn transform(args: TransformArgs) -> HttpResponse {
 }
 
 #[ic_cdk::init]
-fn init() {
+#[candid_method(init)]
+fn init(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    init_schema_version();
+    init_allowed_hosts();
     METADATA.with(|m| {
         let mut metadata = m.borrow().get().clone();
-        metadata.nodes_in_subnet = DEFAULT_NODES_IN_SUBNET;
-        metadata.open_rpc_access = DEFAULT_OPEN_RPC_ACCESS;
+        metadata.nodes_in_subnet = args.nodes_in_subnet.unwrap_or(DEFAULT_NODES_IN_SUBNET);
+        metadata.open_rpc_access = args.open_rpc_access.unwrap_or(DEFAULT_OPEN_RPC_ACCESS);
         m.borrow_mut().set(metadata).unwrap();
     });
-
-    for provider in get_default_providers() {
-        do_register_provider(ic_cdk::caller(), provider);
+    for admin in args.admins.unwrap_or_default() {
+        do_authorize(admin, Auth::ManageService);
+    }
+    for provider in args.providers.unwrap_or_else(get_default_providers) {
+        do_register_initial_provider(ic_cdk::caller(), provider);
     }
+    schedule_credential_key_init();
 }
 
-// #[ic_cdk::post_upgrade]
-// fn post_upgrade() {}
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    save_metrics();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<UpgradeArgs>) {
+    run_migrations();
+    restore_metrics();
+    if let Some(args) = args {
+        if args.providers.is_some() {
+            ic_cdk::trap("Providers can only be set on install; use `register_provider` instead");
+        }
+        METADATA.with(|m| {
+            let mut metadata = m.borrow().get().clone();
+            if let Some(nodes_in_subnet) = args.nodes_in_subnet {
+                metadata.nodes_in_subnet = nodes_in_subnet;
+            }
+            if let Some(open_rpc_access) = args.open_rpc_access {
+                metadata.open_rpc_access = open_rpc_access;
+            }
+            m.borrow_mut().set(metadata).unwrap();
+        });
+        for admin in args.admins.unwrap_or_default() {
+            do_authorize(admin, Auth::ManageService);
+        }
+    }
+    schedule_credential_key_init();
+}
 
 #[query]
 fn http_request(request: AssetHttpRequest) -> AssetHttpResponse {
     match request.path() {
         "/metrics" => serve_metrics(encode_metrics),
-        "/logs" => serve_logs_v2(request, &INFO, &ERROR),
+        "/logs" => {
+            match RequestLogFilter::from_query_params(|name| request.raw_query_param(name)) {
+                Ok(filter) => HttpResponseBuilder::ok()
+                    .header("Content-Type", "application/json; charset=utf-8")
+                    .with_body_and_content_length(
+                        serde_json::to_string(&do_get_request_log(&filter)).unwrap_or_default(),
+                    )
+                    .build(),
+                Err(err) => HttpResponseBuilder::bad_request()
+                    .with_body_and_content_length(err)
+                    .build(),
+            }
+        }
+        "/log/debug" => serve_logs(&DEBUG),
         "/log/info" => serve_logs(&INFO),
         "/log/error" => serve_logs(&ERROR),
         _ => HttpResponseBuilder::not_found().build(),
     }
 }
 
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+async fn rotate_credential_key() {
+    let caller = ic_cdk::caller();
+    do_rotate_credential_key().await;
+    record_audit_entry(caller, AuditAction::RotateCredentialKey);
+}
+
 #[query(guard = "require_admin_or_controller")]
 fn stable_size() -> u64 {
     ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE
@@ -268,6 +520,9 @@ fn stable_size() -> u64 {
 
 #[query(guard = "require_admin_or_controller")]
 fn stable_read(offset: u64, length: u64) -> Vec<u8> {
+    if is_credential_key_range(&DefaultMemoryImpl::default(), offset, length) {
+        ic_cdk::trap("Stable memory range includes the credential key");
+    }
     let mut buffer = Vec::new();
     buffer.resize(length as usize, 0);
     ic_cdk::api::stable::stable64_read(offset, buffer.as_mut_slice());
@@ -276,6 +531,7 @@ fn stable_read(offset: u64, length: u64) -> Vec<u8> {
 
 #[update(guard = "require_admin_or_controller")]
 fn stable_write(offset: u64, buffer: Vec<u8>) {
+    let length = buffer.len() as u64;
     let size = offset + buffer.len() as u64;
     let old_size = ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE;
     if size > old_size {
@@ -284,32 +540,201 @@ fn stable_write(offset: u64, buffer: Vec<u8>) {
         ic_cdk::api::stable::stable64_grow(pages - old_pages).unwrap();
     }
     ic_cdk::api::stable::stable64_write(offset, buffer.as_slice());
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::StableWrite { offset, length },
+    );
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn export_registry(args: ExportRegistryArgs) -> RegistrySnapshot {
+    // Plaintext credentials are only revealed to controllers.
+    if args.include_credentials && !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("Only controllers may export credentials");
+    }
+    do_export_registry(&CanisterKeySource, args)
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn import_registry(snapshot: RegistrySnapshot, mode: ImportMode) {
+    let timestamp = snapshot.timestamp;
+    if let Err(err) = do_import_registry(&CanisterKeySource, snapshot, mode) {
+        ic_cdk::trap(&format!("Invalid snapshot: {}", err));
+    }
+    record_audit_entry(ic_cdk::caller(), AuditAction::ImportRegistry { timestamp });
 }
 
 #[update(guard = "require_admin_or_controller")]
 #[candid_method]
 fn authorize(principal: Principal, auth: Auth) {
-    do_authorize(principal, auth)
+    do_authorize(principal, auth);
+    record_audit_entry(ic_cdk::caller(), AuditAction::Authorize { principal, auth });
+}
+
+#[query]
+#[candid_method(query)]
+fn get_pricing() -> PricingConfig {
+    evm_rpc::get_pricing()
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn set_pricing(pricing: PricingConfig) {
+    do_set_pricing(pricing);
+    record_audit_entry(ic_cdk::caller(), AuditAction::SetPricing);
+}
+
+#[query]
+#[candid_method(query)]
+fn get_service_fees() -> Vec<AuthServiceFee> {
+    METADATA.with(|m| m.borrow().get().service_fees.clone().unwrap_or_default())
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn set_service_fee(auth: Option<Auth>, fee: Option<ServiceFee>) {
+    do_set_service_fee(auth, fee);
+    record_audit_entry(ic_cdk::caller(), AuditAction::SetServiceFee { auth });
+}
+
+#[query]
+#[candid_method(query)]
+fn get_allowed_hosts() -> Vec<AllowedHost> {
+    do_get_allowed_hosts()
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn add_allowed_host(host: AllowedHost) {
+    let hostname = host.hostname.clone();
+    do_add_allowed_host(host);
+    record_audit_entry(ic_cdk::caller(), AuditAction::AddAllowedHost { hostname });
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn remove_allowed_host(hostname: String) -> bool {
+    let removed = do_remove_allowed_host(hostname.clone());
+    if removed {
+        record_audit_entry(
+            ic_cdk::caller(),
+            AuditAction::RemoveAllowedHost { hostname },
+        );
+    }
+    removed
 }
 
 #[query(guard = "require_admin_or_controller")]
 #[candid_method(query)]
-fn get_authorized(auth: Auth) -> Vec<String> {
-    AUTH.with(|a| {
-        let mut result = Vec::new();
-        for (k, v) in a.borrow().iter() {
-            if !v.is_authorized(auth) {
-                result.push(k.0.to_string());
-            }
+fn get_treasury_balance() -> u128 {
+    evm_rpc::get_treasury_balance()
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+async fn withdraw_treasury(amount: u128, canister_id: Principal) {
+    // Debit before sending so that concurrent withdrawals cannot spend the same cycles.
+    if debit_treasury(amount).is_err() {
+        ic_cdk::trap("Insufficient treasury balance");
+    }
+    match ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "deposit_cycles",
+        (DepositCyclesArgs { canister_id },),
+        amount,
+    )
+    .await
+    {
+        Ok(()) => {
+            // Recorded once the cycles are sent, since failed withdrawals are refunded.
+            record_audit_entry(
+                ic_cdk::caller(),
+                AuditAction::WithdrawTreasury {
+                    amount,
+                    canister_id,
+                },
+            );
+            log!(
+                INFO,
+                "{} withdrew {} cycles from the treasury to {}",
+                ic_cdk::caller(),
+                amount,
+                canister_id
+            )
         }
-        result
-    })
+        e => {
+            // Refund on failure to send cycles (without trapping, which would revert the refund).
+            log!(
+                INFO,
+                "Unable to withdraw {} cycles from the treasury to {}: {:?}",
+                amount,
+                canister_id,
+                e
+            );
+            credit_treasury(amount);
+        }
+    };
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn set_rate_limit(auth: Option<Auth>, limit: Option<RateLimit>) {
+    do_set_rate_limit(auth, limit);
+    record_audit_entry(ic_cdk::caller(), AuditAction::SetRateLimit { auth });
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn get_rate_limits() -> Vec<AuthRateLimit> {
+    METADATA.with(|m| m.borrow().get().rate_limits.clone().unwrap_or_default())
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn set_rate_limit_override(principal: Principal, limit: Option<RateLimit>) {
+    do_set_rate_limit_override(principal, limit);
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::SetRateLimitOverride { principal },
+    );
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn get_rate_limit_override(principal: Principal) -> Option<RateLimit> {
+    RATE_LIMIT_OVERRIDES.with(|r| r.borrow().get(&PrincipalStorable(principal)))
+}
+
+#[update(guard = "require_admin_or_controller")]
+#[candid_method]
+fn authorize_with_limits(principal: Principal, auth: Auth, limits: AuthLimits) {
+    do_authorize_with_limits(principal, auth, limits);
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::AuthorizeWithLimits { principal, auth },
+    );
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn list_authorizations(
+    auth: Option<Auth>,
+    start_after: Option<Principal>,
+    limit: u64,
+) -> Vec<Authorization> {
+    do_list_authorizations(auth, start_after, limit)
 }
 
 #[update(guard = "require_admin_or_controller")]
 #[candid_method]
 fn deauthorize(principal: Principal, auth: Auth) {
-    do_deauthorize(principal, auth)
+    do_deauthorize(principal, auth);
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::Deauthorize { principal, auth },
+    );
 }
 
 #[update(guard = "require_admin_or_controller")]
@@ -320,6 +745,10 @@ fn set_open_rpc_access(open_rpc_access: bool) {
         metadata.open_rpc_access = open_rpc_access;
         m.borrow_mut().set(metadata).unwrap();
     });
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::SetOpenRpcAccess { open_rpc_access },
+    );
 }
 
 #[query(guard = "require_admin_or_controller")]
@@ -336,6 +765,16 @@ fn set_nodes_in_subnet(nodes_in_subnet: u32) {
         metadata.nodes_in_subnet = nodes_in_subnet;
         m.borrow_mut().set(metadata).unwrap();
     });
+    record_audit_entry(
+        ic_cdk::caller(),
+        AuditAction::SetNodesInSubnet { nodes_in_subnet },
+    );
+}
+
+#[query(guard = "require_admin_or_controller")]
+#[candid_method(query)]
+fn get_audit_log(start: u64, limit: u64) -> Vec<AuditEntry> {
+    do_get_audit_log(start, limit)
 }
 
 #[query(guard = "require_admin_or_controller")]
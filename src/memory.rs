@@ -1,12 +1,15 @@
+use candid::Principal;
 use ic_canister_log::declare_log_buffer;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 #[cfg(target_arch = "wasm32")]
 use ic_stable_structures::DefaultMemoryImpl;
 #[cfg(not(target_arch = "wasm32"))]
 use ic_stable_structures::VectorMemory;
-use ic_stable_structures::{Cell, StableBTreeMap};
+use ic_stable_structures::{Cell, StableBTreeMap, StableLog};
 use std::cell::RefCell;
+use std::collections::VecDeque;
 
+use crate::constants::CREDENTIAL_KEY_MEMORY_ID;
 use crate::types::*;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -20,7 +23,12 @@ declare_log_buffer!(name = ERROR, capacity = 1000);
 
 thread_local! {
     // Transient static data: this is reset when the canister is upgraded.
-    pub static TRANSIENT_METRICS: RefCell<Metrics> = RefCell::new(Metrics::default());
+    // Metrics are kept on the heap and saved to `STABLE_METRICS` before upgrades.
+    pub static METRICS: RefCell<Metrics> = RefCell::new(Metrics::default());
+    pub static REQUEST_LOG: RefCell<VecDeque<RequestLogEntry>> = RefCell::new(VecDeque::new());
+    pub static NEXT_REQUEST_LOG_ID: RefCell<u64> = RefCell::new(0);
+    pub static RATE_LIMIT_BUCKETS: RefCell<RateLimitBucketMap> =
+        RefCell::new(RateLimitBucketMap::default());
 
     // Stable static data: this is preserved when the canister is upgraded.
     #[cfg(not(target_arch = "wasm32"))]
@@ -34,6 +42,41 @@ thread_local! {
             <Metadata>::default()).unwrap());
     pub static AUTH: RefCell<StableBTreeMap<PrincipalStorable, AuthSet, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))));
-    pub static PROVIDERS: RefCell<StableBTreeMap<u64, Provider, Memory>> = RefCell::new(
+    // Providers registered before schema version 2, moved to `PROVIDERS` by a migration.
+    pub static LEGACY_PROVIDERS: RefCell<StableBTreeMap<u64, LegacyProvider, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))));
+    pub static CREDENTIALS: RefCell<StableBTreeMap<u64, StoredCredentials, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))));
+    pub static CREDENTIAL_KEY: RefCell<Cell<CredentialKeyState, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(CREDENTIAL_KEY_MEMORY_ID))),
+            <CredentialKeyState>::default()).unwrap());
+    pub static BALANCES: RefCell<StableBTreeMap<PrincipalStorable, CyclesStorable, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))));
+    pub static BALANCE_HISTORY: RefCell<StableLog<BalanceEntry, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))).unwrap());
+    pub static RATE_LIMIT_OVERRIDES: RefCell<StableBTreeMap<PrincipalStorable, RateLimit, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))));
+    pub static PAYOUT_LEDGER: RefCell<StableLog<PayoutEntry, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))).unwrap());
+    pub static ESCROW: RefCell<StableBTreeMap<u64, Escrow, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))));
+    pub static TREASURY: RefCell<Cell<CyclesStorable, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))),
+            CyclesStorable::default()).unwrap());
+    pub static STABLE_METRICS: RefCell<Cell<Metrics, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+            Metrics::default()).unwrap());
+    pub static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))));
+    pub static SCHEMA_VERSION: RefCell<Cell<u32, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+            0).unwrap());
+    pub static PROVIDERS: RefCell<StableBTreeMap<u64, Provider, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))));
+    pub static ALLOWED_HOSTS: RefCell<StableBTreeMap<StringStorable, HostScope, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))));
 }
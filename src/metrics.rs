@@ -1,37 +1,210 @@
+use std::collections::HashMap;
+
+use ic_canister_log::log;
+
+use crate::*;
+
 #[macro_export]
 macro_rules! inc_metric {
     ($metric:ident) => {{
-        $crate::TRANSIENT_METRICS.with(|m| m.borrow_mut().$metric += 1);
+        $crate::METRICS.with(|m| m.borrow_mut().$metric += 1);
     }};
 }
 
 #[macro_export]
 macro_rules! inc_metric_entry {
     ($metric:ident, $entry:expr) => {{
-        $crate::TRANSIENT_METRICS.with(|m| {
-            m.borrow_mut()
-                .$metric
-                .entry($entry.clone())
-                .and_modify(|counter| *counter += 1)
-                .or_insert(1);
-        });
+        $crate::add_metric_entry!($metric, $entry, 1);
     }};
 }
 
 #[macro_export]
 macro_rules! add_metric {
     ($metric:ident, $value:expr) => {{
-        $crate::TRANSIENT_METRICS.with(|m| m.borrow_mut().$metric += $value);
+        $crate::METRICS.with(|m| m.borrow_mut().$metric += $value);
+    }};
+}
+
+#[macro_export]
+macro_rules! add_metric_entry {
+    ($metric:ident, $entry:expr, $value:expr) => {{
+        $crate::METRICS.with(|m| {
+            let value = $value;
+            m.borrow_mut()
+                .$metric
+                .entry($entry.clone())
+                .and_modify(|counter| *counter += value)
+                .or_insert(value);
+        });
     }};
 }
 
 #[macro_export]
 macro_rules! get_metric {
     ($metric:ident) => {{
-        $crate::TRANSIENT_METRICS.with(|m| m.borrow().$metric)
+        $crate::METRICS.with(|m| m.borrow().$metric)
     }};
 }
 
+/// Returns the label under which to record a caller-controlled value, grouping new values under
+/// `OTHER_METRIC_LABEL` once `MAX_METRIC_LABELS` distinct values have been recorded.
+pub fn get_metric_label(labels: &HashMap<String, u64>, value: &str) -> String {
+    if labels.len() < MAX_METRIC_LABELS || labels.contains_key(value) {
+        value.to_string()
+    } else {
+        OTHER_METRIC_LABEL.to_string()
+    }
+}
+
+/// Returns the label of a caller-controlled JSON-RPC method name, grouping names which are not
+/// of the form `namespace_method` (or longer than `MAX_METHOD_LABEL_LENGTH`) under
+/// `OTHER_METRIC_LABEL`.
+pub fn get_method_label(labels: &HashMap<String, u64>, method: &str) -> String {
+    let is_valid = method.len() <= MAX_METHOD_LABEL_LENGTH
+        && method.split_once('_').map_or(false, |(namespace, name)| {
+            !namespace.is_empty()
+                && !name.is_empty()
+                && namespace.chars().all(|c| c.is_ascii_lowercase())
+                && name.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if is_valid {
+        get_metric_label(labels, method)
+    } else {
+        OTHER_METRIC_LABEL.to_string()
+    }
+}
+
+/// Records an authorized request in the per-method, per-caller, per-chain and per-provider metrics.
+pub fn record_request_metrics(caller: &Principal, source: &ResolvedSource, json_rpc_payload: &str) {
+    let methods = get_json_rpc_methods(json_rpc_payload);
+    METRICS.with(|m| {
+        let mut m = m.borrow_mut();
+        for method in methods {
+            let label = get_method_label(&m.method_requests, &method);
+            *m.method_requests.entry(label).or_default() += 1;
+        }
+        let label = get_metric_label(&m.caller_requests, &caller.to_text());
+        *m.caller_requests.entry(label).or_default() += 1;
+    });
+    if let ResolvedSource::Provider(provider) = source {
+        inc_metric_entry!(chain_requests, provider.chain_id);
+        inc_metric_entry!(provider_requests, provider.provider_id);
+    }
+}
+
+/// Records a rejected or failed request under the given error type.
+pub fn record_request_error(error: &str) {
+    inc_metric_entry!(request_errors, error.to_string());
+}
+
+fn get_json_rpc_methods(json_rpc_payload: &str) -> Vec<String> {
+    let method = |request: &serde_json::Value| {
+        request
+            .get("method")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+    };
+    match serde_json::from_str::<serde_json::Value>(json_rpc_payload) {
+        Ok(serde_json::Value::Array(requests)) => requests.iter().filter_map(method).collect(),
+        Ok(request) => method(&request).into_iter().collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Returns the method label of the request for latency and response size metrics.
+pub fn get_method_metric_label(json_rpc_payload: &str) -> String {
+    match get_json_rpc_methods(json_rpc_payload).as_slice() {
+        [method] => METRICS.with(|m| get_method_label(&m.borrow().method_requests, method)),
+        [] => OTHER_METRIC_LABEL.to_string(),
+        _ => BATCH_METRIC_LABEL.to_string(),
+    }
+}
+
+/// Records the latency of an outcall and, if successful, the size of the response body.
+///
+/// Hosts are labeled as in `host_requests`, so that the number of histograms stays bounded.
+pub fn record_outcall_metrics(
+    host: &str,
+    method: &str,
+    latency_ns: u64,
+    response_size: Option<u64>,
+) {
+    METRICS.with(|m| {
+        let mut m = m.borrow_mut();
+        let key = (get_metric_label(&m.host_requests, host), method.to_string());
+        m.request_latency_ms
+            .get_or_insert_with(Default::default)
+            .entry(key.clone())
+            .or_default()
+            .observe(REQUEST_LATENCY_BUCKETS_MS, latency_ns / 1_000_000);
+        if let Some(response_size) = response_size {
+            m.response_size_bytes
+                .get_or_insert_with(Default::default)
+                .entry(key)
+                .or_default()
+                .observe(RESPONSE_SIZE_BUCKETS_BYTES, response_size);
+        }
+    });
+}
+
+/// Saves the metrics to stable memory so that they are preserved across upgrades.
+///
+/// This runs in `pre_upgrade`, where trapping would prevent upgrades, so metrics which cannot be
+/// saved are reset instead.
+pub fn save_metrics() {
+    let metrics = METRICS.with(|m| m.borrow().clone());
+    if let Err(err) = STABLE_METRICS.with(|m| m.borrow_mut().set(metrics)) {
+        log!(ERROR, "Unable to save metrics: {:?}", err);
+        let _ = STABLE_METRICS.with(|m| m.borrow_mut().set(Metrics::default()));
+    }
+}
+
+pub fn restore_metrics() {
+    let metrics = STABLE_METRICS.with(|m| m.borrow().get().clone());
+    METRICS.with(|m| *m.borrow_mut() = metrics);
+}
+
+fn encode_counter_vec(
+    w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: impl IntoIterator<Item = (String, f64)>,
+) -> std::io::Result<()> {
+    let mut counter = w.counter_vec(name, help)?;
+    for (k, v) in values {
+        counter = counter.value(&[(label, &k)], v)?;
+    }
+    Ok(())
+}
+
+fn encode_histogram_vec(
+    w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>,
+    name: &str,
+    help: &str,
+    histograms: &HashMap<(String, String), Histogram>,
+    bounds: &[u64],
+    scale: f64,
+) -> std::io::Result<()> {
+    let mut builder = w.histogram_vec(name, help)?;
+    for ((host, method), histogram) in histograms {
+        if histogram.bucket_counts.len() != bounds.len() + 1 {
+            continue;
+        }
+        let buckets = bounds
+            .iter()
+            .map(|bound| *bound as f64 / scale)
+            .chain([f64::INFINITY])
+            .zip(histogram.bucket_counts.iter().map(|count| *count as f64));
+        builder = builder.histogram(
+            &[("host", host), ("method", method)],
+            buckets,
+            histogram.sum as f64 / scale,
+        )?;
+    }
+    Ok(())
+}
+
 pub fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
     w.encode_gauge(
         "canister_version",
@@ -58,20 +231,269 @@ pub fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> st
         get_metric!(request_cycles_refunded) as f64,
         "Cycles refunded by request() calls.",
     )?;
-    crate::TRANSIENT_METRICS.with(|m| {
-        m.borrow()
-            .host_requests
-            .iter()
-            .map(|(k, v)| {
-                w.counter_vec(
-                    "json_rpc_host_requests",
-                    "Number of request() calls to a service host.",
-                )
-                .and_then(|m| m.value(&[("host", k)], *v as f64))
-                .and(Ok(()))
-            })
-            .find(|e| e.is_err())
-            .unwrap_or(Ok(()))
+    w.encode_counter(
+        "request_cycles_service_fee",
+        get_metric!(request_cycles_service_fee) as f64,
+        "Cycles charged as service fees by request() calls.",
+    )?;
+    w.encode_gauge(
+        "treasury_balance",
+        get_treasury_balance() as f64,
+        "Service fees available for withdrawal.",
+    )?;
+    w.encode_counter(
+        "request_err_rate_limited",
+        get_metric!(request_err_rate_limited) as f64,
+        "Number of request() calls rejected by rate limits.",
+    )?;
+    METRICS.with(|m| {
+        let m = m.borrow();
+        encode_counter_vec(
+            w,
+            "json_rpc_host_requests",
+            "Number of request() calls to a service host.",
+            "host",
+            m.host_requests.iter().map(|(k, v)| (k.clone(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "json_rpc_method_requests",
+            "Number of JSON-RPC requests by method.",
+            "method",
+            m.method_requests
+                .iter()
+                .map(|(k, v)| (k.clone(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "json_rpc_chain_requests",
+            "Number of request() calls to registered providers by chain id.",
+            "chain_id",
+            m.chain_requests
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "json_rpc_provider_requests",
+            "Number of request() calls to a registered provider.",
+            "provider_id",
+            m.provider_requests
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "json_rpc_caller_requests",
+            "Number of request() calls by caller.",
+            "caller",
+            m.caller_requests
+                .iter()
+                .map(|(k, v)| (k.clone(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "request_errors",
+            "Number of failed request() calls by error type.",
+            "error",
+            m.request_errors.iter().map(|(k, v)| (k.clone(), *v as f64)),
+        )?;
+        encode_counter_vec(
+            w,
+            "provider_cycles_charged",
+            "Cycles charged for calls to a registered provider.",
+            "provider_id",
+            m.provider_cycles_charged
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v as f64)),
+        )?;
+        encode_histogram_vec(
+            w,
+            "json_rpc_request_latency_seconds",
+            "Latency of HTTPS outcalls by host and method.",
+            &m.request_latency_ms.clone().unwrap_or_default(),
+            REQUEST_LATENCY_BUCKETS_MS,
+            1000.0,
+        )?;
+        encode_histogram_vec(
+            w,
+            "json_rpc_response_size_bytes",
+            "Size of HTTPS outcall response bodies by host and method.",
+            &m.response_size_bytes.clone().unwrap_or_default(),
+            RESPONSE_SIZE_BUCKETS_BYTES,
+            1.0,
+        )
     })?;
     Ok(())
 }
+
+#[test]
+fn test_request_metrics() {
+    use cketh_common::eth_rpc_client::providers::RpcApi;
+
+    let caller = Principal::from_slice(&[1]);
+    let source = ResolvedSource::Api(RpcApi {
+        url: "https://cloudflare-eth.com".to_string(),
+        headers: vec![],
+    });
+    record_request_metrics(
+        &caller,
+        &source,
+        r#"[{"jsonrpc":"2.0","method":"eth_gasPrice","id":1},{"jsonrpc":"2.0","method":"eth_blockNumber","id":2}]"#,
+    );
+    record_request_metrics(
+        &caller,
+        &source,
+        r#"{"jsonrpc":"2.0","method":"eth_gasPrice","id":1}"#,
+    );
+    record_request_error("http");
+    add_metric_entry!(provider_cycles_charged, 3u64, 500u128);
+    add_metric_entry!(provider_cycles_charged, 3u64, 100u128);
+
+    let metrics = METRICS.with(|m| m.borrow().clone());
+    assert_eq!(metrics.method_requests.get("eth_gasPrice"), Some(&2));
+    assert_eq!(metrics.method_requests.get("eth_blockNumber"), Some(&1));
+    assert_eq!(metrics.caller_requests.get(&caller.to_text()), Some(&2));
+    assert!(metrics.provider_requests.is_empty());
+    assert_eq!(metrics.request_errors.get("http"), Some(&1));
+    assert_eq!(metrics.provider_cycles_charged.get(&3), Some(&600));
+
+    // Metrics survive a round trip through stable memory
+    save_metrics();
+    METRICS.with(|m| *m.borrow_mut() = Metrics::default());
+    restore_metrics();
+    assert_eq!(METRICS.with(|m| m.borrow().clone()), metrics);
+}
+
+#[test]
+fn test_restore_previous_metrics() {
+    use candid::{CandidType, Encode};
+    use ic_stable_structures::Storable;
+
+    /// `Metrics` as saved by the first version which kept them in stable memory.
+    #[derive(CandidType)]
+    struct PreviousMetrics {
+        requests: u64,
+        request_cycles_charged: u128,
+        request_cycles_refunded: u128,
+        request_cycles_service_fee: u128,
+        request_err_no_permission: u64,
+        request_err_host_not_allowed: u64,
+        request_err_http: u64,
+        request_err_rate_limited: u64,
+        host_requests: HashMap<String, u64>,
+        method_requests: HashMap<String, u64>,
+        chain_requests: HashMap<u64, u64>,
+        provider_requests: HashMap<u64, u64>,
+        caller_requests: HashMap<String, u64>,
+        request_errors: HashMap<String, u64>,
+        provider_cycles_charged: HashMap<u64, u128>,
+    }
+
+    let previous = PreviousMetrics {
+        requests: 5,
+        request_cycles_charged: 1000,
+        request_cycles_refunded: 10,
+        request_cycles_service_fee: 1,
+        request_err_no_permission: 1,
+        request_err_host_not_allowed: 0,
+        request_err_http: 2,
+        request_err_rate_limited: 0,
+        host_requests: HashMap::from([("example.com".to_string(), 5)]),
+        method_requests: HashMap::from([("eth_gasPrice".to_string(), 5)]),
+        chain_requests: HashMap::from([(1, 5)]),
+        provider_requests: HashMap::from([(0, 5)]),
+        caller_requests: HashMap::new(),
+        request_errors: HashMap::from([("http".to_string(), 2)]),
+        provider_cycles_charged: HashMap::from([(0, 500)]),
+    };
+    let metrics = Metrics::from_bytes(Encode!(&previous).unwrap().into());
+    assert_eq!(metrics.requests, 5);
+    assert_eq!(metrics.provider_cycles_charged.get(&0), Some(&500));
+    assert_eq!(metrics.request_latency_ms, None);
+    assert_eq!(metrics.response_size_bytes, None);
+
+    // Undecodable metrics are reset
+    assert_eq!(
+        Metrics::from_bytes(b"invalid".to_vec().into()),
+        Metrics::default()
+    );
+}
+
+#[test]
+fn test_outcall_metrics() {
+    assert_eq!(
+        get_method_metric_label(r#"{"jsonrpc":"2.0","method":"eth_gasPrice","id":1}"#),
+        "eth_gasPrice"
+    );
+    assert_eq!(
+        get_method_metric_label(r#"[{"method":"eth_gasPrice"},{"method":"eth_chainId"}]"#),
+        BATCH_METRIC_LABEL
+    );
+    assert_eq!(get_method_metric_label("{"), OTHER_METRIC_LABEL);
+
+    record_outcall_metrics("example.com", "eth_gasPrice", 1_500_000_000, Some(100));
+    record_outcall_metrics("example.com", "eth_gasPrice", 90_000_000_000, None);
+    let key = ("example.com".to_string(), "eth_gasPrice".to_string());
+    let metrics = METRICS.with(|m| m.borrow().clone());
+    assert_eq!(
+        metrics.request_latency_ms.as_ref().unwrap()[&key],
+        Histogram {
+            bucket_counts: vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 1],
+            sum: 91_500,
+        }
+    );
+    assert_eq!(
+        metrics.response_size_bytes.as_ref().unwrap()[&key],
+        Histogram {
+            bucket_counts: vec![1, 0, 0, 0, 0, 0, 0, 0, 0],
+            sum: 100,
+        }
+    );
+
+    // Hosts beyond the label limit share a histogram
+    METRICS.with(|m| {
+        m.borrow_mut().host_requests = (0..MAX_METRIC_LABELS)
+            .map(|i| (format!("{}.example.com", i), 1))
+            .collect()
+    });
+    record_outcall_metrics("new.example.com", "eth_gasPrice", 0, Some(1));
+    let key = (OTHER_METRIC_LABEL.to_string(), "eth_gasPrice".to_string());
+    let metrics = METRICS.with(|m| m.borrow().clone());
+    assert_eq!(metrics.response_size_bytes.as_ref().unwrap()[&key].sum, 1);
+
+    let mut histogram = Histogram::default();
+    histogram.observe(&[10, 20], 10);
+    histogram.observe(&[10, 20], 11);
+    histogram.observe(&[10, 20], 21);
+    assert_eq!(histogram.bucket_counts, vec![1, 1, 1]);
+    histogram.observe(&[10], 5);
+    assert_eq!(histogram.bucket_counts, vec![1, 0]);
+}
+
+#[test]
+fn test_metric_label() {
+    let mut labels = HashMap::new();
+    for i in 0..MAX_METRIC_LABELS {
+        labels.insert(i.to_string(), 1);
+    }
+    assert_eq!(get_metric_label(&labels, "0"), "0");
+    assert_eq!(get_metric_label(&labels, "new"), OTHER_METRIC_LABEL);
+
+    let labels = HashMap::new();
+    assert_eq!(get_method_label(&labels, "eth_getLogs"), "eth_getLogs");
+    assert_eq!(get_method_label(&labels, "net_version"), "net_version");
+    let long_method = format!("eth_{}", "a".repeat(MAX_METHOD_LABEL_LENGTH));
+    for method in [
+        "",
+        "eth",
+        "_getLogs",
+        "eth_",
+        "eth_get-logs",
+        "eth_getLogs\"}",
+        "ETH_getLogs",
+        long_method.as_str(),
+    ] {
+        assert_eq!(get_method_label(&labels, method), OTHER_METRIC_LABEL);
+    }
+}
@@ -0,0 +1,215 @@
+use ic_canister_log::log;
+#[cfg(test)]
+use ic_cdk::api::management_canister::http_request::HttpHeader;
+#[cfg(test)]
+use ic_stable_structures::Storable;
+
+use crate::*;
+
+/// `MIGRATIONS[i]` upgrades the stable state from schema version `i` to `i + 1`.
+///
+/// Version 0 is the state written by releases predating schema versioning.
+///
+/// Values stored as Candid (such as `Metadata`, `Provider` and the saved `Metrics`) are not
+/// migrated here: fields may only be added to them as `Option`s, so that values written by earlier
+/// releases still decode. `test_decode_previous_values` and `test_restore_previous_metrics` check
+/// this against the types of earlier releases.
+const MIGRATIONS: &[fn()] = &[migrate_v0_to_v1, migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// Version of the stable state written by this release.
+pub const STABLE_SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Moves legacy authorization sets and plaintext credentials into their current format.
+fn migrate_v0_to_v1() {
+    migrate_auth_sets();
+    migrate_legacy_credentials();
+}
+
+/// Moves providers out of the map limited to `LEGACY_PROVIDER_STORABLE_MAX_SIZE` bytes per entry,
+/// keeping their IDs.
+fn migrate_v1_to_v2() {
+    let legacy_providers: Vec<(u64, LegacyProvider)> =
+        LEGACY_PROVIDERS.with(|p| p.borrow().iter().collect());
+    PROVIDERS.with(|p| {
+        let mut p = p.borrow_mut();
+        for (id, LegacyProvider(provider)) in &legacy_providers {
+            p.insert(*id, provider.clone());
+        }
+    });
+    LEGACY_PROVIDERS.with(|p| {
+        let mut p = p.borrow_mut();
+        for (id, _) in legacy_providers {
+            p.remove(&id);
+        }
+    });
+    // Credentials of releases predating schema versioning are only reachable once the providers
+    // have been moved.
+    migrate_legacy_credentials();
+}
+
+/// Moves the compiled-in host allowlist into stable memory.
+fn migrate_v2_to_v3() {
+    init_allowed_hosts();
+}
+
+pub fn get_schema_version() -> u32 {
+    SCHEMA_VERSION.with(|v| *v.borrow().get())
+}
+
+/// Marks freshly initialized stable state as using the current schema.
+pub fn init_schema_version() {
+    set_schema_version(STABLE_SCHEMA_VERSION);
+}
+
+fn set_schema_version(version: u32) {
+    SCHEMA_VERSION.with(|v| v.borrow_mut().set(version).unwrap());
+}
+
+/// Runs the migrations from the stored schema version to the current one.
+pub fn run_migrations() {
+    let version = get_schema_version();
+    if version > STABLE_SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "Cannot downgrade stable schema from version {} to {}",
+            version, STABLE_SCHEMA_VERSION
+        ));
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        log!(
+            INFO,
+            "Migrating stable schema from version {} to {}",
+            from,
+            from + 1
+        );
+        migration();
+        set_schema_version(from as u32 + 1);
+    }
+}
+
+#[test]
+fn test_run_migrations() {
+    let principal = Principal::from_slice(&[1]);
+    do_authorize(principal, Auth::Rpc);
+
+    assert_eq!(get_schema_version(), 0);
+    run_migrations();
+    assert_eq!(get_schema_version(), STABLE_SCHEMA_VERSION);
+    assert!(is_authorized(&principal, Auth::Rpc));
+
+    // Migrations are only run once
+    run_migrations();
+    assert_eq!(get_schema_version(), STABLE_SCHEMA_VERSION);
+}
+
+#[test]
+fn test_provider_migration() {
+    let provider = Provider {
+        provider_id: 3,
+        owner: Principal::from_slice(&[1]),
+        chain_id: 1,
+        hostname: "cloudflare-eth.com".to_string(),
+        credential_path: String::new(),
+        credential_headers: vec![],
+        cycles_per_call: 1000,
+        cycles_per_message_byte: 10,
+        cycles_owed: 5,
+        primary: true,
+        capabilities: Some(ProviderCapabilities {
+            namespaces: vec![RpcNamespace::Eth, RpcNamespace::Debug],
+            archive: true,
+            max_logs_block_range: Some(10_000),
+            max_batch_size: Some(100),
+        }),
+    };
+    LEGACY_PROVIDERS.with(|p| p.borrow_mut().insert(3, LegacyProvider(provider.clone())));
+    set_schema_version(1);
+    run_migrations();
+    assert_eq!(get_schema_version(), STABLE_SCHEMA_VERSION);
+    assert!(LEGACY_PROVIDERS.with(|p| p.borrow().is_empty()));
+    let migrated = PROVIDERS.with(|p| p.borrow().get(&3)).unwrap();
+    assert_eq!(migrated.to_bytes(), provider.to_bytes());
+
+    // Providers are no longer limited to the legacy entry size
+    let large_provider = Provider {
+        provider_id: 4,
+        capabilities: Some(ProviderCapabilities {
+            namespaces: vec![RpcNamespace::Eth, RpcNamespace::Debug, RpcNamespace::Trace],
+            archive: true,
+            max_logs_block_range: Some(u64::MAX),
+            max_batch_size: Some(u32::MAX),
+        }),
+        credential_headers: vec![HttpHeader {
+            name: "Authorization".to_string(),
+            value: "x".repeat(LEGACY_PROVIDER_STORABLE_MAX_SIZE as usize),
+        }],
+        ..provider
+    };
+    assert!(large_provider.to_bytes().len() > LEGACY_PROVIDER_STORABLE_MAX_SIZE as usize);
+    PROVIDERS.with(|p| p.borrow_mut().insert(4, large_provider.clone()));
+    let stored = PROVIDERS.with(|p| p.borrow().get(&4)).unwrap();
+    assert_eq!(stored.to_bytes(), large_provider.to_bytes());
+}
+
+#[test]
+fn test_decode_previous_values() {
+    use candid::{CandidType, Encode};
+
+    // `Metadata` and `Provider` as written by releases predating schema versioning
+    #[derive(CandidType)]
+    struct PreviousMetadata {
+        nodes_in_subnet: u32,
+        next_provider_id: u64,
+        open_rpc_access: bool,
+    }
+    #[derive(CandidType)]
+    struct PreviousProvider {
+        provider_id: u64,
+        owner: Principal,
+        chain_id: u64,
+        hostname: String,
+        credential_path: String,
+        credential_headers: Vec<HttpHeader>,
+        cycles_per_call: u64,
+        cycles_per_message_byte: u64,
+        cycles_owed: u128,
+        primary: bool,
+    }
+
+    let metadata = Metadata::from_bytes(
+        Encode!(&PreviousMetadata {
+            nodes_in_subnet: 34,
+            next_provider_id: 5,
+            open_rpc_access: true,
+        })
+        .unwrap()
+        .into(),
+    );
+    assert_eq!(metadata.nodes_in_subnet, 34);
+    assert_eq!(metadata.next_provider_id, 5);
+    assert!(metadata.open_rpc_access);
+    assert_eq!(metadata.rate_limits, None);
+    assert_eq!(metadata.pricing, None);
+    assert_eq!(metadata.service_fees, None);
+
+    let provider = Provider::from_bytes(
+        Encode!(&PreviousProvider {
+            provider_id: 3,
+            owner: Principal::from_slice(&[1]),
+            chain_id: 1,
+            hostname: "cloudflare-eth.com".to_string(),
+            credential_path: "/v1/mainnet".to_string(),
+            credential_headers: vec![],
+            cycles_per_call: 1000,
+            cycles_per_message_byte: 10,
+            cycles_owed: 5,
+            primary: true,
+        })
+        .unwrap()
+        .into(),
+    );
+    assert_eq!(provider.provider_id, 3);
+    assert_eq!(provider.hostname, "cloudflare-eth.com");
+    assert_eq!(provider.credential_path, "/v1/mainnet");
+    assert_eq!(provider.cycles_owed, 5);
+    assert_eq!(provider.capabilities, None);
+}
@@ -0,0 +1,369 @@
+use candid::Principal;
+use ic_canister_log::log;
+
+use crate::*;
+
+fn record_payout_entry(provider_id: u64, kind: PayoutEntryKind, amount: u128, cycles_owed: u128) {
+    PAYOUT_LEDGER.with(|l| {
+        let mut ledger = l.borrow_mut();
+        let entry = PayoutEntry {
+            id: ledger.len(),
+            timestamp: get_time(),
+            provider_id,
+            kind,
+            amount,
+            cycles_owed,
+        };
+        ledger
+            .append(&entry)
+            .expect("Unable to append payout entry")
+    });
+}
+
+/// Credits cycles to the provider, or to its escrow if it has been unregistered.
+fn credit_owed_cycles(provider_id: u64, owner: Principal, amount: u128, kind: PayoutEntryKind) {
+    let cycles_owed = PROVIDERS.with(|p| {
+        let mut p = p.borrow_mut();
+        let mut provider = p.get(&provider_id)?;
+        provider.cycles_owed += amount;
+        let cycles_owed = provider.cycles_owed;
+        p.insert(provider_id, provider);
+        Some(cycles_owed)
+    });
+    let cycles_owed = cycles_owed.unwrap_or_else(|| {
+        ESCROW.with(|e| {
+            let mut e = e.borrow_mut();
+            let mut escrow = e.get(&provider_id).unwrap_or(Escrow {
+                owner,
+                cycles_owed: 0,
+            });
+            escrow.cycles_owed += amount;
+            let cycles_owed = escrow.cycles_owed;
+            e.insert(provider_id, escrow);
+            cycles_owed
+        })
+    });
+    record_payout_entry(provider_id, kind, amount, cycles_owed);
+}
+
+/// Credits the provider for a request it served.
+pub fn accrue_provider_cycles(provider: &Provider, amount: u128) {
+    if amount > 0 {
+        credit_owed_cycles(
+            provider.provider_id,
+            provider.owner,
+            amount,
+            PayoutEntryKind::Accrual,
+        );
+    }
+}
+
+/// Moves the cycles owed to an unregistered provider into escrow.
+pub fn move_to_escrow(provider: &Provider) {
+    if provider.cycles_owed > 0 {
+        credit_owed_cycles(
+            provider.provider_id,
+            provider.owner,
+            provider.cycles_owed,
+            PayoutEntryKind::Escrow,
+        );
+    }
+}
+
+pub fn get_escrow(provider_id: u64) -> Option<Escrow> {
+    ESCROW.with(|e| e.borrow().get(&provider_id))
+}
+
+/// Zeroes the cycles owed to the provider (or held in escrow), returning the amount.
+fn take_owed_cycles(caller: &Principal, provider_id: u64, canister_id: Principal) -> u128 {
+    let kind = PayoutEntryKind::Withdrawal { canister_id };
+    if let Some(mut provider) = PROVIDERS.with(|p| p.borrow().get(&provider_id)) {
+        if *caller != provider.owner {
+            ic_cdk::trap("Not owner");
+        }
+        let amount = provider.cycles_owed;
+        if amount < MINIMUM_WITHDRAWAL_CYCLES {
+            ic_cdk::trap("Too few cycles to withdraw");
+        }
+        provider.cycles_owed = 0;
+        PROVIDERS.with(|p| p.borrow_mut().insert(provider_id, provider));
+        record_payout_entry(provider_id, kind, amount, 0);
+        return amount;
+    }
+    let escrow = get_escrow(provider_id).unwrap_or_else(|| ic_cdk::trap("Provider not found"));
+    if *caller != escrow.owner {
+        ic_cdk::trap("Not owner");
+    }
+    let amount = escrow.cycles_owed;
+    if amount < MINIMUM_WITHDRAWAL_CYCLES {
+        ic_cdk::trap("Too few cycles to withdraw");
+    }
+    ESCROW.with(|e| e.borrow_mut().remove(&provider_id));
+    record_payout_entry(provider_id, kind, amount, 0);
+    amount
+}
+
+pub async fn do_withdraw_accumulated_cycles(
+    caller: Principal,
+    provider_id: u64,
+    canister_id: Principal,
+) {
+    let amount = take_owed_cycles(&caller, provider_id, canister_id);
+    match ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "deposit_cycles",
+        (DepositCyclesArgs { canister_id },),
+        amount,
+    )
+    .await
+    {
+        Ok(()) => (),
+        e => {
+            // Refund on failure to send cycles (without trapping, which would revert the refund).
+            log!(
+                INFO,
+                "Unable to send {} cycles to {} for provider {}: {:?}",
+                amount,
+                canister_id,
+                provider_id,
+                e
+            );
+            credit_owed_cycles(
+                provider_id,
+                caller,
+                amount,
+                PayoutEntryKind::WithdrawalRefund { canister_id },
+            );
+        }
+    };
+}
+
+/// Returns `true` if the caller may view the payout history of the given provider.
+pub fn can_view_payouts(caller: &Principal, provider_id: u64) -> bool {
+    if is_admin_or_controller(caller) {
+        return true;
+    }
+    match PROVIDERS.with(|p| p.borrow().get(&provider_id)) {
+        Some(provider) => can_manage_provider(caller, &provider),
+        None => get_escrow(provider_id).map_or(false, |e| e.owner == *caller),
+    }
+}
+
+/// Returns up to `limit` entries from `start`, optionally restricted to one provider.
+pub fn do_get_payout_history(provider_id: Option<u64>, start: u64, limit: u64) -> PayoutHistory {
+    let limit = limit.min(MAX_PAYOUT_HISTORY_PAGE_SIZE) as usize;
+    PAYOUT_LEDGER.with(|l| {
+        let ledger = l.borrow();
+        let end = ledger
+            .len()
+            .min(start.saturating_add(MAX_PAYOUT_HISTORY_SCAN));
+        let mut entries = vec![];
+        for index in start..end {
+            if entries.len() >= limit {
+                return PayoutHistory {
+                    entries,
+                    next_start: Some(index),
+                };
+            }
+            if let Some(entry) = ledger.get(index) {
+                if provider_id.map_or(true, |id| id == entry.provider_id) {
+                    entries.push(entry);
+                }
+            }
+        }
+        PayoutHistory {
+            entries,
+            next_start: if end < ledger.len() { Some(end) } else { None },
+        }
+    })
+}
+
+/// Compares the cycles owed to providers, prepaid callers and the treasury against the canister
+/// balance.
+pub fn do_reconcile_cycles(canister_balance: u128) -> CyclesReconciliation {
+    let providers_owed = PROVIDERS.with(|p| p.borrow().iter().map(|(_, p)| p.cycles_owed).sum());
+    let escrow_owed = ESCROW.with(|e| e.borrow().iter().map(|(_, e)| e.cycles_owed).sum());
+    let prepaid_balances = BALANCES.with(|b| b.borrow().iter().map(|(_, b)| b.0).sum());
+    let treasury_balance = get_treasury_balance();
+    let total_owed: u128 = providers_owed + escrow_owed + prepaid_balances + treasury_balance;
+    CyclesReconciliation {
+        canister_balance,
+        providers_owed,
+        escrow_owed,
+        prepaid_balances,
+        treasury_balance,
+        total_owed,
+        is_solvent: canister_balance >= total_owed,
+    }
+}
+
+#[cfg(test)]
+fn test_provider(provider_id: u64, owner: Principal) -> Provider {
+    Provider {
+        provider_id,
+        owner,
+        chain_id: 1,
+        hostname: "example.com".to_string(),
+        credential_path: String::new(),
+        credential_headers: vec![],
+        cycles_per_call: 0,
+        cycles_per_message_byte: 0,
+        cycles_owed: 0,
+        primary: false,
+        capabilities: None,
+    }
+}
+
+#[test]
+fn test_payout_ledger() {
+    let owner = Principal::from_slice(&[1]);
+    let canister = Principal::from_slice(&[2]);
+    let provider = test_provider(100, owner);
+    PROVIDERS.with(|p| p.borrow_mut().insert(100, provider.clone()));
+    let other = test_provider(101, owner);
+    PROVIDERS.with(|p| p.borrow_mut().insert(101, other.clone()));
+
+    accrue_provider_cycles(&provider, 0);
+    accrue_provider_cycles(&provider, 3 * MINIMUM_WITHDRAWAL_CYCLES);
+    accrue_provider_cycles(&other, 5);
+    assert_eq!(
+        take_owed_cycles(&owner, 100, canister),
+        3 * MINIMUM_WITHDRAWAL_CYCLES
+    );
+    credit_owed_cycles(
+        100,
+        owner,
+        3 * MINIMUM_WITHDRAWAL_CYCLES,
+        PayoutEntryKind::WithdrawalRefund {
+            canister_id: canister,
+        },
+    );
+    assert_eq!(
+        PROVIDERS.with(|p| p.borrow().get(&100).unwrap().cycles_owed),
+        3 * MINIMUM_WITHDRAWAL_CYCLES
+    );
+
+    let history = do_get_payout_history(Some(100), 0, 10);
+    assert_eq!(history.next_start, None);
+    assert_eq!(
+        history
+            .entries
+            .iter()
+            .map(|e| (e.kind.clone(), e.amount, e.cycles_owed))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                PayoutEntryKind::Accrual,
+                3 * MINIMUM_WITHDRAWAL_CYCLES,
+                3 * MINIMUM_WITHDRAWAL_CYCLES
+            ),
+            (
+                PayoutEntryKind::Withdrawal {
+                    canister_id: canister
+                },
+                3 * MINIMUM_WITHDRAWAL_CYCLES,
+                0
+            ),
+            (
+                PayoutEntryKind::WithdrawalRefund {
+                    canister_id: canister
+                },
+                3 * MINIMUM_WITHDRAWAL_CYCLES,
+                3 * MINIMUM_WITHDRAWAL_CYCLES
+            ),
+        ]
+    );
+    assert_eq!(
+        history.entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+        vec![0, 2, 3]
+    );
+
+    // Pagination
+    let page = do_get_payout_history(None, 0, 2);
+    assert_eq!(page.entries.len(), 2);
+    assert_eq!(page.next_start, Some(2));
+    let page = do_get_payout_history(None, 2, 2);
+    assert_eq!(page.entries.len(), 2);
+    assert_eq!(page.next_start, None);
+    assert!(do_get_payout_history(Some(101), 1, 10).entries.len() == 1);
+}
+
+#[test]
+fn test_payout_escrow() {
+    let owner = Principal::from_slice(&[1]);
+    let canister = Principal::from_slice(&[2]);
+    let mut provider = test_provider(200, owner);
+    provider.cycles_owed = MINIMUM_WITHDRAWAL_CYCLES;
+    PROVIDERS.with(|p| p.borrow_mut().insert(200, provider.clone()));
+
+    assert_eq!(
+        do_reconcile_cycles(MINIMUM_WITHDRAWAL_CYCLES),
+        CyclesReconciliation {
+            canister_balance: MINIMUM_WITHDRAWAL_CYCLES,
+            providers_owed: MINIMUM_WITHDRAWAL_CYCLES,
+            escrow_owed: 0,
+            prepaid_balances: 0,
+            treasury_balance: 0,
+            total_owed: MINIMUM_WITHDRAWAL_CYCLES,
+            is_solvent: true,
+        }
+    );
+    credit_treasury(1);
+    let reconciliation = do_reconcile_cycles(MINIMUM_WITHDRAWAL_CYCLES);
+    assert_eq!(reconciliation.treasury_balance, 1);
+    assert_eq!(reconciliation.total_owed, MINIMUM_WITHDRAWAL_CYCLES + 1);
+    assert!(!reconciliation.is_solvent);
+    debit_treasury(1).unwrap();
+
+    // Unregistered providers' cycles are held in escrow
+    PROVIDERS.with(|p| p.borrow_mut().remove(&200));
+    move_to_escrow(&provider);
+    assert_eq!(
+        get_escrow(200),
+        Some(Escrow {
+            owner,
+            cycles_owed: MINIMUM_WITHDRAWAL_CYCLES
+        })
+    );
+    let reconciliation = do_reconcile_cycles(MINIMUM_WITHDRAWAL_CYCLES - 1);
+    assert_eq!(reconciliation.providers_owed, 0);
+    assert_eq!(reconciliation.escrow_owed, MINIMUM_WITHDRAWAL_CYCLES);
+    assert!(!reconciliation.is_solvent);
+
+    assert_eq!(
+        take_owed_cycles(&owner, 200, canister),
+        MINIMUM_WITHDRAWAL_CYCLES
+    );
+    assert_eq!(get_escrow(200), None);
+
+    // Failed withdrawals are refunded to escrow
+    credit_owed_cycles(
+        200,
+        owner,
+        MINIMUM_WITHDRAWAL_CYCLES,
+        PayoutEntryKind::WithdrawalRefund {
+            canister_id: canister,
+        },
+    );
+    assert_eq!(
+        get_escrow(200).map(|e| e.cycles_owed),
+        Some(MINIMUM_WITHDRAWAL_CYCLES)
+    );
+    let kinds = do_get_payout_history(Some(200), 0, 10)
+        .entries
+        .into_iter()
+        .map(|e| e.kind)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            PayoutEntryKind::Escrow,
+            PayoutEntryKind::Withdrawal {
+                canister_id: canister
+            },
+            PayoutEntryKind::WithdrawalRefund {
+                canister_id: canister
+            },
+        ]
+    );
+}
@@ -1,3 +1,5 @@
+use ic_stable_structures::Storable;
+
 use crate::*;
 
 pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
@@ -9,6 +11,7 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             credential_headers: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            capabilities: None,
         },
         RegisterProviderArgs {
             chain_id: ETH_MAINNET_CHAIN_ID,
@@ -17,6 +20,7 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             credential_headers: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            capabilities: None,
         },
         RegisterProviderArgs {
             chain_id: ETH_MAINNET_CHAIN_ID,
@@ -25,6 +29,7 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             credential_headers: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            capabilities: None,
         },
         RegisterProviderArgs {
             chain_id: ETH_SEPOLIA_CHAIN_ID,
@@ -33,6 +38,7 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             credential_headers: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            capabilities: None,
         },
         RegisterProviderArgs {
             chain_id: ETH_SEPOLIA_CHAIN_ID,
@@ -41,13 +47,57 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             credential_headers: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            capabilities: None,
         },
     ]
 }
 
+/// Traps unless the caller may register providers for the given chain and hostname.
+pub fn require_register_provider_scope(caller: &Principal, chain_id: u64, hostname: &str) {
+    let target = AuthTarget {
+        chain_id: Some(chain_id),
+        hostname: Some(hostname.to_string()),
+        provider_id: None,
+    };
+    if !is_authorized_for(caller, Auth::RegisterProvider, &target) {
+        ic_cdk::trap("Not authorized for this chain or hostname");
+    }
+}
+
+/// Returns `true` if the caller owns the provider or has been delegated its management.
+pub fn can_manage_provider(caller: &Principal, provider: &Provider) -> bool {
+    provider.owner == *caller
+        || is_authorized(caller, Auth::ManageService)
+        || is_authorized_for(
+            caller,
+            Auth::ManageProvider,
+            &AuthTarget::provider(provider),
+        )
+}
+
 pub fn do_register_provider(caller: Principal, provider: RegisterProviderArgs) -> u64 {
-    validate_hostname(&provider.hostname).unwrap();
-    validate_credential_path(&provider.credential_path).unwrap();
+    register_provider(caller, provider, true)
+}
+
+/// Registers a provider given in the init arguments.
+///
+/// The vault key is only generated after `init`, so the credentials stay in the provider entry
+/// until `migrate_legacy_credentials` moves them into the vault.
+pub fn do_register_initial_provider(caller: Principal, provider: RegisterProviderArgs) -> u64 {
+    register_provider(caller, provider, false)
+}
+
+fn register_provider(caller: Principal, provider: RegisterProviderArgs, seal: bool) -> u64 {
+    validate_hostname(&provider.hostname, Some(provider.chain_id)).unwrap();
+    validate_credential_path(
+        &provider.hostname,
+        provider.chain_id,
+        &provider.credential_path,
+    )
+    .unwrap();
+    if let Some(headers) = &provider.credential_headers {
+        validate_credential_headers(headers).unwrap();
+    }
     let provider_id = METADATA.with(|m| {
         let mut metadata = m.borrow().get().clone();
         let id = metadata.next_provider_id;
@@ -55,39 +105,114 @@ pub fn do_register_provider(caller: Principal, provider: RegisterProviderArgs) -
         m.borrow_mut().set(metadata).unwrap();
         id
     });
-    PROVIDERS.with(|p| {
-        p.borrow_mut().insert(
+    let mut credential_path = provider.credential_path;
+    let mut credential_headers = provider.credential_headers.unwrap_or_default();
+    if seal {
+        store_credential_path(
+            &CanisterKeySource,
             provider_id,
-            Provider {
-                provider_id,
-                owner: caller,
-                chain_id: provider.chain_id,
-                hostname: provider.hostname,
-                credential_path: provider.credential_path,
-                credential_headers: provider.credential_headers.unwrap_or_default(),
-                cycles_per_call: provider.cycles_per_call,
-                cycles_per_message_byte: provider.cycles_per_message_byte,
-                cycles_owed: 0,
-                primary: false,
-            },
+            std::mem::take(&mut credential_path),
         )
-    });
+        .and_then(|_| {
+            store_credential_headers(
+                &CanisterKeySource,
+                provider_id,
+                std::mem::take(&mut credential_headers),
+            )
+        })
+        .unwrap_or_else(|err| ic_cdk::trap(&err));
+    }
+    let provider = Provider {
+        provider_id,
+        owner: caller,
+        chain_id: provider.chain_id,
+        hostname: provider.hostname,
+        credential_path,
+        credential_headers,
+        cycles_per_call: provider.cycles_per_call,
+        cycles_per_message_byte: provider.cycles_per_message_byte,
+        cycles_owed: 0,
+        primary: false,
+        capabilities: provider.capabilities,
+    };
+    validate_provider_size(&provider);
+    PROVIDERS.with(|p| p.borrow_mut().insert(provider_id, provider));
     provider_id
 }
 
+/// Traps if the provider does not fit into a `PROVIDERS` entry.
+fn validate_provider_size(provider: &Provider) {
+    if provider.to_bytes().len() > PROVIDER_STORABLE_MAX_SIZE as usize {
+        ic_cdk::trap("Provider exceeds maximum size");
+    }
+}
+
+pub fn do_get_provider_details(caller: Principal, provider_id: u64) -> ProviderDetails {
+    let provider = PROVIDERS
+        .with(|p| p.borrow().get(&provider_id))
+        .unwrap_or_else(|| ic_cdk::trap("Provider not found"));
+    if !can_manage_provider(&caller, &provider) && !is_admin_or_controller(&caller) {
+        ic_cdk::trap("Not authorized");
+    }
+    ProviderDetails {
+        cycles_owed: provider.cycles_owed,
+        credentials: get_credential_details(&provider),
+        provider: provider.into(),
+    }
+}
+
+/// Traps unless the caller is an admin or the provider's owner, with a `RegisterProvider` grant
+/// still covering the provider's chain and hostname.
+fn require_provider_owner(caller: &Principal, provider_id: u64) {
+    if is_authorized(caller, Auth::ManageService) {
+        return;
+    }
+    let provider = PROVIDERS
+        .with(|p| p.borrow().get(&provider_id))
+        .unwrap_or_else(|| ic_cdk::trap("Provider not found"));
+    if provider.owner != *caller {
+        ic_cdk::trap("Provider owner != caller");
+    }
+    require_register_provider_scope(caller, provider.chain_id, &provider.hostname);
+}
+
+pub fn do_add_provider_manager(caller: Principal, provider_id: u64, manager: Principal) {
+    if !PROVIDERS.with(|p| p.borrow().contains_key(&provider_id)) {
+        ic_cdk::trap("Provider not found");
+    }
+    require_provider_owner(&caller, provider_id);
+    do_authorize_provider_manager(manager, provider_id);
+}
+
+pub fn do_remove_provider_manager(caller: Principal, provider_id: u64, manager: Principal) {
+    // Managers may also remove themselves, including from providers which no longer exist.
+    if caller != manager {
+        require_provider_owner(&caller, provider_id);
+    }
+    do_deauthorize_provider_manager(manager, provider_id);
+}
+
 pub fn do_unregister_provider(caller: Principal, provider_id: u64) -> bool {
-    PROVIDERS.with(|p| {
+    let removed = PROVIDERS.with(|p| {
         let mut p = p.borrow_mut();
         if let Some(provider) = p.get(&provider_id) {
             if provider.owner == caller || is_authorized(&caller, Auth::ManageService) {
-                p.remove(&provider_id).is_some()
+                remove_credentials(provider_id);
+                p.remove(&provider_id)
             } else {
                 ic_cdk::trap("Not authorized");
             }
         } else {
-            false
+            None
         }
-    })
+    });
+    match removed {
+        Some(provider) => {
+            move_to_escrow(&provider);
+            true
+        }
+        None => false,
+    }
 }
 
 pub fn do_update_provider(caller: Principal, update: UpdateProviderArgs) {
@@ -95,20 +220,27 @@ pub fn do_update_provider(caller: Principal, update: UpdateProviderArgs) {
         let mut p = p.borrow_mut();
         match p.get(&update.provider_id) {
             Some(mut provider) => {
-                if provider.owner != caller && !is_authorized(&caller, Auth::ManageService) {
+                if !can_manage_provider(&caller, &provider) {
                     ic_cdk::trap("Provider owner != caller");
                 }
                 if let Some(hostname) = update.hostname {
-                    validate_hostname(&hostname).unwrap();
+                    validate_hostname(&hostname, Some(provider.chain_id)).unwrap();
+                    if !is_authorized(&caller, Auth::ManageService) {
+                        require_register_provider_scope(&caller, provider.chain_id, &hostname);
+                    }
                     provider.hostname = hostname;
                 }
                 if let Some(path) = update.credential_path {
-                    validate_credential_path(&path).unwrap();
-                    provider.credential_path = path;
+                    validate_credential_path(&provider.hostname, provider.chain_id, &path).unwrap();
+                    store_credential_path(&CanisterKeySource, update.provider_id, path)
+                        .unwrap_or_else(|err| ic_cdk::trap(&err));
+                    provider.credential_path = String::new();
                 }
                 if let Some(headers) = update.credential_headers {
                     validate_credential_headers(&headers).unwrap();
-                    provider.credential_headers = headers;
+                    store_credential_headers(&CanisterKeySource, update.provider_id, headers)
+                        .unwrap_or_else(|err| ic_cdk::trap(&err));
+                    provider.credential_headers = vec![];
                 }
                 if let Some(primary) = update.primary {
                     provider.primary = primary;
@@ -119,6 +251,10 @@ pub fn do_update_provider(caller: Principal, update: UpdateProviderArgs) {
                 if let Some(cycles_per_message_byte) = update.cycles_per_message_byte {
                     provider.cycles_per_message_byte = cycles_per_message_byte;
                 }
+                if let Some(capabilities) = update.capabilities {
+                    provider.capabilities = Some(capabilities);
+                }
+                validate_provider_size(&provider);
                 p.insert(update.provider_id, provider);
             }
             None => ic_cdk::trap("Provider not found"),
@@ -0,0 +1,290 @@
+use candid::Principal;
+
+use crate::*;
+
+impl TokenBucket {
+    fn refill(&mut self, capacity: u128, refill_period_ns: u64, now: u64) {
+        if now <= self.updated_at {
+            return;
+        }
+        let elapsed = (now - self.updated_at) as u128;
+        let refilled = if capacity == 0 || refill_period_ns == 0 {
+            u128::MAX
+        } else {
+            capacity.saturating_mul(elapsed) / refill_period_ns as u128
+        };
+        if refilled >= self.used {
+            self.used = 0;
+            self.updated_at = now;
+        } else {
+            self.used -= refilled;
+            // Only advance by the time accounted for, so that frequent checks still refill.
+            self.updated_at += (refilled * refill_period_ns as u128 / capacity) as u64;
+        }
+    }
+
+    /// Returns the delay until `amount` fits into the bucket, or `None` if it fits now.
+    /// The delay is `u64::MAX` if `amount` exceeds the capacity of the bucket.
+    fn retry_after(
+        &self,
+        amount: u128,
+        capacity: u128,
+        refill_period_ns: u64,
+        now: u64,
+    ) -> Option<u64> {
+        let total = self.used.saturating_add(amount);
+        if total <= capacity {
+            return None;
+        }
+        if amount > capacity {
+            return Some(u64::MAX);
+        }
+        let excess = total - capacity;
+        let refill_ns = (excess.saturating_mul(refill_period_ns as u128) + capacity - 1) / capacity;
+        Some(
+            (refill_ns.min(u64::MAX as u128) as u64)
+                .saturating_sub(now.saturating_sub(self.updated_at)),
+        )
+    }
+}
+
+impl RateLimitBucketMap {
+    fn take(&mut self, principal: &Principal) -> Option<RateLimitBuckets> {
+        let buckets = self.buckets.remove(principal)?;
+        self.by_last_use.remove(&(buckets.last_used, *principal));
+        Some(buckets)
+    }
+
+    fn insert(&mut self, principal: Principal, buckets: RateLimitBuckets) {
+        self.by_last_use.insert((buckets.last_used, principal));
+        self.buckets.insert(principal, buckets);
+    }
+
+    /// Drops the least recently used buckets which have refilled completely.
+    fn evict_refilled(&mut self, now: u64) {
+        while let Some(&(last_used, principal)) = self.by_last_use.iter().next() {
+            let refilled = get_rate_limit(&principal).map_or(true, |limit| {
+                now.saturating_sub(last_used) >= limit.refill_period_ns
+            });
+            if !refilled {
+                break;
+            }
+            self.take(&principal);
+        }
+    }
+}
+
+/// Returns the rate limit applying to the caller: an admin override if present, otherwise the
+/// default for the most privileged `Auth` the caller holds.
+pub fn get_rate_limit(caller: &Principal) -> Option<RateLimit> {
+    if let Some(limit) = RATE_LIMIT_OVERRIDES.with(|r| r.borrow().get(&PrincipalStorable(*caller)))
+    {
+        return Some(limit);
+    }
+    let rate_limits = METADATA.with(|m| m.borrow().get().rate_limits.clone().unwrap_or_default());
+    find_auth_tier(caller, &rate_limits, |l| l.auth).map(|l| l.limit.clone())
+}
+
+/// Consumes one request and `cycles` from the caller's token buckets.
+pub fn check_rate_limit(caller: &Principal, cycles: u128, now: u64) -> Result<(), ProviderError> {
+    let limit = match get_rate_limit(caller) {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let period = limit.refill_period_ns;
+    RATE_LIMIT_BUCKETS.with(|b| {
+        let mut buckets = b.borrow_mut();
+        buckets.evict_refilled(now);
+        let mut entry = buckets.take(caller).unwrap_or_default();
+        entry.last_used = entry.last_used.max(now);
+        let mut retry_after = None;
+        if let Some(max_requests) = limit.max_requests {
+            entry.requests.refill(max_requests as u128, period, now);
+            retry_after = retry_after.max(entry.requests.retry_after(
+                1,
+                max_requests as u128,
+                period,
+                now,
+            ));
+        }
+        if let Some(max_cycles) = limit.max_cycles {
+            entry.cycles.refill(max_cycles, period, now);
+            retry_after =
+                retry_after.max(entry.cycles.retry_after(cycles, max_cycles, period, now));
+        }
+        let result = match retry_after {
+            Some(retry_after_ns) => Err(ProviderError::RateLimited { retry_after_ns }),
+            None => {
+                entry.requests.used += 1;
+                entry.cycles.used = entry.cycles.used.saturating_add(cycles);
+                Ok(())
+            }
+        };
+        buckets.insert(*caller, entry);
+        result
+    })
+}
+
+/// Returns one request and `cycles` to the caller's token buckets, for requests which passed
+/// `check_rate_limit` but were rejected before being made.
+pub fn refund_rate_limit(caller: &Principal, cycles: u128) {
+    RATE_LIMIT_BUCKETS.with(|b| {
+        let mut buckets = b.borrow_mut();
+        if let Some(mut entry) = buckets.take(caller) {
+            entry.requests.used = entry.requests.used.saturating_sub(1);
+            entry.cycles.used = entry.cycles.used.saturating_sub(cycles);
+            buckets.insert(*caller, entry);
+        }
+    })
+}
+
+pub fn validate_rate_limit(limit: &RateLimit) -> Result<(), String> {
+    if limit.refill_period_ns == 0 {
+        return Err("Refill period must be positive".to_string());
+    }
+    Ok(())
+}
+
+pub fn do_set_rate_limit(auth: Option<Auth>, limit: Option<RateLimit>) {
+    if let Some(limit) = &limit {
+        validate_rate_limit(limit).unwrap_or_else(|err| ic_cdk::trap(&err));
+    }
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        let mut rate_limits = metadata.rate_limits.unwrap_or_default();
+        rate_limits.retain(|l| l.auth != auth);
+        if let Some(limit) = limit {
+            rate_limits.push(AuthRateLimit { auth, limit });
+        }
+        metadata.rate_limits = Some(rate_limits);
+        m.borrow_mut().set(metadata).unwrap();
+    });
+}
+
+pub fn do_set_rate_limit_override(principal: Principal, limit: Option<RateLimit>) {
+    RATE_LIMIT_OVERRIDES.with(|r| {
+        let mut overrides = r.borrow_mut();
+        match limit {
+            Some(limit) => {
+                validate_rate_limit(&limit).unwrap_or_else(|err| ic_cdk::trap(&err));
+                overrides.insert(PrincipalStorable(principal), limit);
+            }
+            None => {
+                overrides.remove(&PrincipalStorable(principal));
+            }
+        }
+    });
+}
+
+#[test]
+fn test_token_bucket() {
+    let second = 1_000_000_000;
+    let mut bucket = TokenBucket::default();
+    bucket.refill(10, 10 * second, 100 * second);
+    assert_eq!(bucket.retry_after(10, 10, 10 * second, 100 * second), None);
+    bucket.used = 10;
+    assert_eq!(
+        bucket.retry_after(1, 10, 10 * second, 100 * second),
+        Some(second)
+    );
+    assert_eq!(
+        bucket.retry_after(11, 10, 10 * second, 100 * second),
+        Some(u64::MAX)
+    );
+
+    // Refills gradually, including across frequent checks
+    for i in 1..=10 {
+        bucket.refill(10, 10 * second, 100 * second + i * second / 10);
+    }
+    assert_eq!(bucket.used, 9);
+    assert_eq!(
+        bucket.retry_after(2, 10, 10 * second, 101 * second),
+        Some(second)
+    );
+    bucket.refill(10, 10 * second, 200 * second);
+    assert_eq!(bucket.used, 0);
+}
+
+#[test]
+fn test_rate_limit() {
+    let second = 1_000_000_000;
+    let now = 1_700_000_000 * second;
+    let principal = Principal::from_slice(&[1]);
+    let free_principal = Principal::from_slice(&[2]);
+    do_authorize(free_principal, Auth::FreeRpc);
+
+    // Unlimited by default
+    for _ in 0..100 {
+        assert_eq!(check_rate_limit(&principal, 1_000_000, now), Ok(()));
+    }
+
+    do_set_rate_limit(
+        None,
+        Some(RateLimit {
+            max_requests: Some(2),
+            max_cycles: Some(1000),
+            refill_period_ns: 10 * second,
+        }),
+    );
+    do_set_rate_limit(
+        Some(Auth::FreeRpc),
+        Some(RateLimit {
+            max_requests: Some(5),
+            max_cycles: None,
+            refill_period_ns: 10 * second,
+        }),
+    );
+    assert_eq!(check_rate_limit(&principal, 600, now), Ok(()));
+    assert_eq!(
+        check_rate_limit(&principal, 600, now),
+        Err(ProviderError::RateLimited {
+            retry_after_ns: 2 * second
+        })
+    );
+    assert_eq!(check_rate_limit(&principal, 400, now), Ok(()));
+    assert_eq!(
+        check_rate_limit(&principal, 0, now),
+        Err(ProviderError::RateLimited {
+            retry_after_ns: 5 * second
+        })
+    );
+    assert_eq!(check_rate_limit(&principal, 0, now + 5 * second), Ok(()));
+
+    // Refunded requests do not count towards the limit
+    let refunded_principal = Principal::from_slice(&[3]);
+    assert_eq!(check_rate_limit(&refunded_principal, 1000, now), Ok(()));
+    refund_rate_limit(&refunded_principal, 1000);
+    assert_eq!(check_rate_limit(&refunded_principal, 1000, now), Ok(()));
+    assert_eq!(check_rate_limit(&refunded_principal, 0, now), Ok(()));
+
+    for _ in 0..5 {
+        assert_eq!(check_rate_limit(&free_principal, 1_000_000, now), Ok(()));
+    }
+    assert!(check_rate_limit(&free_principal, 0, now).is_err());
+
+    // Overrides take precedence
+    do_set_rate_limit_override(
+        free_principal,
+        Some(RateLimit {
+            max_requests: Some(10),
+            max_cycles: None,
+            refill_period_ns: 10 * second,
+        }),
+    );
+    assert_eq!(check_rate_limit(&free_principal, 0, now), Ok(()));
+    do_set_rate_limit_override(free_principal, None);
+    assert!(check_rate_limit(&free_principal, 0, now).is_err());
+
+    // Buckets which have refilled completely are evicted
+    let later = now + 20 * second;
+    assert_eq!(check_rate_limit(&free_principal, 0, later), Ok(()));
+    RATE_LIMIT_BUCKETS.with(|b| {
+        let buckets = b.borrow();
+        assert!(!buckets.buckets.contains_key(&principal));
+        assert_eq!(buckets.buckets.len(), buckets.by_last_use.len());
+    });
+
+    do_set_rate_limit(None, None);
+    do_set_rate_limit(Some(Auth::FreeRpc), None);
+    assert_eq!(check_rate_limit(&free_principal, 0, now), Ok(()));
+}
@@ -1,23 +1,116 @@
+use ic_eth::core::types::transaction::eip712::{Eip712, TypedData};
 use ic_eth::core::types::{RecoveryMessage, Signature};
+use ic_eth::core::utils::hash_message;
 
+use crate::*;
+
+/// Returns the recovery ID (27 or 28) of a `v` value, which may also be 0 or 1 or encode a
+/// chain ID as per EIP-155.
+fn normalize_v(v: u64) -> Result<u64, SignatureError> {
+    match v {
+        0 | 1 => Ok(v + 27),
+        27 | 28 => Ok(v),
+        v if v >= 35 => Ok(27 + (v - 35) % 2),
+        v => Err(SignatureError::InvalidRecoveryId(v)),
+    }
+}
+
+/// Parses a 65-byte `r || s || v` signature, with `v` possibly spanning up to 8 big-endian bytes
+/// for large chain IDs, or a 64-byte EIP-2098 compact signature.
+pub fn parse_signature(signature: &[u8]) -> Result<Signature, SignatureError> {
+    let (r, s, v) = match signature.len() {
+        64 => {
+            let mut s: [u8; 32] = signature[32..].try_into().unwrap();
+            // The highest bit of `s` holds the y-parity
+            let y_parity = s[0] >> 7;
+            s[0] &= 0x7f;
+            (&signature[..32], s, 27 + y_parity as u64)
+        }
+        65..=72 => {
+            let v = signature[64..]
+                .iter()
+                .fold(0_u64, |v, byte| (v << 8) | *byte as u64);
+            (
+                &signature[..32],
+                signature[32..64].try_into().unwrap(),
+                normalize_v(v)?,
+            )
+        }
+        length => return Err(SignatureError::InvalidSignatureLength(length as u64)),
+    };
+    Ok(Signature {
+        r: r.into(),
+        s: s[..].into(),
+        v,
+    })
+}
+
+pub fn do_recover_address(
+    message: RecoveryMessage,
+    signature: &[u8],
+) -> Result<[u8; 20], SignatureError> {
+    parse_signature(signature)?
+        .recover(message)
+        .map(|address| address.0)
+        .map_err(|e| SignatureError::RecoveryFailed(e.to_string()))
+}
+
+/// Returns `true` if the signature was made by the address. Signatures from which no address can
+/// be recovered are not valid for any address.
 pub fn do_verify_signature(
     eth_address: &[u8],
     message: RecoveryMessage,
     signature: Vec<u8>,
-) -> bool {
-    let eth_address_bytes: [u8; 20] = eth_address
+) -> Result<bool, SignatureError> {
+    let eth_address: [u8; 20] = eth_address
         .try_into()
-        .unwrap_or_else(|_| ic_cdk::trap("expected 20-byte address"));
-    if signature.len() != 65 {
-        ic_cdk::trap("expected 65-byte signature");
+        .map_err(|_| SignatureError::InvalidAddressLength(eth_address.len() as u64))?;
+    match do_recover_address(message, &signature) {
+        Ok(address) => Ok(address == eth_address),
+        Err(SignatureError::RecoveryFailed(_)) => Ok(false),
+        Err(err) => Err(err),
     }
-    Signature {
-        r: signature[..32].into(),
-        s: signature[32..64].into(),
-        v: signature[64].into(),
-    }
-    .verify(message, eth_address_bytes)
-    .is_ok()
+}
+
+/// Verifies an EIP-191 (`personal_sign`) signature of the message.
+pub fn do_verify_personal_signature(
+    eth_address: &[u8],
+    message: Vec<u8>,
+    signature: Vec<u8>,
+) -> Result<bool, SignatureError> {
+    do_verify_signature(
+        eth_address,
+        RecoveryMessage::Hash(hash_message(message)),
+        signature,
+    )
+}
+
+/// Computes the EIP-712 hash of the typed data.
+pub fn get_typed_data_hash(typed_data: &TypedDataArgs) -> Result<[u8; 32], String> {
+    let parse = |field: &str, json: &str| {
+        serde_json::from_str::<serde_json::Value>(json)
+            .map_err(|e| format!("Invalid {} JSON: {}", field, e))
+    };
+    let typed_data: TypedData = serde_json::from_value(serde_json::json!({
+        "domain": parse("domain", &typed_data.domain)?,
+        "types": parse("types", &typed_data.types)?,
+        "primaryType": typed_data.primary_type,
+        "message": parse("message", &typed_data.message)?,
+    }))
+    .map_err(|e| format!("Invalid typed data: {}", e))?;
+    typed_data
+        .encode_eip712()
+        .map_err(|e| format!("Invalid typed data: {}", e))
+}
+
+/// Verifies an EIP-712 signature of the typed data.
+pub fn do_verify_typed_data_signature(
+    eth_address: &[u8],
+    typed_data: &TypedDataArgs,
+    signature: Vec<u8>,
+) -> Result<bool, SignatureError> {
+    let hash = get_typed_data_hash(typed_data).map_err(SignatureError::InvalidTypedData)?;
+    do_verify_signature(eth_address, RecoveryMessage::Hash(hash.into()), signature)
 }
 
 #[test]
@@ -31,12 +124,176 @@ fn test_verify_signature() {
     let s2 = hex::decode("27ae1f90fd65c86b07aae1287dba8715db7e429ff9bf700205cb8ac904c6ba071c8fb7c6f8b5e15338521fee95a452c6a688f1c6fec5eeddbfa680a2abf300341b").unwrap();
 
     // Invalid message
-    assert!(!do_verify_signature(address, m2.clone(), s1.clone()));
+    assert_eq!(
+        do_verify_signature(address, m2.clone(), s1.clone()),
+        Ok(false)
+    );
 
     // Invalid signature
-    assert!(!do_verify_signature(address, m1.clone(), s2.clone()));
+    assert_eq!(
+        do_verify_signature(address, m1.clone(), s2.clone()),
+        Ok(false)
+    );
 
     // Valid signature
-    assert!(do_verify_signature(address, m1, s1));
-    assert!(do_verify_signature(address, m2, s2));
+    assert_eq!(
+        do_verify_signature(address, m1.clone(), s1.clone()),
+        Ok(true)
+    );
+    assert_eq!(do_verify_signature(address, m2, s2), Ok(true));
+
+    // Malformed input
+    assert_eq!(
+        do_verify_signature(&address[1..], m1.clone(), s1.clone()),
+        Err(SignatureError::InvalidAddressLength(19))
+    );
+    assert_eq!(
+        do_verify_signature(address, m1, s1[..63].to_vec()),
+        Err(SignatureError::InvalidSignatureLength(63))
+    );
+}
+
+#[test]
+fn test_recover_address() {
+    let address = hex::decode("c9b28dca7ea6c5e176a58ba9df53c30ba52c6642").unwrap();
+    let message = RecoveryMessage::Data("hello".as_bytes().to_vec());
+    let signature = hex::decode("5c0e32248c10f7125b32cae1de9988f2dab686031083302f85b0a82f78e9206516b272fb7641f3e8ab63cf9f3a9b9220b2d6ff2699dc34f0d000d7693ca1ea5e1c").unwrap();
+    let with_v = |v: &[u8]| [&signature[..64], v].concat();
+
+    // EIP-2098 compact signature, with the y-parity (1) in the highest bit of `s`
+    let mut compact = signature[..64].to_vec();
+    compact[32] |= 0x80;
+
+    for signature in [
+        signature.clone(),
+        with_v(&[1]),
+        // EIP-155 `v` for Ethereum mainnet (chain ID 1) and Sepolia (chain ID 11155111)
+        with_v(&[38]),
+        with_v(&[0x01, 0x54, 0x6d, 0x72]),
+        compact,
+    ] {
+        assert_eq!(
+            do_recover_address(message.clone(), &signature).map(|a| a.to_vec()),
+            Ok(address.clone())
+        );
+    }
+    // Opposite y-parity
+    for v in [[0], [27], [37]] {
+        assert_ne!(
+            do_recover_address(message.clone(), &with_v(&v)).map(|a| a.to_vec()),
+            Ok(address.clone())
+        );
+    }
+
+    for v in [2, 26, 29, 34] {
+        assert_eq!(
+            do_recover_address(message.clone(), &with_v(&[v])),
+            Err(SignatureError::InvalidRecoveryId(v as u64))
+        );
+    }
+    for length in [0, 63, 73] {
+        assert_eq!(
+            do_recover_address(message.clone(), &vec![1; length]),
+            Err(SignatureError::InvalidSignatureLength(length as u64))
+        );
+    }
+    assert!(matches!(
+        do_recover_address(message, &[0; 65]),
+        Err(SignatureError::RecoveryFailed(_))
+    ));
+}
+
+#[test]
+fn test_verify_personal_signature() {
+    // Test vector from ethers `hash_message`
+    assert_eq!(
+        hash_message("Hello World").0.to_vec(),
+        hex::decode("a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2").unwrap()
+    );
+
+    let address = &hex::decode("c9b28dca7ea6c5e176a58ba9df53c30ba52c6642").unwrap();
+    let signature = hex::decode("5c0e32248c10f7125b32cae1de9988f2dab686031083302f85b0a82f78e9206516b272fb7641f3e8ab63cf9f3a9b9220b2d6ff2699dc34f0d000d7693ca1ea5e1c").unwrap();
+    assert_eq!(
+        do_verify_personal_signature(address, b"hello".to_vec(), signature.clone()),
+        Ok(true)
+    );
+    assert_eq!(
+        do_verify_personal_signature(address, b"other".to_vec(), signature),
+        Ok(false)
+    );
+}
+
+#[test]
+fn test_verify_typed_data_signature() {
+    // "Mail" example from the EIP-712 specification
+    let typed_data = TypedDataArgs {
+        domain: r#"{
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        }"#
+        .to_string(),
+        types: r#"{
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        }"#
+        .to_string(),
+        primary_type: "Mail".to_string(),
+        message: r#"{
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }"#
+        .to_string(),
+    };
+    assert_eq!(
+        get_typed_data_hash(&typed_data).unwrap().to_vec(),
+        hex::decode("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2").unwrap()
+    );
+
+    let address = &hex::decode("cd2a3d9f938e13cd947ec05abc7fe734df8dd826").unwrap();
+    let signature = hex::decode("4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c").unwrap();
+    assert_eq!(
+        do_verify_typed_data_signature(address, &typed_data, signature.clone()),
+        Ok(true)
+    );
+    let other = TypedDataArgs {
+        message: typed_data.message.replace("Hello, Bob!", "Hello, Eve!"),
+        ..typed_data.clone()
+    };
+    assert_eq!(
+        do_verify_typed_data_signature(address, &other, signature.clone()),
+        Ok(false)
+    );
+
+    for invalid in [
+        TypedDataArgs {
+            domain: "{".to_string(),
+            ..typed_data.clone()
+        },
+        TypedDataArgs {
+            primary_type: "Letter".to_string(),
+            ..typed_data.clone()
+        },
+    ] {
+        assert!(get_typed_data_hash(&invalid).is_err());
+        assert!(matches!(
+            do_verify_typed_data_signature(address, &invalid, signature.clone()),
+            Err(SignatureError::InvalidTypedData(_))
+        ));
+    }
 }
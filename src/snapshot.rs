@@ -0,0 +1,532 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+
+use ic_stable_structures::Storable;
+
+use crate::*;
+
+/// Exports a page of the logical state of the canister, optionally with decrypted provider
+/// credentials.
+///
+/// Each list holds at most `limit` entries following its cursor in `args`; the remaining entries
+/// are exported by continuing from the last entry of each full list.
+pub fn do_export_registry(
+    keys: &impl CredentialKeySource,
+    args: ExportRegistryArgs,
+) -> RegistrySnapshot {
+    let limit = args.limit.min(MAX_REGISTRY_EXPORT_PAGE_SIZE) as usize;
+    let principal_bound = |start_after: Option<Principal>| match start_after {
+        Some(principal) => Bound::Excluded(PrincipalStorable(principal)),
+        None => Bound::Unbounded,
+    };
+    let providers_start = match args.providers_after {
+        Some(provider_id) => Bound::Excluded(provider_id),
+        None => Bound::Unbounded,
+    };
+    let providers = PROVIDERS.with(|p| {
+        p.borrow()
+            .range((providers_start, Bound::Unbounded))
+            .take(limit)
+            .map(|(_, provider)| {
+                let credentials = args.include_credentials.then(|| {
+                    let credentials =
+                        load_credentials(keys, &provider).unwrap_or_else(|err| ic_cdk::trap(&err));
+                    ProviderCredentials {
+                        credential_path: credentials.path,
+                        credential_headers: credentials.headers,
+                    }
+                });
+                ProviderSnapshot {
+                    provider_id: provider.provider_id,
+                    owner: provider.owner,
+                    chain_id: provider.chain_id,
+                    hostname: provider.hostname,
+                    cycles_per_call: provider.cycles_per_call,
+                    cycles_per_message_byte: provider.cycles_per_message_byte,
+                    cycles_owed: provider.cycles_owed,
+                    primary: provider.primary,
+                    capabilities: provider.capabilities,
+                    credentials,
+                }
+            })
+            .collect()
+    });
+    let authorizations = AUTH.with(|a| {
+        a.borrow()
+            .range((principal_bound(args.authorizations_after), Bound::Unbounded))
+            .take(limit)
+            .map(|(principal, auths)| Authorization {
+                principal: principal.0,
+                grants: auths.to_vec(),
+            })
+            .collect()
+    });
+    let balances = BALANCES.with(|b| {
+        b.borrow()
+            .range((principal_bound(args.balances_after), Bound::Unbounded))
+            .take(limit)
+            .map(|(principal, balance)| BalanceSnapshot {
+                principal: principal.0,
+                balance: balance.0,
+            })
+            .collect()
+    });
+    RegistrySnapshot {
+        version: REGISTRY_SNAPSHOT_VERSION,
+        timestamp: get_time(),
+        metadata: METADATA.with(|m| m.borrow().get().clone()),
+        providers,
+        authorizations,
+        balances,
+    }
+}
+
+/// Checks that the snapshot can be imported without partially applying it.
+pub fn validate_registry_snapshot(snapshot: &RegistrySnapshot) -> Result<(), String> {
+    if snapshot.version != REGISTRY_SNAPSHOT_VERSION {
+        return Err(format!(
+            "Unsupported snapshot version {} (expected {})",
+            snapshot.version, REGISTRY_SNAPSHOT_VERSION
+        ));
+    }
+    let metadata = &snapshot.metadata;
+    // `Auth` is not hashable, and there are only a few tiers.
+    let mut fee_tiers = vec![];
+    for fee in metadata.service_fees.iter().flatten() {
+        if fee_tiers.contains(&fee.auth) {
+            return Err(format!("Duplicate service fee for {:?}", fee.auth));
+        }
+        fee_tiers.push(fee.auth);
+        if fee.fee.fee_bps > MAX_SERVICE_FEE_BPS {
+            return Err("Service fee must not exceed 100%".to_string());
+        }
+    }
+    let mut rate_limit_tiers = vec![];
+    for rate_limit in metadata.rate_limits.iter().flatten() {
+        if rate_limit_tiers.contains(&rate_limit.auth) {
+            return Err(format!("Duplicate rate limit for {:?}", rate_limit.auth));
+        }
+        rate_limit_tiers.push(rate_limit.auth);
+        validate_rate_limit(&rate_limit.limit)?;
+    }
+    if let Some(pricing) = &metadata.pricing {
+        validate_pricing(pricing)?;
+    }
+    let mut provider_ids = HashSet::new();
+    for provider in &snapshot.providers {
+        let id = provider.provider_id;
+        if !provider_ids.insert(id) {
+            return Err(format!("Duplicate provider {}", id));
+        }
+        if id >= snapshot.metadata.next_provider_id {
+            return Err(format!("Provider {} is not below next_provider_id", id));
+        }
+        validate_hostname(&provider.hostname, Some(provider.chain_id))
+            .map_err(|e| format!("Provider {}: {:?}", id, e))?;
+        if let Some(credentials) = &provider.credentials {
+            validate_credential_path(
+                &provider.hostname,
+                provider.chain_id,
+                &credentials.credential_path,
+            )
+            .and_then(|_| validate_credential_headers(&credentials.credential_headers))
+            .map_err(|e| format!("Provider {}: {:?}", id, e))?;
+        }
+        if get_snapshot_provider(provider).to_bytes().len() > PROVIDER_STORABLE_MAX_SIZE as usize {
+            return Err(format!("Provider {} exceeds maximum size", id));
+        }
+    }
+    let mut principals = HashSet::new();
+    for authorization in &snapshot.authorizations {
+        if !principals.insert(authorization.principal) {
+            return Err(format!(
+                "Duplicate authorization for {}",
+                authorization.principal
+            ));
+        }
+        let auth_set = AuthSet::new(authorization.grants.clone());
+        if auth_set.to_bytes().len() > AUTH_SET_STORABLE_MAX_SIZE as usize {
+            return Err(format!("Too many grants for {}", authorization.principal));
+        }
+    }
+    Ok(())
+}
+
+fn get_snapshot_provider(provider: &ProviderSnapshot) -> Provider {
+    Provider {
+        provider_id: provider.provider_id,
+        owner: provider.owner,
+        chain_id: provider.chain_id,
+        hostname: provider.hostname.clone(),
+        credential_path: String::new(),
+        credential_headers: vec![],
+        cycles_per_call: provider.cycles_per_call,
+        cycles_per_message_byte: provider.cycles_per_message_byte,
+        cycles_owed: provider.cycles_owed,
+        primary: provider.primary,
+        capabilities: provider.capabilities.clone(),
+    }
+}
+
+/// Imports the providers, authorizations and metadata of the snapshot.
+///
+/// In `ImportMode::Merge`, the snapshot's providers and authorizations are added or replace
+/// those with the same key, so that the pages of an export can be imported one by one. In
+/// `ImportMode::Replace`, all other providers and authorizations are removed.
+///
+/// Balances and cycles owed are exported for accounting only: the cycles backing them stay in
+/// the exporting canister, so importing them would create liabilities which no cycles cover.
+/// Balances are left unchanged, providers kept with the same owner keep the cycles owed to them,
+/// and cycles owed to removed or replaced providers are moved to escrow. Providers imported
+/// without credentials have none.
+pub fn do_import_registry(
+    keys: &impl CredentialKeySource,
+    snapshot: RegistrySnapshot,
+    mode: ImportMode,
+) -> Result<(), String> {
+    validate_registry_snapshot(&snapshot)?;
+    // Owed cycles are tracked by provider ID, so they would be lost to a provider taking over
+    // the ID of one with another owner.
+    for provider in &snapshot.providers {
+        let id = provider.provider_id;
+        let current = PROVIDERS.with(|p| p.borrow().get(&id));
+        let owed_to_other = current
+            .map(|p| p.owner != provider.owner && p.cycles_owed > 0)
+            .unwrap_or_default()
+            || get_escrow(id).map_or(false, |e| e.owner != provider.owner);
+        if owed_to_other {
+            return Err(format!(
+                "Provider {} has cycles owed to another owner which must be withdrawn first",
+                id
+            ));
+        }
+    }
+
+    let has_credentials = snapshot.providers.iter().any(|p| {
+        p.credentials.as_ref().map_or(false, |c| {
+            !c.credential_path.is_empty() || !c.credential_headers.is_empty()
+        })
+    });
+    if has_credentials && keys.current_key().is_none() {
+        return Err("Credential key is not initialized yet; please retry later".to_string());
+    }
+
+    // Provider IDs are never reused, since escrows and grants may still refer to them.
+    let next_provider_id = METADATA.with(|m| m.borrow().get().next_provider_id);
+    let metadata = Metadata {
+        next_provider_id: next_provider_id.max(snapshot.metadata.next_provider_id),
+        ..snapshot.metadata
+    };
+    METADATA.with(|m| m.borrow_mut().set(metadata).unwrap());
+
+    let imported_ids: HashSet<u64> = snapshot.providers.iter().map(|p| p.provider_id).collect();
+    let current_providers: Vec<Provider> = PROVIDERS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, provider)| provider)
+            .filter(|p| mode == ImportMode::Replace || imported_ids.contains(&p.provider_id))
+            .collect()
+    });
+    let mut kept_cycles_owed = HashMap::new();
+    for provider in current_providers {
+        let id = provider.provider_id;
+        let kept = snapshot
+            .providers
+            .iter()
+            .any(|p| p.provider_id == id && p.owner == provider.owner);
+        remove_credentials(id);
+        PROVIDERS.with(|p| p.borrow_mut().remove(&id));
+        if kept {
+            kept_cycles_owed.insert(id, provider.cycles_owed);
+        } else {
+            move_to_escrow(&provider);
+        }
+    }
+    for provider in snapshot.providers {
+        if let Some(credentials) = &provider.credentials {
+            store_credential_path(
+                keys,
+                provider.provider_id,
+                credentials.credential_path.clone(),
+            )?;
+            store_credential_headers(
+                keys,
+                provider.provider_id,
+                credentials.credential_headers.clone(),
+            )?;
+        }
+        let provider = Provider {
+            cycles_owed: kept_cycles_owed
+                .get(&provider.provider_id)
+                .copied()
+                .unwrap_or_default(),
+            ..get_snapshot_provider(&provider)
+        };
+        PROVIDERS.with(|p| p.borrow_mut().insert(provider.provider_id, provider));
+    }
+
+    AUTH.with(|a| {
+        let mut auth_map = a.borrow_mut();
+        if mode == ImportMode::Replace {
+            let principals: Vec<_> = auth_map.iter().map(|(principal, _)| principal).collect();
+            for principal in principals {
+                auth_map.remove(&principal);
+            }
+        }
+        for authorization in snapshot.authorizations {
+            let principal = PrincipalStorable(authorization.principal);
+            let auth_set = AuthSet::new(authorization.grants);
+            if auth_set.is_empty() {
+                auth_map.remove(&principal);
+            } else {
+                auth_map.insert(principal, auth_set);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[test]
+fn test_registry_snapshot() {
+    use ic_cdk::api::management_canister::http_request::HttpHeader;
+
+    init_allowed_hosts();
+    let keys = MockKeySource::new(vec![VaultKey {
+        version: 0,
+        key: [1; 32],
+    }]);
+    let owner = Principal::from_slice(&[1]);
+    let admin = Principal::from_slice(&[2]);
+    let user = Principal::from_slice(&[3]);
+    let credentials = ProviderCredentials {
+        credential_path: "/v2/key".to_string(),
+        credential_headers: vec![HttpHeader {
+            name: "Authorization".to_string(),
+            value: "Bearer token".to_string(),
+        }],
+    };
+    let provider = ProviderSnapshot {
+        provider_id: 0,
+        owner,
+        chain_id: ETH_MAINNET_CHAIN_ID,
+        hostname: "cloudflare-eth.com".to_string(),
+        cycles_per_call: 1000,
+        cycles_per_message_byte: 10,
+        cycles_owed: 50,
+        primary: true,
+        capabilities: None,
+        credentials: Some(credentials.clone()),
+    };
+    let snapshot = RegistrySnapshot {
+        version: REGISTRY_SNAPSHOT_VERSION,
+        timestamp: 0,
+        metadata: Metadata {
+            nodes_in_subnet: 28,
+            next_provider_id: 1,
+            open_rpc_access: false,
+            ..Default::default()
+        },
+        providers: vec![provider.clone()],
+        authorizations: vec![Authorization {
+            principal: admin,
+            grants: AuthSet::new(vec![AuthGrant::new(
+                Auth::ManageService,
+                AuthLimits::default(),
+            )])
+            .to_vec(),
+        }],
+        balances: vec![BalanceSnapshot {
+            principal: user,
+            balance: 5000,
+        }],
+    };
+
+    // Replaces the current state, except for balances and cycles owed
+    for (provider_id, cycles_owed) in [(0, 20), (7, 300)] {
+        PROVIDERS.with(|p| {
+            p.borrow_mut().insert(
+                provider_id,
+                Provider {
+                    provider_id,
+                    cycles_owed,
+                    ..get_snapshot_provider(&provider)
+                },
+            )
+        });
+    }
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        metadata.next_provider_id = 8;
+        m.borrow_mut().set(metadata).unwrap();
+    });
+    do_authorize(user, Auth::Rpc);
+    credit_balance(&owner, 100, BalanceEntryKind::RequestRefund);
+    do_import_registry(&keys, snapshot.clone(), ImportMode::Replace).unwrap();
+
+    let export_args = ExportRegistryArgs {
+        include_credentials: true,
+        limit: MAX_REGISTRY_EXPORT_PAGE_SIZE,
+        ..Default::default()
+    };
+    let exported = do_export_registry(&keys, export_args.clone());
+    assert_eq!(exported.metadata.nodes_in_subnet, 28);
+    // Provider IDs are not reused
+    assert_eq!(exported.metadata.next_provider_id, 8);
+    assert_eq!(
+        exported.providers,
+        vec![ProviderSnapshot {
+            cycles_owed: 20,
+            ..provider.clone()
+        }]
+    );
+    assert_eq!(exported.authorizations, snapshot.authorizations);
+    assert_eq!(
+        exported.balances,
+        vec![BalanceSnapshot {
+            principal: owner,
+            balance: 100,
+        }]
+    );
+    assert!(!is_authorized(&user, Auth::Rpc));
+    assert_eq!(get_balance(&user), 0);
+    // Cycles owed to the dropped provider are kept in escrow
+    assert_eq!(get_escrow(7).map(|e| e.cycles_owed), Some(300));
+
+    // Exports are paginated
+    let page = do_export_registry(
+        &keys,
+        ExportRegistryArgs {
+            providers_after: Some(0),
+            authorizations_after: Some(admin),
+            limit: 1,
+            ..export_args.clone()
+        },
+    );
+    assert!(page.providers.is_empty());
+    assert!(page.authorizations.is_empty());
+    assert_eq!(page.balances, exported.balances);
+    credit_balance(&user, 5, BalanceEntryKind::RequestRefund);
+    let page = do_export_registry(
+        &keys,
+        ExportRegistryArgs {
+            limit: 1,
+            ..export_args.clone()
+        },
+    );
+    assert_eq!(page.balances, exported.balances);
+    let page = do_export_registry(
+        &keys,
+        ExportRegistryArgs {
+            balances_after: Some(owner),
+            limit: 1,
+            ..export_args
+        },
+    );
+    assert_eq!(page.balances.len(), 1);
+    assert_eq!(page.balances[0].principal, user);
+
+    // Credentials are optional
+    let exported = do_export_registry(
+        &keys,
+        ExportRegistryArgs {
+            limit: MAX_REGISTRY_EXPORT_PAGE_SIZE,
+            ..Default::default()
+        },
+    );
+    assert_eq!(exported.providers[0].credentials, None);
+    do_import_registry(&keys, exported, ImportMode::Replace).unwrap();
+    let provider = PROVIDERS.with(|p| p.borrow().get(&0)).unwrap();
+    assert_eq!(
+        load_credentials(&keys, &provider),
+        Ok(Credentials::default())
+    );
+
+    // Pages of an export are merged into the current state
+    let page = RegistrySnapshot {
+        metadata: Metadata {
+            next_provider_id: 9,
+            ..snapshot.metadata.clone()
+        },
+        providers: vec![ProviderSnapshot {
+            provider_id: 8,
+            credentials: None,
+            ..provider.clone()
+        }],
+        authorizations: vec![Authorization {
+            principal: user,
+            grants: AuthSet::new(vec![AuthGrant::new(Auth::Rpc, AuthLimits::default())]).to_vec(),
+        }],
+        ..snapshot.clone()
+    };
+    do_import_registry(&keys, page, ImportMode::Merge).unwrap();
+    assert!(PROVIDERS.with(|p| p.borrow().get(&0)).is_some());
+    assert!(PROVIDERS.with(|p| p.borrow().get(&8)).is_some());
+    assert!(is_authorized(&admin, Auth::ManageService));
+    assert!(is_authorized(&user, Auth::Rpc));
+
+    // Invalid snapshots are rejected before changing the state
+    for invalid in [
+        RegistrySnapshot {
+            version: REGISTRY_SNAPSHOT_VERSION + 1,
+            ..snapshot.clone()
+        },
+        RegistrySnapshot {
+            providers: vec![ProviderSnapshot {
+                provider_id: 1,
+                ..snapshot.providers[0].clone()
+            }],
+            ..snapshot.clone()
+        },
+        RegistrySnapshot {
+            providers: vec![ProviderSnapshot {
+                hostname: "example.com".to_string(),
+                ..snapshot.providers[0].clone()
+            }],
+            ..snapshot.clone()
+        },
+        // Provider 7 has cycles in escrow for another owner
+        RegistrySnapshot {
+            metadata: Metadata {
+                next_provider_id: 8,
+                ..snapshot.metadata.clone()
+            },
+            providers: vec![ProviderSnapshot {
+                provider_id: 7,
+                owner: user,
+                ..snapshot.providers[0].clone()
+            }],
+            ..snapshot.clone()
+        },
+        RegistrySnapshot {
+            metadata: Metadata {
+                pricing: Some(PricingConfig {
+                    ingress_message_received_cost: 0,
+                    ingress_message_byte_received_cost: 0,
+                    http_outcall_request_base_cost: 0,
+                    http_outcall_request_per_node_cost: 0,
+                    http_outcall_request_byte_cost: 0,
+                    http_outcall_response_byte_cost: 0,
+                }),
+                ..snapshot.metadata.clone()
+            },
+            ..snapshot.clone()
+        },
+        RegistrySnapshot {
+            metadata: Metadata {
+                rate_limits: Some(vec![AuthRateLimit {
+                    auth: None,
+                    limit: RateLimit {
+                        max_requests: Some(1),
+                        max_cycles: None,
+                        refill_period_ns: 0,
+                    },
+                }]),
+                ..snapshot.metadata.clone()
+            },
+            ..snapshot.clone()
+        },
+    ] {
+        assert!(do_import_registry(&keys, invalid, ImportMode::Merge).is_err());
+    }
+    assert_eq!(get_balance(&user), 5);
+}
@@ -1,5 +1,5 @@
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
-use cketh_common::eth_rpc::{ProviderError, RpcError};
+use cketh_common::eth_rpc::HttpOutcallError;
 use cketh_common::eth_rpc_client::providers::{EthereumProvider, RpcApi, SepoliaProvider};
 
 use ic_cdk::api::management_canister::http_request::HttpHeader;
@@ -8,10 +8,16 @@ use ic_stable_structures::{BoundedStorable, Storable};
 
 use serde::Serialize;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use crate::constants::STRING_STORABLE_MAX_SIZE;
-use crate::{AUTH_SET_STORABLE_MAX_SIZE, PROVIDERS};
+use crate::{
+    RpcRequirements, AUTH_SET_STORABLE_MAX_SIZE, CREDENTIALS_STORABLE_MAX_SIZE,
+    HOST_SCOPE_STORABLE_MAX_SIZE, HTTP_OUTCALL_REQUEST_BASE_COST, HTTP_OUTCALL_REQUEST_BYTE_COST,
+    HTTP_OUTCALL_REQUEST_PER_NODE_COST, HTTP_OUTCALL_RESPONSE_BYTE_COST,
+    INGRESS_MESSAGE_BYTE_RECEIVED_COST, INGRESS_MESSAGE_RECEIVED_COST,
+    LEGACY_PROVIDER_STORABLE_MAX_SIZE, PROVIDERS, PROVIDER_STORABLE_MAX_SIZE,
+};
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum Source {
@@ -28,65 +34,262 @@ pub enum Source {
 }
 
 impl Source {
-    pub fn resolve(self) -> Result<ResolvedSource, ProviderError> {
+    pub fn resolve(self, requirements: &RpcRequirements) -> Result<ResolvedSource, ProviderError> {
         Ok(match self {
             Source::Custom { url, headers } => ResolvedSource::Api(RpcApi {
                 url,
                 headers: headers.unwrap_or_default(),
             }),
             Source::Provider(id) => ResolvedSource::Provider({
-                PROVIDERS.with(|providers| {
-                    providers
-                        .borrow()
-                        .get(&id)
-                        .ok_or(ProviderError::ProviderNotFound)
-                })?
+                let provider = PROVIDERS
+                    .with(|providers| providers.borrow().get(&id))
+                    .ok_or(ProviderError::ProviderNotFound)?;
+                if !provider.can_serve(requirements) {
+                    return Err(ProviderError::UnsupportedCapabilities);
+                }
+                provider
             }),
-            Source::Chain(id) => ResolvedSource::Provider(PROVIDERS.with(|providers| {
-                let providers = providers.borrow();
-                Ok(providers
-                    .iter()
-                    .find(|(_, p)| p.primary && p.chain_id == id)
-                    .or_else(|| providers.iter().find(|(_, p)| p.chain_id == id))
-                    .ok_or(ProviderError::ProviderNotFound)?
-                    .1)
-            })?),
+            Source::Chain(id) => {
+                ResolvedSource::Provider(find_provider(requirements, |p| p.chain_id == id)?)
+            }
             Source::Service { hostname, chain_id } => {
-                ResolvedSource::Provider(PROVIDERS.with(|providers| {
-                    let matches_provider = |p: &Provider| {
-                        p.hostname == hostname
-                            && match chain_id {
-                                Some(id) => p.chain_id == id,
-                                None => true,
-                            }
-                    };
-                    let providers = providers.borrow();
-                    Ok(providers
-                        .iter()
-                        .find(|(_, p)| p.primary && matches_provider(p))
-                        .or_else(|| providers.iter().find(|(_, p)| matches_provider(p)))
-                        .ok_or(ProviderError::ProviderNotFound)?
-                        .1)
+                ResolvedSource::Provider(find_provider(requirements, |p| {
+                    p.hostname == hostname
+                        && match chain_id {
+                            Some(id) => p.chain_id == id,
+                            None => true,
+                        }
                 })?)
             }
         })
     }
 }
 
+/// Finds a provider matching the predicate which can serve the request, preferring primary
+/// providers.
+///
+/// Returns `UnsupportedCapabilities` if providers match but none of them can serve the request.
+fn find_provider(
+    requirements: &RpcRequirements,
+    matches: impl Fn(&Provider) -> bool,
+) -> Result<Provider, ProviderError> {
+    PROVIDERS.with(|providers| {
+        let providers = providers.borrow();
+        let mut found = false;
+        let mut fallback = None;
+        for (_, provider) in providers.iter().filter(|(_, p)| matches(p)) {
+            found = true;
+            if provider.can_serve(requirements) {
+                if provider.primary {
+                    return Ok(provider);
+                }
+                fallback.get_or_insert(provider);
+            }
+        }
+        fallback.ok_or(if found {
+            ProviderError::UnsupportedCapabilities
+        } else {
+            ProviderError::ProviderNotFound
+        })
+    })
+}
+
 pub enum ResolvedSource {
     Api(RpcApi),
     Provider(Provider),
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
 pub struct Metrics {
     pub requests: u64,
     pub request_cycles_charged: u128,
     pub request_cycles_refunded: u128,
+    pub request_cycles_service_fee: u128,
     pub request_err_no_permission: u64,
     pub request_err_host_not_allowed: u64,
     pub request_err_http: u64,
+    pub request_err_rate_limited: u64,
     pub host_requests: HashMap<String, u64>,
+    pub method_requests: HashMap<String, u64>,
+    pub chain_requests: HashMap<u64, u64>,
+    pub provider_requests: HashMap<u64, u64>,
+    pub caller_requests: HashMap<String, u64>,
+    pub request_errors: HashMap<String, u64>,
+    pub provider_cycles_charged: HashMap<u64, u128>,
+    // Fields added after metrics were first saved in stable memory must be optional, so that
+    // metrics saved by earlier versions can still be decoded.
+    /// Outcall latency (in milliseconds) by host and method.
+    pub request_latency_ms: Option<HashMap<(String, String), Histogram>>,
+    /// Response body size (in bytes) by host and method.
+    pub response_size_bytes: Option<HashMap<(String, String), Histogram>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogSeverity {
+    Info,
+    Error,
+}
+
+/// Structured record of a JSON-RPC request served over HTTPS outcalls.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestLogEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub severity: LogSeverity,
+    pub caller: String,
+    pub provider_id: Option<u64>,
+    pub chain_id: Option<u64>,
+    pub method: String,
+    pub host: Option<String>,
+    pub cycles_charged: u128,
+    /// HTTP status code of the response, if the outcall succeeded.
+    pub status: Option<u16>,
+    pub latency_ns: Option<u64>,
+    /// Variant of the `RpcError` returned to the caller, if any.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestLogFilter {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub caller: Option<String>,
+    pub provider_id: Option<u64>,
+    pub severity: Option<LogSeverity>,
+    /// Only entries with at least this id are returned.
+    pub cursor: u64,
+    pub limit: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestLogPage {
+    pub entries: Vec<RequestLogEntry>,
+    /// Cursor to continue from, if more entries may match.
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum AuditAction {
+    Authorize {
+        principal: Principal,
+        auth: Auth,
+    },
+    AuthorizeWithLimits {
+        principal: Principal,
+        auth: Auth,
+    },
+    Deauthorize {
+        principal: Principal,
+        auth: Auth,
+    },
+    SetOpenRpcAccess {
+        open_rpc_access: bool,
+    },
+    SetNodesInSubnet {
+        nodes_in_subnet: u32,
+    },
+    SetPricing,
+    SetServiceFee {
+        auth: Option<Auth>,
+    },
+    SetRateLimit {
+        auth: Option<Auth>,
+    },
+    SetRateLimitOverride {
+        principal: Principal,
+    },
+    RegisterProvider {
+        provider_id: u64,
+    },
+    UpdateProvider {
+        provider_id: u64,
+    },
+    UnregisterProvider {
+        provider_id: u64,
+    },
+    AddProviderManager {
+        provider_id: u64,
+        manager: Principal,
+    },
+    RemoveProviderManager {
+        provider_id: u64,
+        manager: Principal,
+    },
+    RotateCredentialKey,
+    WithdrawTreasury {
+        amount: u128,
+        canister_id: Principal,
+    },
+    StableWrite {
+        offset: u64,
+        length: u64,
+    },
+    ImportRegistry {
+        /// Time at which the imported snapshot was exported.
+        timestamp: u64,
+    },
+    AddAllowedHost {
+        hostname: String,
+    },
+    RemoveAllowedHost {
+        hostname: String,
+    },
+}
+
+/// Record of an administrative action.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub caller: Principal,
+    pub action: AuditAction,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AuditEntry {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Observations counted by bucket, where the last bucket is unbounded.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct Histogram {
+    pub bucket_counts: Vec<u64>,
+    pub sum: u128,
+}
+
+impl Histogram {
+    /// Counts the value in the first bucket whose bound is not exceeded.
+    /// Resets the histogram if the number of buckets has changed.
+    pub fn observe(&mut self, bounds: &[u64], value: u64) {
+        if self.bucket_counts.len() != bounds.len() + 1 {
+            self.bucket_counts = vec![0; bounds.len() + 1];
+        }
+        let index = bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(bounds.len());
+        self.bucket_counts[index] += 1;
+        self.sum += value as u128;
+    }
+}
+
+impl Storable for Metrics {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    /// Metrics which cannot be decoded are reset rather than failing the upgrade.
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, CandidType, Serialize, Deserialize)]
@@ -95,17 +298,127 @@ pub enum Auth {
     RegisterProvider,
     Rpc,
     FreeRpc,
+    ManageProvider,
+}
+
+/// Resources a grant is limited to. Each restriction only applies when set.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct AuthScope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_ids: Option<Vec<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostnames: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_ids: Option<Vec<u64>>,
+}
+
+impl AuthScope {
+    /// Returns `true` if every restriction of the scope is satisfied by the target.
+    /// A restriction is never satisfied by a target which leaves the corresponding field unset.
+    pub fn allows(&self, target: &AuthTarget) -> bool {
+        fn contains<T: PartialEq>(allowed: &Option<Vec<T>>, value: Option<&T>) -> bool {
+            match allowed {
+                Some(allowed) => value.map_or(false, |v| allowed.contains(v)),
+                None => true,
+            }
+        }
+        contains(&self.chain_ids, target.chain_id.as_ref())
+            && contains(&self.hostnames, target.hostname.as_ref())
+            && contains(&self.provider_ids, target.provider_id.as_ref())
+    }
+}
+
+/// Resource an authorization is checked against.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthTarget {
+    pub chain_id: Option<u64>,
+    pub hostname: Option<String>,
+    pub provider_id: Option<u64>,
+}
+
+impl AuthTarget {
+    pub fn provider(provider: &Provider) -> Self {
+        Self {
+            chain_id: Some(provider.chain_id),
+            hostname: Some(provider.hostname.clone()),
+            provider_id: Some(provider.provider_id),
+        }
+    }
+}
+
+/// Optional restrictions on an authorization grant.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct AuthLimits {
+    /// Expiration time (in nanoseconds since the UNIX epoch).
+    pub expires_at: Option<u64>,
+    pub max_requests: Option<u64>,
+    pub max_cycles: Option<u128>,
+    pub scope: Option<AuthScope>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct AuthGrant {
+    pub auth: Auth,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cycles: Option<u128>,
+    #[serde(default)]
+    pub requests_used: u64,
+    #[serde(default)]
+    pub cycles_used: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<AuthScope>,
+}
+
+impl AuthGrant {
+    pub fn new(auth: Auth, limits: AuthLimits) -> Self {
+        Self {
+            auth,
+            expires_at: limits.expires_at,
+            max_requests: limits.max_requests,
+            max_cycles: limits.max_cycles,
+            requests_used: 0,
+            cycles_used: 0,
+            scope: limits.scope,
+        }
+    }
+
+    /// Returns `true` if the target is within the scope of the grant.
+    pub fn allows_target(&self, target: &AuthTarget) -> bool {
+        self.scope
+            .as_ref()
+            .map_or(true, |scope| scope.allows(target))
+    }
+
+    /// Returns `true` if the grant has not expired and allows another request costing `cycles`.
+    pub fn allows_usage(&self, now: u64, cycles: u128) -> bool {
+        self.expires_at.map_or(true, |t| now < t)
+            && self
+                .max_requests
+                .map_or(true, |max| self.requests_used < max)
+            && self
+                .max_cycles
+                .map_or(true, |max| self.cycles_used.saturating_add(cycles) <= max)
+    }
+
+    pub fn record_usage(&mut self, cycles: u128) {
+        self.requests_used += 1;
+        self.cycles_used = self.cycles_used.saturating_add(cycles);
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize, Default)]
-pub struct AuthSet(Vec<Auth>);
+pub struct AuthSet(Vec<AuthGrant>);
 
 impl AuthSet {
-    pub fn new(auths: Vec<Auth>) -> Self {
-        let mut auth_set = Self(Vec::with_capacity(auths.len()));
-        for auth in auths {
+    pub fn new(grants: Vec<AuthGrant>) -> Self {
+        let mut auth_set = Self(Vec::with_capacity(grants.len()));
+        for grant in grants {
             // Deduplicate
-            auth_set.authorize(auth);
+            auth_set.authorize(grant);
         }
         auth_set
     }
@@ -114,21 +427,49 @@ impl AuthSet {
         self.0.is_empty()
     }
 
-    pub fn is_authorized(&self, auth: Auth) -> bool {
-        self.0.contains(&auth)
+    pub fn to_vec(&self) -> Vec<AuthGrant> {
+        self.0.clone()
     }
 
-    pub fn authorize(&mut self, auth: Auth) -> bool {
-        if !self.is_authorized(auth) {
-            self.0.push(auth);
-            true
-        } else {
-            false
+    pub fn get(&self, auth: Auth) -> Option<&AuthGrant> {
+        self.0.iter().find(|g| g.auth == auth)
+    }
+
+    pub fn get_mut(&mut self, auth: Auth) -> Option<&mut AuthGrant> {
+        self.0.iter_mut().find(|g| g.auth == auth)
+    }
+
+    /// Returns `true` if a grant exists for `auth`, regardless of its expiration or quotas.
+    pub fn has_grant(&self, auth: Auth) -> bool {
+        self.get(auth).is_some()
+    }
+
+    /// Returns `true` if an active grant exists for `auth`, regardless of its scope.
+    pub fn is_authorized(&self, auth: Auth, now: u64) -> bool {
+        self.get(auth).map_or(false, |g| g.allows_usage(now, 0))
+    }
+
+    pub fn is_authorized_for(&self, auth: Auth, target: &AuthTarget, now: u64) -> bool {
+        self.get(auth)
+            .map_or(false, |g| g.allows_usage(now, 0) && g.allows_target(target))
+    }
+
+    /// Adds a grant, replacing (and resetting the usage of) any existing grant for the same `Auth`.
+    pub fn authorize(&mut self, grant: AuthGrant) -> bool {
+        match self.get_mut(grant.auth) {
+            Some(existing) => {
+                *existing = grant;
+                false
+            }
+            None => {
+                self.0.push(grant);
+                true
+            }
         }
     }
 
     pub fn deauthorize(&mut self, auth: Auth) -> bool {
-        if let Some(index) = self.0.iter().position(|a| *a == auth) {
+        if let Some(index) = self.0.iter().position(|g| g.auth == auth) {
             self.0.remove(index);
             true
         } else {
@@ -137,10 +478,28 @@ impl AuthSet {
     }
 }
 
+// Entries written before grants supported limits only contain the `Auth` variant.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAuthGrant {
+    Grant(AuthGrant),
+    Legacy(Auth),
+}
+
 // Using explicit JSON representation in place of enum indices for security
 impl Storable for AuthSet {
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        serde_json::from_slice(&bytes).expect("Unable to deserialize AuthSet")
+        let grants: Vec<StoredAuthGrant> =
+            serde_json::from_slice(&bytes).expect("Unable to deserialize AuthSet");
+        Self(
+            grants
+                .into_iter()
+                .map(|grant| match grant {
+                    StoredAuthGrant::Grant(grant) => grant,
+                    StoredAuthGrant::Legacy(auth) => AuthGrant::new(auth, AuthLimits::default()),
+                })
+                .collect(),
+        )
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
@@ -153,11 +512,182 @@ impl BoundedStorable for AuthSet {
     const IS_FIXED_SIZE: bool = false;
 }
 
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Authorization {
+    pub principal: Principal,
+    pub grants: Vec<AuthGrant>,
+}
+
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
 pub struct Metadata {
     pub nodes_in_subnet: u32,
     pub next_provider_id: u64,
     pub open_rpc_access: bool,
+    pub rate_limits: Option<Vec<AuthRateLimit>>,
+    pub pricing: Option<PricingConfig>,
+    pub service_fees: Option<Vec<AuthServiceFee>>,
+}
+
+/// Cycles pricing parameters. Ingress costs are specified for a 13-node subnet and scale linearly
+/// with the subnet size, whereas HTTPS outcall costs are specified per node.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PricingConfig {
+    pub ingress_message_received_cost: u128,
+    pub ingress_message_byte_received_cost: u128,
+    pub http_outcall_request_base_cost: u128,
+    pub http_outcall_request_per_node_cost: u128,
+    pub http_outcall_request_byte_cost: u128,
+    pub http_outcall_response_byte_cost: u128,
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        Self {
+            ingress_message_received_cost: INGRESS_MESSAGE_RECEIVED_COST,
+            ingress_message_byte_received_cost: INGRESS_MESSAGE_BYTE_RECEIVED_COST,
+            http_outcall_request_base_cost: HTTP_OUTCALL_REQUEST_BASE_COST,
+            http_outcall_request_per_node_cost: HTTP_OUTCALL_REQUEST_PER_NODE_COST,
+            http_outcall_request_byte_cost: HTTP_OUTCALL_REQUEST_BYTE_COST,
+            http_outcall_response_byte_cost: HTTP_OUTCALL_RESPONSE_BYTE_COST,
+        }
+    }
+}
+
+/// Token-bucket limits on the requests made by a single caller.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct RateLimit {
+    /// Maximum number of requests in a burst.
+    pub max_requests: Option<u64>,
+    /// Maximum cycles cost of the requests in a burst.
+    pub max_cycles: Option<u128>,
+    /// Time (in nanoseconds) for an exhausted bucket to refill completely.
+    pub refill_period_ns: u64,
+}
+
+impl Storable for RateLimit {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RateLimit {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenBucket {
+    /// Amount consumed and not yet refilled.
+    pub used: u128,
+    pub updated_at: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitBuckets {
+    pub requests: TokenBucket,
+    pub cycles: TokenBucket,
+    pub last_used: u64,
+}
+
+/// Token buckets of the callers, indexed by their last use so that buckets which have refilled
+/// completely can be evicted without scanning every caller.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitBucketMap {
+    pub buckets: HashMap<Principal, RateLimitBuckets>,
+    pub by_last_use: BTreeSet<(u64, Principal)>,
+}
+
+/// Default rate limit for callers holding the given `Auth` (`None` for all other callers).
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AuthRateLimit {
+    pub auth: Option<Auth>,
+    pub limit: RateLimit,
+}
+
+/// Fee charged by the service on top of the HTTPS outcall and provider costs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ServiceFee {
+    /// Fee in basis points of the HTTPS outcall and provider costs.
+    pub fee_bps: u32,
+    /// Fee in cycles per call.
+    pub flat_fee: u128,
+}
+
+/// Service fee for callers holding the given `Auth` (`None` for all other callers).
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AuthServiceFee {
+    pub auth: Option<Auth>,
+    pub fee: ServiceFee,
+}
+
+/// Host which providers and custom sources may use, optionally only for the given chains.
+///
+/// A `*.` prefix allows all subdomains (but not the domain itself).
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AllowedHost {
+    pub hostname: String,
+    pub chain_ids: Option<Vec<u64>>,
+    /// Ports allowed in addition to the default HTTPS port.
+    pub ports: Option<Vec<u16>>,
+    /// Allowed URL path prefixes (any path if unset).
+    pub path_prefixes: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct HostScope {
+    pub chain_ids: Option<Vec<u64>>,
+    pub ports: Option<Vec<u16>>,
+    pub path_prefixes: Option<Vec<String>>,
+}
+
+impl HostScope {
+    /// Returns `true` if the host may be used for the chain. Custom URLs have no known chain
+    /// (`None`), so they may only use hosts which are not scoped to chains.
+    pub fn allows(&self, chain_id: Option<u64>) -> bool {
+        match (&self.chain_ids, chain_id) {
+            (Some(chain_ids), Some(chain_id)) => chain_ids.contains(&chain_id),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Returns `true` if URLs may use the port (`None` meaning the default HTTPS port).
+    pub fn allows_port(&self, port: Option<u16>) -> bool {
+        match port {
+            None => true,
+            Some(port) => self.ports.iter().flatten().any(|p| *p == port),
+        }
+    }
+
+    /// Returns `true` if URLs may use the path. A prefix matches whole path segments only, so
+    /// that `/v1` matches `/v1/key` but not `/v10`.
+    pub fn allows_path(&self, path: &str) -> bool {
+        match &self.path_prefixes {
+            None => true,
+            Some(prefixes) => prefixes.iter().any(|prefix| {
+                path.strip_prefix(prefix.as_str()).map_or(false, |rest| {
+                    prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+                })
+            }),
+        }
+    }
+}
+
+impl Storable for HostScope {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for HostScope {
+    const MAX_SIZE: u32 = HOST_SCOPE_STORABLE_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -197,6 +727,175 @@ impl BoundedStorable for PrincipalStorable {
     const IS_FIXED_SIZE: bool = false;
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CyclesStorable(pub u128);
+
+impl Storable for CyclesStorable {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_be_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(u128::from_be_bytes(
+            bytes.as_ref().try_into().expect("Invalid cycles balance"),
+        ))
+    }
+}
+
+impl BoundedStorable for CyclesStorable {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum BalanceEntryKind {
+    Deposit {
+        from: Principal,
+    },
+    Withdraw {
+        to: Principal,
+    },
+    /// Credit for a withdrawal which could not be delivered.
+    WithdrawRefund {
+        to: Principal,
+    },
+    Request,
+    /// Credit for the part of a request's estimated cost which was not consumed.
+    RequestRefund,
+}
+
+/// Ledger-style record of a change to a caller's prepaid cycles balance.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct BalanceEntry {
+    pub timestamp: u64,
+    pub principal: Principal,
+    pub kind: BalanceEntryKind,
+    pub amount: u128,
+    /// Balance after the entry was applied.
+    pub balance: u128,
+}
+
+impl Storable for BalanceEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum PayoutEntryKind {
+    /// Cycles earned by serving a request.
+    Accrual,
+    Withdrawal {
+        canister_id: Principal,
+    },
+    /// Credit for a withdrawal which could not be delivered.
+    WithdrawalRefund {
+        canister_id: Principal,
+    },
+    /// Cycles moved to escrow when the provider was unregistered.
+    Escrow,
+}
+
+/// Append-only record of a change to the cycles owed to a provider.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PayoutEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub provider_id: u64,
+    pub kind: PayoutEntryKind,
+    pub amount: u128,
+    /// Cycles owed to the provider (or held in escrow) after the entry was applied.
+    pub cycles_owed: u128,
+}
+
+impl Storable for PayoutEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PayoutHistory {
+    pub entries: Vec<PayoutEntry>,
+    /// Index to continue from, if the log was not scanned to the end.
+    pub next_start: Option<u64>,
+}
+
+/// Cycles owed to the owner of an unregistered provider.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Escrow {
+    pub owner: Principal,
+    pub cycles_owed: u128,
+}
+
+impl Storable for Escrow {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Escrow {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct CyclesReconciliation {
+    pub canister_balance: u128,
+    pub providers_owed: u128,
+    pub escrow_owed: u128,
+    pub prepaid_balances: u128,
+    /// Service fees collected and not yet withdrawn.
+    pub treasury_balance: u128,
+    pub total_owed: u128,
+    /// `false` if the canister balance does not cover everything owed.
+    pub is_solvent: bool,
+}
+
+#[derive(CandidType)]
+pub struct DepositCyclesArgs {
+    pub canister_id: Principal,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, CandidType, Deserialize)]
+pub enum RpcNamespace {
+    Eth,
+    Debug,
+    Trace,
+}
+
+/// Describes which JSON-RPC requests a provider is able to serve.
+///
+/// Subscription methods (`eth_subscribe`, `eth_unsubscribe`) are never supported
+/// since HTTPS outcalls cannot keep a WebSocket connection open.
+#[derive(Clone, Debug, Eq, PartialEq, CandidType, Deserialize)]
+pub struct ProviderCapabilities {
+    pub namespaces: Vec<RpcNamespace>,
+    pub archive: bool,
+    pub max_logs_block_range: Option<u64>,
+    pub max_batch_size: Option<u32>,
+}
+
+impl Default for ProviderCapabilities {
+    fn default() -> Self {
+        Self {
+            namespaces: vec![RpcNamespace::Eth],
+            archive: false,
+            max_logs_block_range: None,
+            max_batch_size: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, CandidType, Deserialize)]
 pub struct ProviderView {
     pub provider_id: u64,
@@ -206,6 +905,7 @@ pub struct ProviderView {
     pub cycles_per_call: u64,
     pub cycles_per_message_byte: u64,
     pub primary: bool,
+    pub capabilities: Option<ProviderCapabilities>,
 }
 
 impl From<Provider> for ProviderView {
@@ -218,10 +918,112 @@ impl From<Provider> for ProviderView {
             cycles_per_call: provider.cycles_per_call,
             cycles_per_message_byte: provider.cycles_per_message_byte,
             primary: provider.primary,
+            capabilities: provider.capabilities,
         }
     }
 }
 
+#[derive(Clone, Debug, Default, Eq, PartialEq, CandidType, Deserialize)]
+pub struct CredentialDetails {
+    pub header_names: Vec<String>,
+    pub masked_path: String,
+    pub path_fingerprint: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, CandidType, Deserialize)]
+pub struct ProviderDetails {
+    pub provider: ProviderView,
+    pub cycles_owed: u128,
+    pub credentials: CredentialDetails,
+}
+
+/// Arguments for installing the canister. Unset fields take their default values.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub nodes_in_subnet: Option<u32>,
+    pub open_rpc_access: Option<bool>,
+    /// Principals granted `Auth::ManageService`.
+    pub admins: Option<Vec<Principal>>,
+    /// Providers to register instead of the default providers.
+    pub providers: Option<Vec<RegisterProviderArgs>>,
+}
+
+/// Arguments for upgrading the canister. Unset fields keep their current values.
+///
+/// Upgrade arguments are encoded with the `InitArgs` type of the Candid interface.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct UpgradeArgs {
+    pub nodes_in_subnet: Option<u32>,
+    pub open_rpc_access: Option<bool>,
+    /// Principals granted `Auth::ManageService` in addition to existing admins.
+    pub admins: Option<Vec<Principal>>,
+    /// Rejected on upgrade: providers are only registered on install, and afterwards with
+    /// `register_provider`.
+    pub providers: Option<Vec<RegisterProviderArgs>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProviderCredentials {
+    pub credential_path: String,
+    pub credential_headers: Vec<HttpHeader>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProviderSnapshot {
+    pub provider_id: u64,
+    pub owner: Principal,
+    pub chain_id: u64,
+    pub hostname: String,
+    pub cycles_per_call: u64,
+    pub cycles_per_message_byte: u64,
+    /// Ignored on import, see `do_import_registry`.
+    pub cycles_owed: u128,
+    pub primary: bool,
+    pub capabilities: Option<ProviderCapabilities>,
+    /// Plaintext credentials, unless excluded from the export.
+    pub credentials: Option<ProviderCredentials>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct BalanceSnapshot {
+    pub principal: Principal,
+    pub balance: u128,
+}
+
+/// Logical state of the canister, independent of its stable memory layout.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RegistrySnapshot {
+    /// Format version, see `REGISTRY_SNAPSHOT_VERSION`.
+    pub version: u32,
+    /// Time at which the snapshot was exported.
+    pub timestamp: u64,
+    pub metadata: Metadata,
+    pub providers: Vec<ProviderSnapshot>,
+    pub authorizations: Vec<Authorization>,
+    /// Ignored on import, see `do_import_registry`.
+    pub balances: Vec<BalanceSnapshot>,
+}
+
+/// How `import_registry` applies a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ImportMode {
+    /// Adds the snapshot's providers and authorizations, replacing those with the same key.
+    Merge,
+    /// Replaces all providers and authorizations with those of the snapshot.
+    Replace,
+}
+
+/// Page of a registry export. Each list of the snapshot starts after the given cursor.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ExportRegistryArgs {
+    pub include_credentials: bool,
+    pub providers_after: Option<u64>,
+    pub authorizations_after: Option<Principal>,
+    pub balances_after: Option<Principal>,
+    /// Maximum number of entries per list, at most `MAX_REGISTRY_EXPORT_PAGE_SIZE`.
+    pub limit: u64,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct RegisterProviderArgs {
     pub chain_id: u64,
@@ -230,6 +1032,7 @@ pub struct RegisterProviderArgs {
     pub credential_headers: Option<Vec<HttpHeader>>,
     pub cycles_per_call: u64,
     pub cycles_per_message_byte: u64,
+    pub capabilities: Option<ProviderCapabilities>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -241,6 +1044,7 @@ pub struct UpdateProviderArgs {
     pub cycles_per_call: Option<u64>,
     pub cycles_per_message_byte: Option<u64>,
     pub primary: Option<bool>,
+    pub capabilities: Option<ProviderCapabilities>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -249,20 +1053,24 @@ pub struct Provider {
     pub owner: Principal,
     pub chain_id: u64,
     pub hostname: String,
+    // Plaintext credentials from before the credential vault, moved to `CREDENTIALS` on upgrade.
     pub credential_path: String,
     pub credential_headers: Vec<HttpHeader>,
     pub cycles_per_call: u64,
     pub cycles_per_message_byte: u64,
     pub cycles_owed: u128,
     pub primary: bool,
+    // Optional for compatibility with providers registered before capabilities were tracked.
+    pub capabilities: Option<ProviderCapabilities>,
 }
 
 impl Provider {
-    pub fn api(&self) -> RpcApi {
-        RpcApi {
-            url: format!("https://{}{}", self.hostname, self.credential_path),
-            headers: self.credential_headers.clone(),
-        }
+    /// Returns `true` if the provider can serve the request. Providers with unknown capabilities
+    /// are assumed to support it, leaving the provider to reject unsupported requests.
+    pub fn can_serve(&self, requirements: &RpcRequirements) -> bool {
+        self.capabilities
+            .as_ref()
+            .map_or(true, |capabilities| capabilities.can_serve(requirements))
     }
 }
 
@@ -285,10 +1093,89 @@ impl Storable for Provider {
 }
 
 impl BoundedStorable for Provider {
-    const MAX_SIZE: u32 = 256; // A reasonable limit.
+    const MAX_SIZE: u32 = PROVIDER_STORABLE_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Provider stored in the map used before schema version 2, whose entries were limited to
+/// `LEGACY_PROVIDER_STORABLE_MAX_SIZE` bytes.
+#[derive(Clone, Debug)]
+pub struct LegacyProvider(pub Provider);
+
+impl Storable for LegacyProvider {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        self.0.to_bytes()
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(Provider::from_bytes(bytes))
+    }
+}
+
+impl BoundedStorable for LegacyProvider {
+    const MAX_SIZE: u32 = LEGACY_PROVIDER_STORABLE_MAX_SIZE;
     const IS_FIXED_SIZE: bool = false;
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum CredentialSecret {
+    /// Plaintext written by earlier versions before the vault key was generated.
+    Pending(Vec<u8>),
+    Sealed {
+        key_version: u32,
+        nonce: [u8; 12],
+        ciphertext: Vec<u8>,
+    },
+}
+
+impl CredentialSecret {
+    pub fn is_sealed_with(&self, version: u32) -> bool {
+        matches!(self, CredentialSecret::Sealed { key_version, .. } if *key_version == version)
+    }
+}
+
+/// Encrypted provider credentials along with non-secret metadata for auditing and cost estimation.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct StoredCredentials {
+    pub path: Option<CredentialSecret>,
+    pub path_len: u32,
+    pub headers: Option<CredentialSecret>,
+    pub details: CredentialDetails,
+}
+
+impl Storable for StoredCredentials {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for StoredCredentials {
+    const MAX_SIZE: u32 = CREDENTIALS_STORABLE_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct CredentialKeyState {
+    pub version: u32,
+    pub seed: Option<[u8; 32]>,
+    // Only set while credentials are being re-encrypted during a key rotation.
+    pub previous_seed: Option<[u8; 32]>,
+    pub next_nonce: u64,
+    /// Key of credential fingerprints, which is not rotated along with the seed.
+    pub fingerprint_key: Option<[u8; 32]>,
+}
+
+impl Storable for CredentialKeyState {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum Message {
     Data(Vec<u8>),
@@ -312,8 +1199,207 @@ pub struct SignedMessage {
     pub signature: Vec<u8>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum SignatureError {
+    InvalidAddressLength(u64),
+    InvalidSignatureLength(u64),
+    InvalidRecoveryId(u64),
+    InvalidTypedData(String),
+    RecoveryFailed(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SignedPersonalMessage {
+    pub address: Vec<u8>,
+    pub message: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// EIP-712 typed data, with the domain, types and message as JSON.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TypedDataArgs {
+    pub domain: String,
+    pub types: String,
+    pub primary_type: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SignedTypedData {
+    pub address: Vec<u8>,
+    pub typed_data: TypedDataArgs,
+    pub signature: Vec<u8>,
+}
+
 pub type RpcResult<T> = Result<T, RpcError>;
 
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ProviderError {
+    NoPermission,
+    TooFewCycles {
+        expected: u128,
+        received: u128,
+    },
+    ProviderNotFound,
+    /// The provider exists but does not support the requested methods, block range or batch size.
+    UnsupportedCapabilities,
+    /// The provider's credentials could not be decrypted, e.g. while the vault key is rotated.
+    CredentialsUnavailable,
+    /// The caller exceeded its rate limit and may retry after the given delay (in nanoseconds).
+    RateLimited {
+        retry_after_ns: u64,
+    },
+}
+
+impl From<cketh_common::eth_rpc::ProviderError> for ProviderError {
+    fn from(err: cketh_common::eth_rpc::ProviderError) -> Self {
+        use cketh_common::eth_rpc::ProviderError as CkEthProviderError;
+        match err {
+            CkEthProviderError::NoPermission => ProviderError::NoPermission,
+            CkEthProviderError::TooFewCycles { expected, received } => {
+                ProviderError::TooFewCycles { expected, received }
+            }
+            CkEthProviderError::ProviderNotFound => ProviderError::ProviderNotFound,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    /// The error's `data` field, such as the revert data of a failed call. JSON strings are
+    /// returned as is, other values in their JSON encoding.
+    pub data: Option<String>,
+    /// The revert reason decoded from `data`, if it is hex-encoded.
+    pub revert: Option<RevertReason>,
+}
+
+/// The reason for which a contract call reverted.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum RevertReason {
+    Error(String),
+    Panic {
+        code: candid::Nat,
+        description: Option<String>,
+    },
+    /// A custom error from the contract ABI, with its arguments formatted as text.
+    Custom {
+        name: String,
+        args: Vec<String>,
+    },
+    /// Hex-encoded revert data which could not be decoded.
+    Unknown(String),
+}
+
+impl From<ic_eth::RevertReason> for RevertReason {
+    fn from(reason: ic_eth::RevertReason) -> Self {
+        match reason {
+            ic_eth::RevertReason::Error(message) => RevertReason::Error(message),
+            ic_eth::RevertReason::Panic { code, description } => RevertReason::Panic {
+                code: code.to_string().parse().expect("BUG: invalid panic code"),
+                description: description.map(|d| d.to_string()),
+            },
+            ic_eth::RevertReason::Custom { name, args } => RevertReason::Custom {
+                name,
+                args: args.iter().map(ic_eth::revert::format_token).collect(),
+            },
+            ic_eth::RevertReason::Unknown(data) => {
+                RevertReason::Unknown(ic_eth::utils::to_hex(&data))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct RequestOptions {
+    /// Returns the body of responses with a non-2xx HTTP status instead of an error.
+    pub allow_http_errors: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ValidationError {
+    CredentialPathNotAllowed(String),
+    HostNotAllowed(String),
+    CredentialHeaderNotAllowed(String),
+    UrlParseError(String),
+    InvalidHex(String),
+    /// The contract ABI given to `eth_call` is not valid JSON ABI.
+    InvalidAbi(String),
+}
+
+impl From<cketh_common::eth_rpc::ValidationError> for ValidationError {
+    fn from(err: cketh_common::eth_rpc::ValidationError) -> Self {
+        use cketh_common::eth_rpc::ValidationError as CkEthValidationError;
+        match err {
+            CkEthValidationError::CredentialPathNotAllowed(s) => {
+                ValidationError::CredentialPathNotAllowed(s)
+            }
+            CkEthValidationError::HostNotAllowed(s) => ValidationError::HostNotAllowed(s),
+            CkEthValidationError::CredentialHeaderNotAllowed(s) => {
+                ValidationError::CredentialHeaderNotAllowed(s)
+            }
+            CkEthValidationError::UrlParseError(s) => ValidationError::UrlParseError(s),
+            CkEthValidationError::InvalidHex(s) => ValidationError::InvalidHex(s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RpcError {
+    ProviderError(ProviderError),
+    HttpOutcallError(HttpOutcallError),
+    JsonRpcError(JsonRpcError),
+    ValidationError(ValidationError),
+}
+
+impl From<ProviderError> for RpcError {
+    fn from(err: ProviderError) -> Self {
+        RpcError::ProviderError(err)
+    }
+}
+
+impl From<HttpOutcallError> for RpcError {
+    fn from(err: HttpOutcallError) -> Self {
+        RpcError::HttpOutcallError(err)
+    }
+}
+
+impl From<ValidationError> for RpcError {
+    fn from(err: ValidationError) -> Self {
+        RpcError::ValidationError(err)
+    }
+}
+
+impl From<cketh_common::eth_rpc::ValidationError> for RpcError {
+    fn from(err: cketh_common::eth_rpc::ValidationError) -> Self {
+        RpcError::ValidationError(err.into())
+    }
+}
+
+impl From<cketh_common::eth_rpc::ProviderError> for RpcError {
+    fn from(err: cketh_common::eth_rpc::ProviderError) -> Self {
+        RpcError::ProviderError(err.into())
+    }
+}
+
+impl From<cketh_common::eth_rpc::RpcError> for RpcError {
+    fn from(err: cketh_common::eth_rpc::RpcError) -> Self {
+        use cketh_common::eth_rpc::RpcError as CkEthRpcError;
+        match err {
+            CkEthRpcError::ProviderError(err) => RpcError::ProviderError(err.into()),
+            CkEthRpcError::HttpOutcallError(err) => RpcError::HttpOutcallError(err),
+            CkEthRpcError::JsonRpcError(err) => RpcError::JsonRpcError(JsonRpcError {
+                code: err.code,
+                message: err.message,
+                data: None,
+                revert: None,
+            }),
+            CkEthRpcError::ValidationError(err) => RpcError::ValidationError(err.into()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum CandidRpcSource {
     EthMainnet(Option<EthereumProvider>),
@@ -444,6 +1530,16 @@ pub mod candid_types {
         }
     }
 
+    #[derive(Clone, Debug, CandidType, Deserialize)]
+    pub struct CallArgs {
+        pub to: String,
+        pub data: String,
+        /// Defaults to the latest block.
+        pub block: Option<BlockSpec>,
+        /// JSON ABI of the contract, used to decode custom errors.
+        pub abi: Option<String>,
+    }
+
     #[derive(Clone, Debug, CandidType, Deserialize)]
     pub struct GetTransactionCountArgs {
         pub address: String,
@@ -1,6 +1,11 @@
 use candid::Principal;
+use ic_eth::core::utils::keccak256;
 use serde_json::Value;
 
+const MASKED_SEGMENT: &str = "***";
+// Block size of Keccak-256, as used by HMAC
+const HMAC_BLOCK_SIZE: usize = 136;
+
 pub fn to_principal(principal: &str) -> Principal {
     match Principal::from_text(principal) {
         Ok(p) => p,
@@ -8,11 +13,117 @@ pub fn to_principal(principal: &str) -> Principal {
     }
 }
 
+/// Current time in nanoseconds since the UNIX epoch.
+pub fn get_time() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::time()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+}
+
 pub fn canonicalize_json(text: &[u8]) -> Option<Vec<u8>> {
     let json = serde_json::from_slice::<Value>(text).ok()?;
     serde_json::to_vec(&json).ok()
 }
 
+/// Hides API keys in a credential path while preserving its structure (e.g. `/v3/***`).
+///
+/// Only API version segments (such as `v3`) and query parameter names are shown.
+pub fn mask_credential_path(credential_path: &str) -> String {
+    let mut masked = String::with_capacity(credential_path.len());
+    let mut in_query = false;
+    for segment in credential_path.split_inclusive(is_path_separator) {
+        let (value, separator) = match segment.char_indices().last() {
+            Some((i, c)) if is_path_separator(c) => segment.split_at(i),
+            _ => (segment, ""),
+        };
+        let is_query_name = in_query && separator == "=";
+        if value.is_empty() || is_query_name || (!in_query && is_version_segment(value)) {
+            masked.push_str(value);
+        } else {
+            masked.push_str(MASKED_SEGMENT);
+        }
+        masked.push_str(separator);
+        in_query |= separator == "?";
+    }
+    masked
+}
+
+fn is_path_separator(c: char) -> bool {
+    matches!(c, '/' | '?' | '&' | '=')
+}
+
+fn is_version_segment(segment: &str) -> bool {
+    segment.strip_prefix('v').map_or(false, |version| {
+        !version.is_empty() && version.len() <= 2 && version.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+fn hmac_keccak256(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    let mut block = [0; HMAC_BLOCK_SIZE];
+    block[..key.len()].copy_from_slice(key);
+    let pad = |byte: u8| block.iter().map(move |b| b ^ byte);
+    let inner = keccak256(pad(0x36).chain(data.iter().copied()).collect::<Vec<_>>());
+    keccak256(pad(0x5c).chain(inner).collect::<Vec<_>>())
+}
+
+/// Short identifier for comparing secrets without revealing them.
+///
+/// The identifier is keyed so that low-entropy secrets cannot be recovered by brute force
+/// without the key.
+pub fn get_fingerprint(key: &[u8; 32], data: &[u8]) -> String {
+    hex::encode(&hmac_keccak256(key, data)[..8])
+}
+
+#[test]
+fn test_mask_credential_path() {
+    assert_eq!(mask_credential_path(""), "");
+    assert_eq!(mask_credential_path("/"), "/");
+    assert_eq!(mask_credential_path("/v1/mainnet"), "/v1/***");
+    assert_eq!(
+        mask_credential_path("/v3/0123456789abcdef0123456789abcdef"),
+        "/v3/***"
+    );
+    // Short keys are masked as well
+    assert_eq!(mask_credential_path("/abc123"), "/***");
+    assert_eq!(mask_credential_path("/v2/k/"), "/v2/***/");
+    assert_eq!(mask_credential_path("/version/v1x"), "/***/***");
+    assert_eq!(
+        mask_credential_path("/eth?apikey=abc&network=x"),
+        "/***?apikey=***&network=***"
+    );
+    assert_eq!(mask_credential_path("?v1=k&flag"), "?v1=***&***");
+}
+
+#[test]
+fn test_fingerprint() {
+    let key = [1; 32];
+    assert_eq!(
+        get_fingerprint(&key, b"/v3/a"),
+        get_fingerprint(&key, b"/v3/a")
+    );
+    assert_ne!(
+        get_fingerprint(&key, b"/v3/a"),
+        get_fingerprint(&key, b"/v3/b")
+    );
+    assert_ne!(
+        get_fingerprint(&key, b"/v3/a"),
+        get_fingerprint(&[2; 32], b"/v3/a")
+    );
+    assert_ne!(
+        get_fingerprint(&key, b"/v3/a"),
+        hex::encode(&keccak256(b"/v3/a")[..8])
+    );
+    assert_eq!(get_fingerprint(&key, b"/v3/a").len(), 16);
+}
+
 #[test]
 fn test_canonicalize_json() {
     assert_eq!(
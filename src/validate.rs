@@ -1,40 +1,202 @@
-use cketh_common::eth_rpc::ValidationError;
 use ic_cdk::api::management_canister::http_request::HttpHeader;
+use std::collections::HashSet;
+use url::Position;
 
 use crate::*;
 
-pub fn validate_hostname(hostname: &str) -> Result<(), ValidationError> {
-    if !SERVICE_HOSTS_ALLOWLIST.contains(&hostname) {
+/// Checks that the hostname is on the allowlist for the chain (`None` for custom URLs, see
+/// `HostScope::allows`).
+pub fn validate_hostname(hostname: &str, chain_id: Option<u64>) -> Result<(), ValidationError> {
+    if !is_host_allowed(hostname, chain_id) {
         Err(ValidationError::HostNotAllowed(hostname.to_string()))
     } else {
         Ok(())
     }
 }
 
-pub fn validate_credential_path(credential_path: &str) -> Result<(), ValidationError> {
+/// Checks that appending the credential path to the provider's hostname yields an allowed URL.
+pub fn validate_credential_path(
+    hostname: &str,
+    chain_id: u64,
+    credential_path: &str,
+) -> Result<(), ValidationError> {
+    let not_allowed = || ValidationError::CredentialPathNotAllowed(credential_path.to_string());
+    // A leading `//` could be taken for the start of another authority.
     if !(credential_path.is_empty()
         || credential_path.starts_with('/')
         || credential_path.starts_with('?'))
+        || credential_path.starts_with("//")
     {
-        Err(ValidationError::CredentialPathNotAllowed(
-            credential_path.to_string(),
-        ))
-    } else {
+        return Err(not_allowed());
+    }
+    let url = validate_url(
+        &format!("https://{}{}", hostname, credential_path),
+        Some(chain_id),
+    )
+    .map_err(|_| not_allowed())?;
+    // Paths which change when parsed (e.g. with dot segments or unescaped characters) could be
+    // interpreted differently by the HTTP client, and are therefore rejected.
+    let canonical_path = &url[Position::BeforePath..];
+    if canonical_path == credential_path || canonical_path == format!("/{}", credential_path) {
         Ok(())
+    } else {
+        Err(not_allowed())
     }
 }
 
+fn is_header_name_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
 pub fn validate_credential_headers(
     credential_headers: &[HttpHeader],
 ) -> Result<(), ValidationError> {
-    if credential_headers
-        .iter()
-        .any(|HttpHeader { name, .. }| name == CONTENT_TYPE_HEADER)
-    {
-        Err(ValidationError::CredentialHeaderNotAllowed(
-            CONTENT_TYPE_HEADER.to_string(),
-        ))
-    } else {
-        Ok(())
+    let mut names = HashSet::new();
+    for HttpHeader { name, value } in credential_headers {
+        let lowercase_name = name.to_ascii_lowercase();
+        // Header names are case-insensitive, so duplicates could override each other. Line breaks
+        // in names or values could be used to inject further headers or requests.
+        if !is_header_name_token(name)
+            || value.contains(['\r', '\n', '\0'])
+            || RESERVED_CREDENTIAL_HEADERS
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(name))
+            || RESERVED_CREDENTIAL_HEADER_PREFIXES
+                .iter()
+                .any(|prefix| lowercase_name.starts_with(&prefix.to_ascii_lowercase()))
+            || !names.insert(lowercase_name)
+        {
+            return Err(ValidationError::CredentialHeaderNotAllowed(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_validate_credential_headers() {
+    let header = |name: &str| HttpHeader {
+        name: name.to_string(),
+        value: "value".to_string(),
+    };
+    let is_not_allowed = |headers: &[HttpHeader], expected: &str| {
+        matches!(
+            validate_credential_headers(headers),
+            Err(ValidationError::CredentialHeaderNotAllowed(name)) if name == expected
+        )
+    };
+    assert!(validate_credential_headers(&[]).is_ok());
+    assert!(validate_credential_headers(&[header("Authorization"), header("X-Api-Key")]).is_ok());
+    for name in [
+        "Content-Type",
+        "content-type",
+        "Host",
+        "HOST",
+        "Content-Length",
+        "Transfer-Encoding",
+        "transfer-encoding",
+        "Connection",
+        "Keep-Alive",
+        "Upgrade",
+        "TE",
+        "Trailer",
+        "Expect",
+        "Forwarded",
+        "Via",
+        "Proxy-Authorization",
+        "proxy-connection",
+        "X-Forwarded-For",
+        "x-forwarded-host",
+        "",
+        "X Api Key",
+        "X-Api-Key:",
+        "X-Api-Key\r\nHost",
+        "X-Api-Key\n",
+        "Authorizätion",
+    ] {
+        assert!(is_not_allowed(&[header(name)], name), "{:?}", name);
+    }
+    assert!(is_not_allowed(
+        &[header("Authorization"), header("authorization")],
+        "authorization"
+    ));
+    for value in ["a\r\nHost: example.com", "a\nb", "a\rb", "a\0b"] {
+        let headers = [HttpHeader {
+            name: "X-Api-Key".to_string(),
+            value: value.to_string(),
+        }];
+        assert!(is_not_allowed(&headers, "X-Api-Key"), "{:?}", value);
+    }
+    for name in [
+        "X-Forwarded",
+        "X-Proxy-Key",
+        "Api_Key",
+        "x-api-key",
+        "Accept",
+    ] {
+        assert!(
+            validate_credential_headers(&[header(name)]).is_ok(),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_validate_credential_path() {
+    init_allowed_hosts();
+    do_add_allowed_host(AllowedHost {
+        hostname: "rpc.example.com".to_string(),
+        path_prefixes: Some(vec!["/v1".to_string()]),
+        ..Default::default()
+    });
+    let mainnet = ETH_MAINNET_CHAIN_ID;
+    for (hostname, path) in [
+        ("cloudflare-eth.com", ""),
+        ("cloudflare-eth.com", "/"),
+        ("cloudflare-eth.com", "/v1/mainnet"),
+        ("cloudflare-eth.com", "?key=abc"),
+        ("cloudflare-eth.com", "/v1?key=abc&x=1"),
+        ("cloudflare-eth.com", "/v1/key%20with%20space"),
+        ("rpc.example.com", "/v1"),
+        ("rpc.example.com", "/v1/key"),
+    ] {
+        assert!(
+            validate_credential_path(hostname, mainnet, path).is_ok(),
+            "{}{}",
+            hostname,
+            path
+        );
+    }
+    for (hostname, path) in [
+        // Must extend the path of the hostname
+        ("cloudflare-eth.com", "v1"),
+        ("cloudflare-eth.com", "@example.org"),
+        ("cloudflare-eth.com", ".example.org"),
+        ("cloudflare-eth.com", ":8545/"),
+        ("cloudflare-eth.com", "#/v1"),
+        // Not in canonical form
+        ("cloudflare-eth.com", "//example.org/"),
+        ("cloudflare-eth.com", "/v1/../admin"),
+        ("cloudflare-eth.com", "/v1/./key"),
+        ("cloudflare-eth.com", "/v1/%2e%2e/admin"),
+        ("cloudflare-eth.com", "/v1\\admin"),
+        ("cloudflare-eth.com", "/key with space"),
+        ("cloudflare-eth.com", "/key\r\nHost: example.org"),
+        ("cloudflare-eth.com", "/kéy"),
+        // Path prefixes
+        ("rpc.example.com", ""),
+        ("rpc.example.com", "/v2"),
+        ("rpc.example.com", "/v10"),
+        ("rpc.example.com", "?key=abc"),
+    ] {
+        assert!(
+            validate_credential_path(hostname, mainnet, path).is_err(),
+            "{}{}",
+            hostname,
+            path
+        );
     }
 }
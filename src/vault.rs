@@ -0,0 +1,732 @@
+use std::time::Duration;
+
+use candid::{Decode, Encode};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use cketh_common::eth_rpc_client::providers::RpcApi;
+use ic_canister_log::log;
+use ic_cdk::api::management_canister::http_request::HttpHeader;
+use ic_eth::core::utils::keccak256;
+
+use crate::*;
+
+const KEY_DERIVATION_DOMAIN: &[u8] = b"evm_rpc/credentials";
+const PATH_FIELD: u8 = 0;
+const HEADERS_FIELD: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VaultKey {
+    pub version: u32,
+    pub key: [u8; 32],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub path: String,
+    pub headers: Vec<HttpHeader>,
+}
+
+/// Provides the keys used to encrypt provider credentials.
+///
+/// This is the extension point for deriving keys with vetKeys once available.
+pub trait CredentialKeySource {
+    fn current_key(&self) -> Option<VaultKey>;
+
+    fn get_key(&self, version: u32) -> Option<VaultKey>;
+
+    /// Returns a nonce which has never been used with the current key.
+    fn next_nonce(&self) -> u64;
+
+    /// Returns the key used for credential fingerprints.
+    fn fingerprint_key(&self) -> Option<[u8; 32]>;
+}
+
+/// Derives keys from a random seed kept in a dedicated stable memory region.
+///
+/// `stable_read` refuses the region of the seed (see `is_credential_key_range`), but the
+/// credentials remain readable by anyone who can read the whole stable memory, such as
+/// controllers installing another Wasm module, until a key source outside stable memory (such as
+/// vetKeys) is available.
+pub struct CanisterKeySource;
+
+impl CredentialKeySource for CanisterKeySource {
+    fn current_key(&self) -> Option<VaultKey> {
+        let state = CREDENTIAL_KEY.with(|k| k.borrow().get().clone());
+        state.seed.map(|seed| derive_key(&seed, state.version))
+    }
+
+    fn get_key(&self, version: u32) -> Option<VaultKey> {
+        let state = CREDENTIAL_KEY.with(|k| k.borrow().get().clone());
+        if version == state.version {
+            state.seed.map(|seed| derive_key(&seed, version))
+        } else if version + 1 == state.version {
+            state.previous_seed.map(|seed| derive_key(&seed, version))
+        } else {
+            None
+        }
+    }
+
+    fn next_nonce(&self) -> u64 {
+        let mut nonce = 0;
+        update_key_state(|state| {
+            nonce = state.next_nonce;
+            state.next_nonce += 1;
+        });
+        nonce
+    }
+
+    fn fingerprint_key(&self) -> Option<[u8; 32]> {
+        CREDENTIAL_KEY.with(|k| k.borrow().get().fingerprint_key)
+    }
+}
+
+#[cfg(test)]
+pub struct MockKeySource {
+    keys: Vec<VaultKey>,
+    next_nonce: std::cell::Cell<u64>,
+}
+
+#[cfg(test)]
+impl MockKeySource {
+    pub fn new(keys: Vec<VaultKey>) -> Self {
+        Self {
+            keys,
+            next_nonce: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl CredentialKeySource for MockKeySource {
+    fn current_key(&self) -> Option<VaultKey> {
+        self.keys.last().cloned()
+    }
+
+    fn get_key(&self, version: u32) -> Option<VaultKey> {
+        self.keys.iter().find(|k| k.version == version).cloned()
+    }
+
+    fn next_nonce(&self) -> u64 {
+        let nonce = self.next_nonce.get();
+        self.next_nonce.set(nonce + 1);
+        nonce
+    }
+
+    fn fingerprint_key(&self) -> Option<[u8; 32]> {
+        Some([0; 32])
+    }
+}
+
+fn derive_key(seed: &[u8; 32], version: u32) -> VaultKey {
+    let mut material = KEY_DERIVATION_DOMAIN.to_vec();
+    material.extend_from_slice(&version.to_be_bytes());
+    material.extend_from_slice(seed);
+    VaultKey {
+        version,
+        key: keccak256(material),
+    }
+}
+
+// Binds each ciphertext to its provider and field so that it cannot be swapped with another one.
+fn get_associated_data(provider_id: u64, field: u8) -> Vec<u8> {
+    let mut data = provider_id.to_be_bytes().to_vec();
+    data.push(field);
+    data
+}
+
+pub fn seal(
+    key: &VaultKey,
+    nonce: u64,
+    associated_data: &[u8],
+    plaintext: &[u8],
+) -> CredentialSecret {
+    let mut nonce_bytes = [0; 12];
+    nonce_bytes[4..].copy_from_slice(&nonce.to_be_bytes());
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key.key))
+        .encrypt(
+            Nonce::from_slice(&nonce_bytes),
+            Payload {
+                msg: plaintext,
+                aad: associated_data,
+            },
+        )
+        .expect("Unable to encrypt credentials");
+    CredentialSecret::Sealed {
+        key_version: key.version,
+        nonce: nonce_bytes,
+        ciphertext,
+    }
+}
+
+/// Returns the plaintext of the secret, or `None` if it cannot be decrypted with the key.
+pub fn open(key: &VaultKey, associated_data: &[u8], secret: &CredentialSecret) -> Option<Vec<u8>> {
+    match secret {
+        CredentialSecret::Pending(plaintext) => Some(plaintext.clone()),
+        CredentialSecret::Sealed {
+            key_version,
+            nonce,
+            ciphertext,
+        } => {
+            if *key_version != key.version {
+                return None;
+            }
+            ChaCha20Poly1305::new(Key::from_slice(&key.key))
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: associated_data,
+                    },
+                )
+                .ok()
+        }
+    }
+}
+
+/// Seals a credential field, which is not stored at all if empty.
+///
+/// Credentials are never stored in plaintext, so they are refused until the key is generated.
+fn seal_field(
+    keys: &impl CredentialKeySource,
+    provider_id: u64,
+    field: u8,
+    plaintext: Vec<u8>,
+) -> Result<Option<CredentialSecret>, String> {
+    if plaintext.is_empty() {
+        return Ok(None);
+    }
+    let key = keys
+        .current_key()
+        .ok_or("Credential key is not initialized yet; please retry later")?;
+    Ok(Some(seal(
+        &key,
+        keys.next_nonce(),
+        &get_associated_data(provider_id, field),
+        &plaintext,
+    )))
+}
+
+fn open_field(
+    keys: &impl CredentialKeySource,
+    provider_id: u64,
+    field: u8,
+    secret: &CredentialSecret,
+) -> Result<Vec<u8>, String> {
+    let associated_data = get_associated_data(provider_id, field);
+    let plaintext = match secret {
+        CredentialSecret::Pending(plaintext) => Some(plaintext.clone()),
+        CredentialSecret::Sealed { key_version, .. } => keys
+            .get_key(*key_version)
+            .and_then(|key| open(&key, &associated_data, secret)),
+    };
+    plaintext.ok_or_else(|| format!("Unable to decrypt credentials of provider {}", provider_id))
+}
+
+fn update_stored_credentials(provider_id: u64, f: impl FnOnce(&mut StoredCredentials)) {
+    CREDENTIALS.with(|c| {
+        let mut credentials = c.borrow().get(&provider_id).unwrap_or_default();
+        f(&mut credentials);
+        c.borrow_mut().insert(provider_id, credentials);
+    });
+}
+
+pub fn store_credential_path(
+    keys: &impl CredentialKeySource,
+    provider_id: u64,
+    path: String,
+) -> Result<(), String> {
+    let masked_path = mask_credential_path(&path);
+    let path_fingerprint = get_path_fingerprint(keys, &path);
+    let path_len = path.len() as u32;
+    let secret = seal_field(keys, provider_id, PATH_FIELD, path.into_bytes())?;
+    update_stored_credentials(provider_id, |stored| {
+        stored.details.masked_path = masked_path;
+        stored.details.path_fingerprint = path_fingerprint;
+        stored.path_len = path_len;
+        stored.path = secret;
+    });
+    Ok(())
+}
+
+pub fn store_credential_headers(
+    keys: &impl CredentialKeySource,
+    provider_id: u64,
+    headers: Vec<HttpHeader>,
+) -> Result<(), String> {
+    let header_names = headers.iter().map(|h| h.name.clone()).collect();
+    let plaintext = if headers.is_empty() {
+        vec![]
+    } else {
+        Encode!(&headers).expect("Unable to encode credential headers")
+    };
+    let secret = seal_field(keys, provider_id, HEADERS_FIELD, plaintext)?;
+    update_stored_credentials(provider_id, |stored| {
+        stored.details.header_names = header_names;
+        stored.headers = secret;
+    });
+    Ok(())
+}
+
+pub fn remove_credentials(provider_id: u64) {
+    CREDENTIALS.with(|c| c.borrow_mut().remove(&provider_id));
+}
+
+pub fn load_credentials(
+    keys: &impl CredentialKeySource,
+    provider: &Provider,
+) -> Result<Credentials, String> {
+    let id = provider.provider_id;
+    let stored = match CREDENTIALS.with(|c| c.borrow().get(&id)) {
+        Some(stored) => stored,
+        // Legacy plaintext credentials which have not been moved to the vault yet
+        None => {
+            return Ok(Credentials {
+                path: provider.credential_path.clone(),
+                headers: provider.credential_headers.clone(),
+            })
+        }
+    };
+    let path = match stored.path {
+        Some(secret) => String::from_utf8(open_field(keys, id, PATH_FIELD, &secret)?)
+            .map_err(|_| format!("Invalid credential path of provider {}", id))?,
+        None => String::new(),
+    };
+    let headers = match stored.headers {
+        Some(secret) => Decode!(
+            &open_field(keys, id, HEADERS_FIELD, &secret)?,
+            Vec<HttpHeader>
+        )
+        .map_err(|_| format!("Invalid credential headers of provider {}", id))?,
+        None => vec![],
+    };
+    Ok(Credentials { path, headers })
+}
+
+/// Resolves the provider's API endpoint, decrypting its credentials.
+pub fn get_provider_api(
+    keys: &impl CredentialKeySource,
+    provider: &Provider,
+) -> Result<RpcApi, String> {
+    let credentials = load_credentials(keys, provider)?;
+    Ok(RpcApi {
+        url: format!("https://{}{}", provider.hostname, credentials.path),
+        headers: credentials.headers,
+    })
+}
+
+/// Length of the provider's API URL, used for estimating costs without decrypting credentials.
+pub fn get_provider_url_len(provider: &Provider) -> usize {
+    let path_len = CREDENTIALS
+        .with(|c| c.borrow().get(&provider.provider_id))
+        .map(|stored| stored.path_len as usize)
+        .unwrap_or(provider.credential_path.len());
+    "https://".len() + provider.hostname.len() + path_len
+}
+
+fn get_path_fingerprint(keys: &impl CredentialKeySource, path: &str) -> String {
+    keys.fingerprint_key()
+        .map(|key| get_fingerprint(&key, path.as_bytes()))
+        .unwrap_or_default()
+}
+
+pub fn get_credential_details(provider: &Provider) -> CredentialDetails {
+    CREDENTIALS
+        .with(|c| c.borrow().get(&provider.provider_id))
+        .map(|stored| stored.details)
+        .unwrap_or_else(|| CredentialDetails {
+            header_names: provider
+                .credential_headers
+                .iter()
+                .map(|h| h.name.clone())
+                .collect(),
+            masked_path: mask_credential_path(&provider.credential_path),
+            path_fingerprint: get_path_fingerprint(&CanisterKeySource, &provider.credential_path),
+        })
+}
+
+/// Encrypts pending credentials and re-encrypts credentials sealed with a previous key.
+pub fn reseal_credentials(keys: &impl CredentialKeySource) -> usize {
+    let version = match keys.current_key() {
+        Some(key) => key.version,
+        None => return 0,
+    };
+    let is_current = |secret: &Option<CredentialSecret>| {
+        secret
+            .as_ref()
+            .map_or(true, |secret| secret.is_sealed_with(version))
+    };
+    let stale_ids: Vec<u64> = CREDENTIALS.with(|c| {
+        c.borrow()
+            .iter()
+            .filter(|(_, stored)| !is_current(&stored.path) || !is_current(&stored.headers))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    let reseal = |id: u64, field: u8, secret: &Option<CredentialSecret>| match secret {
+        Some(secret) => seal_field(keys, id, field, open_field(keys, id, field, secret)?),
+        None => Ok(None),
+    };
+    let mut count = 0;
+    for id in stale_ids {
+        let stored = CREDENTIALS
+            .with(|c| c.borrow().get(&id))
+            .unwrap_or_default();
+        match reseal(id, PATH_FIELD, &stored.path)
+            .and_then(|path| Ok((path, reseal(id, HEADERS_FIELD, &stored.headers)?)))
+        {
+            Ok((path, headers)) => {
+                update_stored_credentials(id, |stored| {
+                    stored.path = path;
+                    stored.headers = headers;
+                });
+                count += 1;
+            }
+            // Left as is rather than failing every other provider
+            Err(err) => log!(INFO, "Unable to re-encrypt credentials: {}", err),
+        }
+    }
+    count
+}
+
+/// Returns `true` if all credentials are sealed with the current key.
+fn are_credentials_current(keys: &impl CredentialKeySource) -> bool {
+    let version = match keys.current_key() {
+        Some(key) => key.version,
+        None => return false,
+    };
+    CREDENTIALS.with(|c| {
+        c.borrow().iter().all(|(_, stored)| {
+            [&stored.path, &stored.headers]
+                .into_iter()
+                .flatten()
+                .all(|secret| secret.is_sealed_with(version))
+        })
+    })
+}
+
+/// Moves plaintext credentials stored in `PROVIDERS` by earlier versions into the vault.
+///
+/// The credentials stay in `PROVIDERS` until the vault key has been generated.
+pub fn migrate_legacy_credentials() {
+    if CanisterKeySource.current_key().is_none() {
+        return;
+    }
+    let legacy_providers: Vec<Provider> = PROVIDERS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, provider)| provider)
+            .filter(|p| !p.credential_path.is_empty() || !p.credential_headers.is_empty())
+            .collect()
+    });
+    for mut provider in legacy_providers {
+        let id = provider.provider_id;
+        store_credential_path(
+            &CanisterKeySource,
+            id,
+            std::mem::take(&mut provider.credential_path),
+        )
+        .and_then(|_| {
+            store_credential_headers(
+                &CanisterKeySource,
+                id,
+                std::mem::take(&mut provider.credential_headers),
+            )
+        })
+        .expect("BUG: credential key is initialized");
+        PROVIDERS.with(|p| p.borrow_mut().insert(id, provider));
+    }
+}
+
+async fn get_random_seed() -> [u8; 32] {
+    let (bytes,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .unwrap_or_else(|(code, message)| {
+            ic_cdk::trap(&format!("Unable to get randomness: {:?} {}", code, message))
+        });
+    bytes
+        .try_into()
+        .unwrap_or_else(|_| ic_cdk::trap("Unexpected randomness length"))
+}
+
+/// Generates the vault key (if necessary), moves legacy credentials into the vault and seals
+/// pending credentials written by earlier versions.
+///
+/// Management canister calls are not allowed in `init` or `post_upgrade`, so this runs in a timer.
+pub fn schedule_credential_key_init() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(init_credential_key()));
+}
+
+fn update_key_state(f: impl FnOnce(&mut CredentialKeyState)) {
+    CREDENTIAL_KEY.with(|k| {
+        let mut state = k.borrow().get().clone();
+        f(&mut state);
+        k.borrow_mut().set(state).unwrap();
+    });
+}
+
+/// Recomputes the masked paths and fingerprints of stored credentials, e.g. after a new
+/// fingerprint key.
+fn refresh_credential_details(keys: &impl CredentialKeySource) {
+    let ids: Vec<u64> = CREDENTIALS.with(|c| c.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        update_stored_credentials(id, |stored| {
+            // Credentials which cannot be decrypted keep their previous details.
+            if let Some(Ok(path)) = stored
+                .path
+                .as_ref()
+                .map(|secret| open_field(keys, id, PATH_FIELD, secret))
+            {
+                let path = String::from_utf8_lossy(&path);
+                stored.details.masked_path = mask_credential_path(&path);
+                stored.details.path_fingerprint = get_path_fingerprint(keys, &path);
+            }
+        });
+    }
+}
+
+async fn init_credential_key() {
+    if CanisterKeySource.fingerprint_key().is_none() {
+        let key = get_random_seed().await;
+        let mut initialized = false;
+        update_key_state(|state| {
+            if state.fingerprint_key.is_none() {
+                state.fingerprint_key = Some(key);
+                initialized = true;
+            }
+        });
+        if initialized {
+            refresh_credential_details(&CanisterKeySource);
+        }
+    }
+    if CanisterKeySource.current_key().is_none() {
+        let seed = get_random_seed().await;
+        update_key_state(|state| {
+            // Another call may have initialized the key while awaiting.
+            if state.seed.is_none() {
+                state.version += 1;
+                state.seed = Some(seed);
+                state.next_nonce = 0;
+            }
+        });
+    }
+    migrate_legacy_credentials();
+    let count = reseal_credentials(&CanisterKeySource);
+    log!(INFO, "Sealed credentials for {} provider(s)", count);
+}
+
+pub async fn do_rotate_credential_key() {
+    if CREDENTIAL_KEY.with(|k| k.borrow().get().previous_seed.is_some()) {
+        reseal_credentials(&CanisterKeySource);
+        if !are_credentials_current(&CanisterKeySource) {
+            ic_cdk::trap("Credentials of the previous key rotation could not be re-encrypted");
+        }
+        update_key_state(|state| state.previous_seed = None);
+    }
+    let seed = get_random_seed().await;
+    update_key_state(|state| {
+        state.previous_seed = state.seed.replace(seed);
+        state.version += 1;
+        state.next_nonce = 0;
+    });
+    let count = reseal_credentials(&CanisterKeySource);
+    // The previous key is kept until every credential could be re-encrypted.
+    if are_credentials_current(&CanisterKeySource) {
+        update_key_state(|state| state.previous_seed = None);
+    }
+    log!(
+        INFO,
+        "Rotated credential key; re-encrypted credentials for {} provider(s)",
+        count
+    );
+}
+
+#[cfg(test)]
+fn test_provider(provider_id: u64) -> Provider {
+    Provider {
+        provider_id,
+        owner: Principal::anonymous(),
+        chain_id: 1,
+        hostname: "rpc.ankr.com".to_string(),
+        credential_path: "".to_string(),
+        credential_headers: vec![],
+        cycles_per_call: 0,
+        cycles_per_message_byte: 0,
+        cycles_owed: 0,
+        primary: false,
+        capabilities: None,
+    }
+}
+
+// Layout of the `ic-stable-structures` memory manager (version 1): a header followed by the
+// owner of each bucket, with the buckets starting at the second Wasm page.
+const MEMORY_MANAGER_MAGIC: &[u8; 4] = b"MGR\x01";
+const MEMORY_MANAGER_HEADER_SIZE: u64 = 2080;
+const MEMORY_MANAGER_BUCKETS_OFFSET: u64 = WASM_PAGE_SIZE;
+
+/// Returns `true` if the byte range of the raw stable memory overlaps a bucket of the credential
+/// key, or if the memory manager layout is not recognized.
+pub fn is_credential_key_range(
+    memory: &impl ic_stable_structures::Memory,
+    offset: u64,
+    length: u64,
+) -> bool {
+    let end = offset.saturating_add(length);
+    if memory.size() == 0 || end <= MEMORY_MANAGER_BUCKETS_OFFSET {
+        return false;
+    }
+    let mut header = [0; 8];
+    memory.read(0, &mut header);
+    if header[..4] != MEMORY_MANAGER_MAGIC[..] {
+        return true;
+    }
+    let num_buckets = u16::from_le_bytes([header[4], header[5]]) as u64;
+    let bucket_size = u16::from_le_bytes([header[6], header[7]]) as u64 * WASM_PAGE_SIZE;
+    let mut owners = vec![0; num_buckets as usize];
+    memory.read(MEMORY_MANAGER_HEADER_SIZE, &mut owners);
+    owners.iter().enumerate().any(|(bucket, owner)| {
+        let start = MEMORY_MANAGER_BUCKETS_OFFSET + bucket as u64 * bucket_size;
+        *owner == CREDENTIAL_KEY_MEMORY_ID && offset < start + bucket_size && start < end
+    })
+}
+
+#[test]
+fn test_seal_and_open() {
+    let key = VaultKey {
+        version: 1,
+        key: [1; 32],
+    };
+    let other_key = VaultKey {
+        version: 1,
+        key: [2; 32],
+    };
+    let secret = seal(&key, 0, b"a", b"secret");
+    assert!(secret.is_sealed_with(1));
+    assert_eq!(open(&key, b"a", &secret), Some(b"secret".to_vec()));
+    assert_eq!(open(&key, b"b", &secret), None);
+    assert_eq!(open(&other_key, b"a", &secret), None);
+    assert_ne!(seal(&key, 1, b"a", b"secret"), secret);
+}
+
+#[test]
+fn test_credential_vault() {
+    let provider = test_provider(3);
+    let headers = vec![HttpHeader {
+        name: "Authorization".to_string(),
+        value: "Bearer abc".to_string(),
+    }];
+
+    // Credentials are refused until a key is available
+    let no_keys = MockKeySource::new(vec![]);
+    assert!(store_credential_path(&no_keys, 3, "/v2/secret-api-key".to_string()).is_err());
+    assert!(store_credential_headers(&no_keys, 3, headers.clone()).is_err());
+    assert!(CREDENTIALS.with(|c| c.borrow().get(&3)).is_none());
+    store_credential_path(&no_keys, 3, String::new()).unwrap();
+    store_credential_headers(&no_keys, 3, vec![]).unwrap();
+    assert_eq!(
+        load_credentials(&no_keys, &provider),
+        Ok(Credentials::default())
+    );
+
+    // Pending credentials written by earlier versions
+    let key_1 = VaultKey {
+        version: 1,
+        key: [1; 32],
+    };
+    let keys = MockKeySource::new(vec![key_1.clone()]);
+    store_credential_path(&keys, 3, "/v2/secret-api-key".to_string()).unwrap();
+    update_stored_credentials(3, |stored| {
+        stored.path = Some(CredentialSecret::Pending(b"/v2/secret-api-key".to_vec()));
+        stored.headers = Some(CredentialSecret::Pending(Encode!(&headers).unwrap()));
+    });
+    let stored = CREDENTIALS.with(|c| c.borrow().get(&3)).unwrap();
+    assert_eq!(stored.details.header_names, vec!["Authorization"]);
+    assert_eq!(stored.details.masked_path, "/v2/***");
+    assert_eq!(
+        stored.details.path_fingerprint,
+        get_fingerprint(&[0; 32], b"/v2/secret-api-key")
+    );
+    assert_eq!(
+        get_provider_url_len(&provider),
+        "https://rpc.ankr.com/v2/secret-api-key".len()
+    );
+
+    assert_eq!(reseal_credentials(&keys), 1);
+    assert_eq!(reseal_credentials(&keys), 0);
+    let stored = CREDENTIALS.with(|c| c.borrow().get(&3)).unwrap();
+    assert!(stored.path.unwrap().is_sealed_with(1));
+    assert!(stored.headers.unwrap().is_sealed_with(1));
+    let expected = Credentials {
+        path: "/v2/secret-api-key".to_string(),
+        headers,
+    };
+    assert_eq!(load_credentials(&keys, &provider), Ok(expected.clone()));
+    assert_eq!(
+        get_provider_api(&keys, &provider).unwrap().url,
+        "https://rpc.ankr.com/v2/secret-api-key"
+    );
+
+    // Key rotation
+    let key_2 = VaultKey {
+        version: 2,
+        key: [2; 32],
+    };
+    let rotation_keys = MockKeySource::new(vec![key_1, key_2.clone()]);
+    assert_eq!(reseal_credentials(&rotation_keys), 1);
+    let new_keys = MockKeySource::new(vec![key_2.clone()]);
+    assert_eq!(load_credentials(&new_keys, &provider), Ok(expected));
+    assert!(are_credentials_current(&new_keys));
+
+    // Credentials which cannot be decrypted are reported rather than trapping
+    update_stored_credentials(3, |stored| {
+        stored.path = Some(seal(&key_2, 100, b"other", b"/v2/other-key"));
+    });
+    assert!(load_credentials(&new_keys, &provider).is_err());
+    assert!(get_provider_api(&new_keys, &provider).is_err());
+    let key_3 = VaultKey {
+        version: 3,
+        key: [3; 32],
+    };
+    let rotation_keys = MockKeySource::new(vec![key_2, key_3]);
+    assert_eq!(reseal_credentials(&rotation_keys), 0);
+    assert!(!are_credentials_current(&rotation_keys));
+
+    remove_credentials(3);
+    assert_eq!(
+        load_credentials(&new_keys, &provider),
+        Ok(Credentials::default())
+    );
+}
+
+#[test]
+fn test_credential_key_range() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{Memory, VectorMemory};
+
+    let memory = VectorMemory::default();
+    let manager = MemoryManager::init(memory.clone());
+    assert!(!is_credential_key_range(&memory, 0, u64::MAX));
+    for id in [0, CREDENTIAL_KEY_MEMORY_ID, 5] {
+        let virtual_memory = manager.get(MemoryId::new(id));
+        virtual_memory.grow(1);
+        virtual_memory.write(0, &[id + 1; 8]);
+    }
+
+    // Locate the key bytes in the raw memory
+    let mut raw = vec![0; memory.size() as usize * WASM_PAGE_SIZE as usize];
+    memory.read(0, &mut raw);
+    let marker = [CREDENTIAL_KEY_MEMORY_ID + 1; 8];
+    let key_offset = raw.windows(8).position(|w| w == marker).unwrap() as u64;
+    assert!(is_credential_key_range(&memory, key_offset, 8));
+    assert!(is_credential_key_range(&memory, 0, u64::MAX));
+    assert!(!is_credential_key_range(
+        &memory,
+        0,
+        MEMORY_MANAGER_BUCKETS_OFFSET
+    ));
+    let other_offset = raw.windows(8).position(|w| w == [6; 8]).unwrap() as u64;
+    assert!(!is_credential_key_range(&memory, other_offset, 8));
+
+    // Unknown layouts are refused entirely
+    memory.write(0, b"XXXX");
+    assert!(is_credential_key_range(&memory, other_offset, 8));
+}
@@ -3,6 +3,7 @@ use std::rc::Rc;
 use candid::{CandidType, Decode, Encode, Nat};
 use evm_rpc::*;
 use ic_base_types::{CanisterId, PrincipalId};
+use ic_cdk::api::management_canister::http_request::HttpHeader;
 use ic_ic00_types::BoundedVec;
 use ic_state_machine_tests::{CanisterSettingsArgs, StateMachine, StateMachineBuilder, WasmResult};
 use ic_test_utilities_load_wasm::load_wasm;
@@ -15,6 +16,16 @@ fn evm_rpc_wasm() -> Vec<u8> {
     load_wasm(std::env::var("CARGO_MANIFEST_DIR").unwrap(), "evm_rpc", &[])
 }
 
+/// Wasm of a previous release to test upgrades from, built by `scripts/build_previous_release`.
+fn old_evm_rpc_wasm() -> Vec<u8> {
+    let path = std::env::var("EVM_RPC_OLD_WASM_PATH").unwrap_or_else(|_| {
+        panic!(
+            "EVM_RPC_OLD_WASM_PATH is not set; run `export EVM_RPC_OLD_WASM_PATH=$(scripts/build_previous_release)`"
+        )
+    });
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e))
+}
+
 fn assert_reply(result: WasmResult) -> Vec<u8> {
     match result {
         WasmResult::Reply(bytes) => bytes,
@@ -40,6 +51,10 @@ impl Default for EvmRpcSetup {
 
 impl EvmRpcSetup {
     pub fn new() -> Self {
+        Self::with_wasm(evm_rpc_wasm(), Encode!(&Some(InitArgs::default())).unwrap())
+    }
+
+    pub fn with_wasm(wasm: Vec<u8>, init_args: Vec<u8>) -> Self {
         let env = Rc::new(
             StateMachineBuilder::new()
                 .with_default_canister_range()
@@ -52,8 +67,12 @@ impl EvmRpcSetup {
             args.controllers = Some(BoundedVec::new(vec![controller]));
             args
         }));
-        env.install_existing_canister(evm_rpc_id, evm_rpc_wasm(), Encode!(&()).unwrap())
+        env.install_existing_canister(evm_rpc_id, wasm, init_args)
             .unwrap();
+        // Let the timer generate the credential key, without which credentials are refused.
+        for _ in 0..3 {
+            env.tick();
+        }
 
         let caller = PrincipalId::new_user_test_id(DEFAULT_CALLER_TEST_ID);
 
@@ -65,6 +84,12 @@ impl EvmRpcSetup {
         }
     }
 
+    pub fn upgrade(&self, args: Option<UpgradeArgs>) {
+        self.env
+            .upgrade_canister(self.evm_rpc_id, evm_rpc_wasm(), Encode!(&args).unwrap())
+            .unwrap();
+    }
+
     pub fn as_controller(&self) -> Self {
         let mut setup = self.clone();
         setup.caller = self.controller;
@@ -129,6 +154,10 @@ impl EvmRpcSetup {
         self.call_update("register_provider", Encode!(&args).unwrap())
     }
 
+    pub fn get_audit_log(&self) -> Vec<AuditEntry> {
+        self.call_query("get_audit_log", Encode!(&0u64, &100u64).unwrap())
+    }
+
     pub fn authorize_caller(self, auth: Auth) -> Self {
         self.as_controller().authorize(&self.caller, auth);
         self
@@ -139,6 +168,41 @@ impl EvmRpcSetup {
         self
     }
 
+    pub fn set_nodes_in_subnet(&self, nodes_in_subnet: u32) {
+        self.call_update("set_nodes_in_subnet", Encode!(&nodes_in_subnet).unwrap())
+    }
+
+    pub fn get_nodes_in_subnet(&self) -> u32 {
+        self.call_query("get_nodes_in_subnet", Encode!().unwrap())
+    }
+
+    pub fn set_open_rpc_access(&self, open_rpc_access: bool) {
+        self.call_update("set_open_rpc_access", Encode!(&open_rpc_access).unwrap())
+    }
+
+    pub fn get_open_rpc_access(&self) -> bool {
+        self.call_query("get_open_rpc_access", Encode!().unwrap())
+    }
+
+    pub fn add_allowed_host(&self, host: AllowedHost) {
+        self.call_update("add_allowed_host", Encode!(&host).unwrap())
+    }
+
+    pub fn remove_allowed_host(&self, hostname: &str) -> bool {
+        self.call_update("remove_allowed_host", Encode!(&hostname).unwrap())
+    }
+
+    pub fn get_allowed_hosts(&self) -> Vec<AllowedHost> {
+        self.call_query("get_allowed_hosts", Encode!().unwrap())
+    }
+
+    pub fn list_authorizations(&self, auth: Option<Auth>) -> Vec<Authorization> {
+        self.call_query(
+            "list_authorizations",
+            Encode!(&auth, &None::<candid::Principal>, &100u64).unwrap(),
+        )
+    }
+
     pub fn request_cost(
         &self,
         source: Source,
@@ -174,6 +238,7 @@ fn test_provider_registry() {
         credential_headers: None,
         cycles_per_call: 0,
         cycles_per_message_byte: 0,
+        capabilities: None,
     });
     let b_id = setup.register_provider(RegisterProviderArgs {
         chain_id: 5,
@@ -182,6 +247,7 @@ fn test_provider_registry() {
         credential_headers: None,
         cycles_per_call: 0,
         cycles_per_message_byte: 0,
+        capabilities: None,
     });
     assert_eq!(a_id + 1, b_id);
     let providers = setup.get_providers();
@@ -198,6 +264,7 @@ fn test_provider_registry() {
                 cycles_per_call: 0,
                 cycles_per_message_byte: 0,
                 primary: false,
+                capabilities: None,
             },
             ProviderView {
                 provider_id: first_new_id + 1,
@@ -207,7 +274,207 @@ fn test_provider_registry() {
                 cycles_per_call: 0,
                 cycles_per_message_byte: 0,
                 primary: false,
+                capabilities: None,
             }
         ]
     )
 }
+
+#[test]
+fn test_provider_audit_log() {
+    let setup = EvmRpcSetup::new().authorize_caller(Auth::RegisterProvider);
+    let args = RegisterProviderArgs {
+        chain_id: 1,
+        hostname: "cloudflare-eth.com".to_string(),
+        credential_path: "".to_string(),
+        credential_headers: None,
+        cycles_per_call: 0,
+        cycles_per_message_byte: 0,
+        capabilities: None,
+    };
+    let provider_registered = |setup: &EvmRpcSetup, provider_id: u64| {
+        setup
+            .as_controller()
+            .get_audit_log()
+            .iter()
+            .any(|entry| entry.action == AuditAction::RegisterProvider { provider_id })
+    };
+
+    // Changes made by provider owners are not recorded in the admin audit log
+    let provider_id = setup.register_provider(args.clone());
+    assert!(!provider_registered(&setup, provider_id));
+
+    let admin_setup = setup.as_controller();
+    admin_setup.authorize(&admin_setup.caller, Auth::RegisterProvider);
+    let provider_id = admin_setup.register_provider(args);
+    assert!(provider_registered(&setup, provider_id));
+}
+
+#[test]
+fn test_allowed_hosts() {
+    let setup = EvmRpcSetup::new().authorize_caller(Auth::RegisterProvider);
+    let controller = setup.as_controller();
+    assert_eq!(
+        controller.get_allowed_hosts().len(),
+        DEFAULT_ALLOWED_HOSTS.len()
+    );
+    let host = AllowedHost {
+        hostname: "*.example.org".to_string(),
+        chain_ids: Some(vec![ETH_SEPOLIA_CHAIN_ID]),
+        ..Default::default()
+    };
+    controller.add_allowed_host(host.clone());
+    assert!(controller.get_allowed_hosts().contains(&host));
+    let provider_id = setup.register_provider(RegisterProviderArgs {
+        chain_id: ETH_SEPOLIA_CHAIN_ID,
+        hostname: "rpc.example.org".to_string(),
+        credential_path: "".to_string(),
+        credential_headers: None,
+        cycles_per_call: 0,
+        cycles_per_message_byte: 0,
+        capabilities: None,
+    });
+    assert!(setup
+        .get_providers()
+        .iter()
+        .any(|p| p.provider_id == provider_id && p.hostname == "rpc.example.org"));
+    assert!(controller.remove_allowed_host("*.example.org"));
+    assert!(!controller.remove_allowed_host("*.example.org"));
+}
+
+#[test]
+fn test_register_large_provider() {
+    let setup = EvmRpcSetup::new().authorize_caller(Auth::RegisterProvider);
+    // Close to the former 256-byte limit per provider entry once the type table is included.
+    let capabilities = ProviderCapabilities {
+        namespaces: vec![RpcNamespace::Eth, RpcNamespace::Debug, RpcNamespace::Trace],
+        archive: true,
+        max_logs_block_range: Some(u64::MAX),
+        max_batch_size: Some(u32::MAX),
+    };
+    let provider_id = setup.register_provider(RegisterProviderArgs {
+        chain_id: 1,
+        hostname: "erigon-mainnet--rpc.datahub.figment.io".to_string(),
+        credential_path: format!("/v1/{}", "a".repeat(200)),
+        credential_headers: Some(vec![HttpHeader {
+            name: "Authorization".to_string(),
+            value: format!("Bearer {}", "b".repeat(200)),
+        }]),
+        cycles_per_call: u64::MAX,
+        cycles_per_message_byte: u64::MAX,
+        capabilities: Some(capabilities.clone()),
+    });
+    setup.upgrade(None);
+    let provider = setup
+        .get_providers()
+        .into_iter()
+        .find(|p| p.provider_id == provider_id)
+        .expect("provider was lost");
+    assert_eq!(provider.hostname, "erigon-mainnet--rpc.datahub.figment.io");
+    assert_eq!(provider.cycles_per_call, u64::MAX);
+    assert_eq!(provider.capabilities, Some(capabilities));
+}
+
+/// Populates the state through methods available in every release.
+fn populate_state(setup: &EvmRpcSetup) -> u64 {
+    let controller = setup.as_controller();
+    controller.authorize(&setup.caller, Auth::RegisterProvider);
+    controller.authorize(&setup.caller, Auth::Rpc);
+    controller.set_nodes_in_subnet(34);
+    controller.set_open_rpc_access(false);
+    setup.register_provider(RegisterProviderArgs {
+        chain_id: 1,
+        hostname: "cloudflare-eth.com".to_string(),
+        credential_path: "/v1/mainnet".to_string(),
+        credential_headers: None,
+        cycles_per_call: 1000,
+        cycles_per_message_byte: 10,
+        capabilities: None,
+    })
+}
+
+fn assert_state_preserved(setup: &EvmRpcSetup, provider_id: u64) {
+    let controller = setup.as_controller();
+    assert_eq!(controller.get_nodes_in_subnet(), 34);
+    assert!(!controller.get_open_rpc_access());
+    let authorization = controller
+        .list_authorizations(None)
+        .into_iter()
+        .find(|a| a.principal == setup.caller.0)
+        .expect("authorizations were lost");
+    let mut auths = authorization
+        .grants
+        .iter()
+        .map(|g| format!("{:?}", g.auth))
+        .collect::<Vec<_>>();
+    auths.sort();
+    assert_eq!(auths, vec!["RegisterProvider", "Rpc"]);
+    let provider = setup
+        .get_providers()
+        .into_iter()
+        .find(|p| p.provider_id == provider_id)
+        .expect("provider was lost");
+    assert_eq!(provider.owner, setup.caller.0);
+    assert_eq!(provider.hostname, "cloudflare-eth.com");
+    assert_eq!(provider.cycles_per_call, 1000);
+    assert_eq!(provider.cycles_per_message_byte, 10);
+}
+
+#[test]
+fn test_init_args() {
+    let admin = PrincipalId::new_user_test_id(1);
+    let setup = EvmRpcSetup::with_wasm(
+        evm_rpc_wasm(),
+        Encode!(&Some(InitArgs {
+            nodes_in_subnet: Some(28),
+            open_rpc_access: Some(false),
+            admins: Some(vec![admin.0]),
+            providers: Some(vec![]),
+        }))
+        .unwrap(),
+    );
+    let admin_setup = setup.as_caller(admin);
+    assert_eq!(admin_setup.get_nodes_in_subnet(), 28);
+    assert!(!admin_setup.get_open_rpc_access());
+    assert!(setup.get_providers().is_empty());
+}
+
+#[test]
+fn test_upgrade() {
+    let setup = EvmRpcSetup::new();
+    let provider_id = populate_state(&setup);
+    setup.upgrade(None);
+    assert_state_preserved(&setup, provider_id);
+
+    // Upgrade arguments only change the given fields
+    let admin = PrincipalId::new_user_test_id(1);
+    setup.upgrade(Some(UpgradeArgs {
+        open_rpc_access: Some(true),
+        admins: Some(vec![admin.0]),
+        ..Default::default()
+    }));
+    let admin_setup = setup.as_caller(admin);
+    assert!(admin_setup.get_open_rpc_access());
+    assert_eq!(admin_setup.get_nodes_in_subnet(), 34);
+}
+
+#[test]
+fn test_upgrade_rejects_providers() {
+    let setup = EvmRpcSetup::new();
+    let args = Some(UpgradeArgs {
+        providers: Some(vec![]),
+        ..Default::default()
+    });
+    assert!(setup
+        .env
+        .upgrade_canister(setup.evm_rpc_id, evm_rpc_wasm(), Encode!(&args).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_upgrade_from_previous_release() {
+    let setup = EvmRpcSetup::with_wasm(old_evm_rpc_wasm(), Encode!(&()).unwrap());
+    let provider_id = populate_state(&setup);
+    setup.upgrade(None);
+    assert_state_preserved(&setup, provider_id);
+}